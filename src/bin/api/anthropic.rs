@@ -0,0 +1,524 @@
+//! Anthropic Messages API (`POST /v1/messages`) on top of the provider clients
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{sse::Event, IntoResponse, Response},
+    Extension, Json,
+};
+use reverse_api::qwen::models::StreamEvent;
+use reverse_api::Logger;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+use tracing::Instrument;
+use utoipa::ToSchema;
+
+use super::auth::Tenant;
+use super::cancellation::CancelOnDrop;
+use super::error::{ApiError, ErrorResponse};
+use super::handlers::sse_response_with_guard;
+use super::providers::{self, CompletionOptions, PromptMessage};
+use super::state::AppState;
+use super::stats::RequestModel;
+use super::types::Usage;
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct MessagesRequest {
+    pub model: String,
+    pub messages: Vec<InputMessage>,
+    #[serde(default)]
+    pub system: Option<MessageContent>,
+    /// Required by the Anthropic API; the upstream web clients cannot cap
+    /// output, so it is only validated
+    pub max_tokens: u32,
+    #[serde(default)]
+    pub stream: bool,
+    #[serde(default)]
+    pub thinking: Option<ThinkingConfig>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct InputMessage {
+    pub role: String,
+    pub content: MessageContent,
+}
+
+/// Message content: a plain string or an array of content blocks
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Blocks(Vec<ContentBlock>),
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentBlock {
+    Text {
+        text: String,
+    },
+    Image {
+        source: ImageSource,
+    },
+    /// Tool calls, tool results, earlier thinking and the like are not
+    /// forwarded upstream
+    #[serde(other)]
+    Unsupported,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ImageSource {
+    Base64 { media_type: String, data: String },
+    Url { url: String },
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ThinkingConfig {
+    Enabled { budget_tokens: Option<u32> },
+    Disabled,
+}
+
+impl MessageContent {
+    pub fn text(&self) -> String {
+        match self {
+            MessageContent::Text(text) => text.clone(),
+            MessageContent::Blocks(blocks) => blocks
+                .iter()
+                .filter_map(|block| match block {
+                    ContentBlock::Text { text } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+
+    /// Images as URLs, base64 sources becoming data URLs
+    pub fn images(&self) -> Vec<String> {
+        let MessageContent::Blocks(blocks) = self else {
+            return vec![];
+        };
+        blocks
+            .iter()
+            .filter_map(|block| match block {
+                ContentBlock::Image {
+                    source: ImageSource::Base64 { media_type, data },
+                } => Some(format!("data:{};base64,{}", media_type, data)),
+                ContentBlock::Image {
+                    source: ImageSource::Url { url },
+                } => Some(url.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// System prompt first, then the conversation, as provider prompt messages
+pub fn prompt_messages(request: &MessagesRequest) -> Vec<PromptMessage> {
+    let system = request
+        .system
+        .as_ref()
+        .map(MessageContent::text)
+        .filter(|text| !text.trim().is_empty())
+        .map(|content| PromptMessage {
+            role: "system".to_string(),
+            content,
+        });
+    system
+        .into_iter()
+        .chain(request.messages.iter().map(|message| PromptMessage {
+            role: message.role.clone(),
+            content: message.content.text(),
+        }))
+        .collect()
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OutputBlock {
+    Thinking { thinking: String, signature: String },
+    Text { text: String },
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct MessageUsage {
+    pub input_tokens: u32,
+    pub output_tokens: u32,
+}
+
+impl From<Usage> for MessageUsage {
+    fn from(usage: Usage) -> Self {
+        Self {
+            input_tokens: usage.prompt_tokens,
+            output_tokens: usage.completion_tokens,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct MessagesResponse {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub object: String,
+    pub role: String,
+    pub model: String,
+    pub content: Vec<OutputBlock>,
+    pub stop_reason: Option<String>,
+    pub stop_sequence: Option<String>,
+    pub usage: MessageUsage,
+}
+
+/// Turns provider deltas into the Anthropic streaming event sequence,
+/// opening a new content block whenever output switches between thinking
+/// and text
+pub struct BlockStream {
+    open: Option<BlockKind>,
+    index: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BlockKind {
+    Thinking,
+    Text,
+}
+
+impl BlockStream {
+    pub fn new() -> Self {
+        Self {
+            open: None,
+            index: 0,
+        }
+    }
+
+    /// Events, as `(name, data)`, for one provider delta
+    pub fn push(&mut self, event: StreamEvent) -> Vec<(&'static str, Value)> {
+        let (kind, text) = match event {
+            StreamEvent::Thinking(text) => (BlockKind::Thinking, text),
+            StreamEvent::Answer(text) => (BlockKind::Text, text),
+            StreamEvent::WebSearch(_) => return vec![],
+        };
+        if text.is_empty() {
+            return vec![];
+        }
+
+        let mut events = Vec::new();
+        if self.open != Some(kind) {
+            events.extend(self.close());
+            let content_block = match kind {
+                BlockKind::Thinking => json!({"type": "thinking", "thinking": "", "signature": ""}),
+                BlockKind::Text => json!({"type": "text", "text": ""}),
+            };
+            events.push((
+                "content_block_start",
+                json!({"type": "content_block_start", "index": self.index, "content_block": content_block}),
+            ));
+            self.open = Some(kind);
+        }
+        let delta = match kind {
+            BlockKind::Thinking => json!({"type": "thinking_delta", "thinking": text}),
+            BlockKind::Text => json!({"type": "text_delta", "text": text}),
+        };
+        events.push((
+            "content_block_delta",
+            json!({"type": "content_block_delta", "index": self.index, "delta": delta}),
+        ));
+        events
+    }
+
+    /// Close the open block, if any
+    pub fn close(&mut self) -> Option<(&'static str, Value)> {
+        self.open.take()?;
+        let event = json!({"type": "content_block_stop", "index": self.index});
+        self.index += 1;
+        Some(("content_block_stop", event))
+    }
+}
+
+fn sse_event(name: &str, data: Value) -> Event {
+    Event::default().event(name).data(data.to_string())
+}
+
+/// [`ApiError`] rendered in the Anthropic error format
+pub struct AnthropicError(ApiError);
+
+impl From<ApiError> for AnthropicError {
+    fn from(error: ApiError) -> Self {
+        Self(error)
+    }
+}
+
+fn error_type(status: StatusCode) -> &'static str {
+    match status {
+        StatusCode::BAD_REQUEST => "invalid_request_error",
+        StatusCode::UNAUTHORIZED => "authentication_error",
+        StatusCode::NOT_FOUND => "not_found_error",
+        StatusCode::TOO_MANY_REQUESTS => "rate_limit_error",
+        _ => "api_error",
+    }
+}
+
+fn error_body(error: &ApiError) -> Value {
+    json!({
+        "type": "error",
+        "error": {
+            "type": error_type(error.status),
+            "message": error.message
+        }
+    })
+}
+
+impl IntoResponse for AnthropicError {
+    fn into_response(self) -> Response {
+        (self.0.status, Json(error_body(&self.0))).into_response()
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/messages",
+    tag = "compat",
+    summary = "Anthropic Messages",
+    description = "Anthropic Messages API 兼容接口；stream 为 true 时依次推送 message_start、content_block_start/delta/stop、message_delta、message_stop 事件",
+    request_body = MessagesRequest,
+    responses(
+        (status = 200, description = "回答", content(
+            (MessagesResponse = "application/json"),
+            ("text/event-stream")
+        )),
+        (status = 400, description = "消息为空、模型不支持，或图片多于一张或模型不支持图片", body = ErrorResponse)
+    )
+)]
+pub async fn create_message(
+    State(state): State<AppState>,
+    Extension(Tenant(tenant)): Extension<Tenant>,
+    Json(payload): Json<MessagesRequest>,
+) -> Result<Response, AnthropicError> {
+    if payload.messages.is_empty() {
+        return Err(ApiError::bad_request("'messages' must not be empty").into());
+    }
+    if payload.max_tokens == 0 {
+        return Err(ApiError::bad_request("'max_tokens' must be at least 1").into());
+    }
+    let model = state
+        .config()
+        .resolve_model(&payload.model)
+        .ok_or_else(|| providers::unsupported_model(&payload.model))?;
+
+    let prompt = providers::flatten_messages(&prompt_messages(&payload));
+    if prompt.trim().is_empty() {
+        return Err(ApiError::bad_request("Message content cannot be empty").into());
+    }
+    let images = payload
+        .messages
+        .iter()
+        .flat_map(|m| m.content.images())
+        .collect();
+    let image = providers::completion_image(&model, images)?;
+    let (thinking, thinking_budget) = match payload.thinking {
+        Some(ThinkingConfig::Enabled { budget_tokens }) => (true, budget_tokens),
+        _ => (false, None),
+    };
+
+    Logger::info(&format!(
+        "Anthropic message with model {} ({} messages)",
+        model,
+        payload.messages.len()
+    ));
+
+    let id = format!("msg_{}", uuid::Uuid::new_v4().simple());
+    let input_tokens = Usage::estimate(&prompt, "").prompt_tokens;
+
+    if payload.stream {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let response_model = model.clone();
+        let guard = CancelOnDrop::default();
+        let cancel = guard.token();
+
+        tokio::spawn(
+            async move {
+                let start = json!({
+                    "type": "message_start",
+                    "message": {
+                        "id": id,
+                        "type": "message",
+                        "role": "assistant",
+                        "model": model,
+                        "content": [],
+                        "stop_reason": null,
+                        "stop_sequence": null,
+                        "usage": {"input_tokens": input_tokens, "output_tokens": 0}
+                    }
+                });
+                let _ = tx.send(sse_event("message_start", start));
+
+                let blocks = std::sync::Mutex::new(BlockStream::new());
+                let delta_tx = tx.clone();
+                let result = providers::complete(
+                    &state,
+                    &tenant,
+                    &model,
+                    &prompt,
+                    CompletionOptions {
+                        image: image.as_deref(),
+                        thinking,
+                        thinking_budget,
+                        cancel: Some(&cancel),
+                    },
+                    |event| {
+                        for (name, data) in blocks.lock().unwrap().push(event) {
+                            let _ = delta_tx.send(sse_event(name, data));
+                        }
+                    },
+                )
+                .await;
+
+                match result {
+                    Ok(completion) => {
+                        if let Some((name, data)) = blocks.lock().unwrap().close() {
+                            let _ = tx.send(sse_event(name, data));
+                        }
+                        let output_tokens =
+                            Usage::estimate("", &completion.content).completion_tokens;
+                        let _ = tx.send(sse_event(
+                            "message_delta",
+                            json!({
+                                "type": "message_delta",
+                                "delta": {"stop_reason": "end_turn", "stop_sequence": null},
+                                "usage": {"output_tokens": output_tokens}
+                            }),
+                        ));
+                        let _ = tx.send(sse_event("message_stop", json!({"type": "message_stop"})));
+                    }
+                    Err(e) => {
+                        Logger::error(&format!("Anthropic message stream failed: {}", e.message));
+                        let _ = tx.send(sse_event("error", error_body(&e)));
+                    }
+                }
+            }
+            .instrument(tracing::Span::current()),
+        );
+
+        let mut response = sse_response_with_guard(rx, guard);
+        response
+            .extensions_mut()
+            .insert(RequestModel(response_model));
+        return Ok(response);
+    }
+
+    let completion = providers::complete(
+        &state,
+        &tenant,
+        &model,
+        &prompt,
+        CompletionOptions {
+            image: image.as_deref(),
+            thinking,
+            thinking_budget,
+            ..Default::default()
+        },
+        |_| {},
+    )
+    .await?;
+
+    let mut content = Vec::new();
+    if let Some(thinking) = completion.thinking.filter(|t| !t.is_empty()) {
+        content.push(OutputBlock::Thinking {
+            thinking,
+            signature: String::new(),
+        });
+    }
+    let usage = Usage::estimate(&prompt, &completion.content).into();
+    content.push(OutputBlock::Text {
+        text: completion.content,
+    });
+
+    let response = MessagesResponse {
+        id,
+        object: "message".to_string(),
+        role: "assistant".to_string(),
+        model: model.clone(),
+        content,
+        stop_reason: Some("end_turn".to_string()),
+        stop_sequence: None,
+        usage,
+    };
+    let mut response = Json(response).into_response();
+    response.extensions_mut().insert(RequestModel(model));
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_translation() {
+        let request: MessagesRequest = serde_json::from_value(json!({
+            "model": "qwen3-max",
+            "max_tokens": 1024,
+            "system": [{"type": "text", "text": "Be brief."}],
+            "messages": [
+                {"role": "user", "content": [
+                    {"type": "text", "text": "What is this?"},
+                    {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "AAAA"}},
+                    {"type": "tool_result", "tool_use_id": "t1", "content": "ignored"}
+                ]}
+            ],
+            "thinking": {"type": "enabled", "budget_tokens": 2048}
+        }))
+        .unwrap();
+
+        let messages = prompt_messages(&request);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].role, "system");
+        assert_eq!(messages[0].content, "Be brief.");
+        assert_eq!(messages[1].content, "What is this?");
+        assert_eq!(
+            request.messages[0].content.images(),
+            vec!["data:image/png;base64,AAAA".to_string()]
+        );
+        assert!(matches!(
+            request.thinking,
+            Some(ThinkingConfig::Enabled {
+                budget_tokens: Some(2048)
+            })
+        ));
+    }
+
+    #[test]
+    fn test_block_stream() {
+        let mut blocks = BlockStream::new();
+        let names = |events: Vec<(&'static str, Value)>| {
+            events.into_iter().map(|(name, _)| name).collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            names(blocks.push(StreamEvent::Thinking("hmm".into()))),
+            ["content_block_start", "content_block_delta"]
+        );
+        assert_eq!(
+            names(blocks.push(StreamEvent::Thinking("...".into()))),
+            ["content_block_delta"]
+        );
+        let events = blocks.push(StreamEvent::Answer("Hi".into()));
+        assert_eq!(
+            names(events.clone()),
+            [
+                "content_block_stop",
+                "content_block_start",
+                "content_block_delta"
+            ]
+        );
+        assert_eq!(events[2].1["index"], 1);
+        assert_eq!(events[2].1["delta"]["text"], "Hi");
+        assert!(blocks.push(StreamEvent::Answer(String::new())).is_empty());
+
+        let (name, data) = blocks.close().unwrap();
+        assert_eq!(name, "content_block_stop");
+        assert_eq!(data["index"], 1);
+        assert!(blocks.close().is_none());
+    }
+}
@@ -0,0 +1,262 @@
+// Fed by the server-sent events of /dashboard/events: a `snapshot` when
+// connecting, a `request` for every finished request and a `summary` whenever
+// the totals change.
+
+const MAX_REQUESTS = 100;
+const TABLE_REQUESTS = 15;
+let requests = [];
+
+// Request fields and thread contents come from clients, so never insert them
+// as HTML
+function escapeHtml(value) {
+    return String(value).replace(/[&<>"']/g, c => ({
+        '&': '&amp;', '<': '&lt;', '>': '&gt;', '"': '&quot;', "'": '&#39;'
+    })[c]);
+}
+
+function setText(id, value) {
+    document.getElementById(id).textContent = value;
+}
+
+function table(headers, rows) {
+    return '<table><thead><tr>' + headers.map(h => '<th>' + h + '</th>').join('') +
+        '</tr></thead><tbody>' + rows.join('') + '</tbody></table>';
+}
+
+function render(id, headers, rows, empty) {
+    document.getElementById(id).innerHTML = rows.length > 0
+        ? table(headers, rows)
+        : '<div class="empty-state">' + empty + '</div>';
+}
+
+function badge(value, isError) {
+    return '<span class="status-badge ' + (isError ? 'status-error' : 'status-success') + '">' +
+        escapeHtml(value) + '</span>';
+}
+
+function bar(value, max, isError) {
+    const width = max > 0 ? Math.round(value / max * 100) : 0;
+    return '<div class="bar' + (isError ? ' bar-error' : '') + '" style="width: ' + width + '%"></div>';
+}
+
+function renderSummary(summary) {
+    const stats = summary.stats;
+    setText('stat-total', stats.total_requests);
+    setText('stat-success', stats.successful_requests);
+    setText('stat-failed', stats.failed_requests);
+    setText('stat-average', stats.average_response_time);
+    setText('stat-threads', summary.threads);
+    setText('stat-in-flight', summary.in_flight);
+    setText('stat-rate', stats.total_requests > 0
+        ? Math.floor(stats.successful_requests / stats.total_requests * 100)
+        : 0);
+
+    render('providers-container', ['供应商', '请求', '失败', '进行中', '平均耗时'],
+        summary.providers.map(p => '<tr><td>' + escapeHtml(p.provider) + '</td>' +
+            '<td>' + p.requests + '</td><td>' + badge(p.errors, p.errors > 0) + '</td>' +
+            '<td>' + p.in_flight + '</td><td>' + p.average_ms + ' ms</td></tr>'),
+        '暂无上游请求');
+
+    const busiest = Math.max(0, ...summary.models.map(m => m.requests));
+    render('models-container', ['模型', '请求', '失败', '平均耗时', '首 token', ''],
+        summary.models.map(m => '<tr><td>' + escapeHtml(m.model) + '</td>' +
+            '<td>' + m.requests + '</td><td>' + badge(m.errors, m.errors > 0) + '</td>' +
+            '<td>' + m.average_ms + ' ms</td>' +
+            '<td>' + (m.average_first_token_ms == null ? '-' : m.average_first_token_ms + ' ms') + '</td>' +
+            '<td style="width: 25%">' + bar(m.requests, busiest, false) + '</td></tr>'),
+        '暂无上游请求');
+
+    const errors = (list, label, empty, id) => {
+        const most = Math.max(0, ...list.map(e => e.count));
+        render(id, [label, '类型', '次数', ''],
+            list.map(e => '<tr><td>' + escapeHtml(e.source) + '</td><td>' + escapeHtml(e.kind) + '</td>' +
+                '<td>' + e.count + '</td><td style="width: 30%">' + bar(e.count, most, true) + '</td></tr>'),
+            empty);
+    };
+    errors(summary.upstream_errors, '供应商', '暂无错误', 'upstream-errors-container');
+    errors(summary.http_errors, '路由', '暂无错误', 'http-errors-container');
+
+    const quota = (used, limit) => limit == null ? used : used + ' / ' + limit;
+    render('keys-container', ['Key', '租户', '每分钟', '并发', '今日请求', '今日字符', '总请求', '被拒绝'],
+        summary.keys.map(k => '<tr><td>' + escapeHtml(k.key) + '</td><td>' + escapeHtml(k.tenant) + '</td>' +
+            '<td>' + quota(k.requests_last_minute, k.limits.requests_per_minute) + '</td>' +
+            '<td>' + quota(k.in_flight, k.limits.max_concurrent) + '</td>' +
+            '<td>' + quota(k.daily_requests, k.limits.daily_requests) + '</td>' +
+            '<td>' + quota(k.daily_characters, k.limits.daily_characters) + '</td>' +
+            '<td>' + k.total_requests + '</td>' +
+            '<td>' + badge(k.rejected_requests, k.rejected_requests > 0) + '</td></tr>'),
+        '未配置 API Key 或暂无请求');
+}
+
+function renderRequests() {
+    render('requests-container', ['时间', '方法', '路径', '状态', '耗时', '模型', 'API Key', 'User Agent'],
+        requests.slice(0, TABLE_REQUESTS).map(r => {
+            const time = new Date(r.timestamp * 1000).toLocaleTimeString();
+            return '<tr><td>' + time + '</td><td>' + escapeHtml(r.method) + '</td>' +
+                '<td>' + escapeHtml(r.path) + '</td>' +
+                '<td>' + badge(r.status, r.status < 200 || r.status >= 300) + '</td>' +
+                '<td>' + (r.duration_ms / 1000).toFixed(2) + 's</td>' +
+                '<td>' + escapeHtml(r.model || '-') + '</td>' +
+                '<td>' + escapeHtml(r.api_key || '-') + '</td>' +
+                '<td>' + escapeHtml(r.user_agent || 'N/A') + '</td></tr>';
+        }),
+        '暂无请求');
+    drawChart();
+}
+
+// Response time of the latest requests, oldest on the left
+function drawChart() {
+    const canvas = document.getElementById('latency-chart');
+    const width = canvas.parentElement.clientWidth;
+    const height = canvas.parentElement.clientHeight;
+    const ratio = window.devicePixelRatio || 1;
+    canvas.width = width * ratio;
+    canvas.height = height * ratio;
+    canvas.style.width = width + 'px';
+    canvas.style.height = height + 'px';
+    const ctx = canvas.getContext('2d');
+    ctx.scale(ratio, ratio);
+    ctx.clearRect(0, 0, width, height);
+
+    const points = requests.slice(0, 50).reverse();
+    const left = 50, bottom = 20, top = 10;
+    const plotWidth = width - left - 10, plotHeight = height - top - bottom;
+    const max = Math.max(1, ...points.map(r => r.duration_ms)) / 1000;
+
+    ctx.font = '12px sans-serif';
+    ctx.fillStyle = '#999';
+    ctx.strokeStyle = '#eee';
+    for (let i = 0; i <= 4; i++) {
+        const y = top + plotHeight - plotHeight * i / 4;
+        ctx.beginPath();
+        ctx.moveTo(left, y);
+        ctx.lineTo(width - 10, y);
+        ctx.stroke();
+        ctx.fillText((max * i / 4).toFixed(1) + 's', 5, y + 4);
+    }
+    if (points.length === 0) {
+        return;
+    }
+
+    const x = i => left + (points.length === 1 ? plotWidth / 2 : plotWidth * i / (points.length - 1));
+    const y = r => top + plotHeight - plotHeight * (r.duration_ms / 1000) / max;
+    ctx.beginPath();
+    points.forEach((r, i) => i === 0 ? ctx.moveTo(x(i), y(r)) : ctx.lineTo(x(i), y(r)));
+    ctx.strokeStyle = '#667eea';
+    ctx.lineWidth = 2;
+    ctx.stroke();
+    points.forEach((r, i) => {
+        ctx.beginPath();
+        ctx.arc(x(i), y(r), 3, 0, Math.PI * 2);
+        ctx.fillStyle = r.status >= 400 ? '#dc3545' : '#667eea';
+        ctx.fill();
+    });
+}
+
+function addRequest(request) {
+    if (requests.some(r => r.id === request.id)) {
+        return;
+    }
+    requests.unshift(request);
+    requests.length = Math.min(requests.length, MAX_REQUESTS);
+    renderRequests();
+}
+
+let events = null;
+
+// With API keys configured the live data needs an admin key, passed as a
+// query parameter since EventSource cannot set headers
+function connect() {
+    const badgeEl = document.getElementById('live-badge');
+    const key = apiKey();
+    if (events) {
+        events.close();
+    }
+    events = new EventSource('/dashboard/events' + (key ? '?api_key=' + encodeURIComponent(key) : ''));
+    events.onopen = () => {
+        badgeEl.textContent = '实时';
+        badgeEl.classList.remove('offline');
+    };
+    // EventSource reconnects by itself and gets a fresh snapshot, but gives
+    // up on a rejected key
+    events.onerror = () => {
+        const rejected = events.readyState === EventSource.CLOSED;
+        badgeEl.textContent = rejected ? '需要管理员 API Key' : '已断开，重连中';
+        badgeEl.classList.add('offline');
+    };
+    events.addEventListener('snapshot', e => {
+        const snapshot = JSON.parse(e.data);
+        requests = snapshot.requests.slice().reverse();
+        renderSummary(snapshot.summary);
+        renderRequests();
+    });
+    events.addEventListener('summary', e => renderSummary(JSON.parse(e.data)));
+    events.addEventListener('request', e => addRequest(JSON.parse(e.data)));
+}
+
+// The thread browser goes through the regular API, so it only shows the
+// threads of the tenant owning the key
+function apiKey() {
+    const key = document.getElementById('api-key').value.trim();
+    sessionStorage.setItem('dashboard-api-key', key);
+    return key;
+}
+
+function apiHeaders() {
+    const key = apiKey();
+    return key ? { 'Authorization': 'Bearer ' + key } : {};
+}
+
+async function apiGet(path) {
+    const response = await fetch(path, { headers: apiHeaders() });
+    const body = await response.json();
+    if (!response.ok) {
+        throw new Error(body.detail || body.error || response.statusText);
+    }
+    return body;
+}
+
+async function loadThreads() {
+    const container = document.getElementById('threads-container');
+    document.getElementById('messages-container').innerHTML = '';
+    try {
+        const threads = await apiGet('/v1/threads?limit=100');
+        if (threads.data.length === 0) {
+            container.innerHTML = '<div class="empty-state">暂无线程</div>';
+            return;
+        }
+        container.innerHTML = table(['线程', '创建时间'], threads.data.map(t =>
+            '<tr class="thread-row" data-id="' + escapeHtml(t.id) + '"><td>' +
+            escapeHtml(t.metadata && t.metadata.title ? t.metadata.title : t.id) + '</td>' +
+            '<td>' + new Date(t.created_at * 1000).toLocaleString() + '</td></tr>'));
+        container.querySelectorAll('.thread-row').forEach(row =>
+            row.addEventListener('click', () => loadMessages(row)));
+    } catch (error) {
+        container.innerHTML = '<div class="empty-state">加载失败：' + escapeHtml(error.message) + '</div>';
+    }
+}
+
+async function loadMessages(row) {
+    document.querySelectorAll('.thread-row').forEach(r => r.classList.remove('selected'));
+    row.classList.add('selected');
+    const container = document.getElementById('messages-container');
+    try {
+        const id = encodeURIComponent(row.dataset.id);
+        const messages = await apiGet('/v1/threads/' + id + '/messages?order=asc&limit=100');
+        container.innerHTML = messages.data.map(m =>
+            '<div class="message message-' + (m.role === 'user' ? 'user' : 'assistant') + '">' +
+            '<div class="message-meta">' + escapeHtml(m.role) + ' · ' +
+            new Date(m.created_at * 1000).toLocaleString() +
+            (m.status ? ' · ' + escapeHtml(m.status) : '') + '</div>' +
+            escapeHtml(m.content.map(part => part.text.value).join('\n')) + '</div>'
+        ).join('') || '<div class="empty-state">暂无消息</div>';
+    } catch (error) {
+        container.innerHTML = '<div class="empty-state">加载失败：' + escapeHtml(error.message) + '</div>';
+    }
+}
+
+document.getElementById('api-key').value = sessionStorage.getItem('dashboard-api-key') || '';
+document.getElementById('load-threads').addEventListener('click', loadThreads);
+document.getElementById('api-key').addEventListener('change', connect);
+window.addEventListener('resize', drawChart);
+connect();
@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use axum::{
+    extract::{Query, Request, State},
+    http::{
+        header::{AUTHORIZATION, UPGRADE},
+        HeaderMap,
+    },
+    middleware::Next,
+    response::Response,
+};
+use serde::Deserialize;
+
+use super::error::ApiError;
+use super::rate_limit::{CallerKey, KeyLimits};
+use super::state::AppState;
+
+/// Tenant used for every request when no API keys are configured
+pub const DEFAULT_TENANT: &str = "default";
+
+/// Owner of the request, inserted into request extensions by [`require_api_key`]
+#[derive(Debug, Clone)]
+pub struct Tenant(pub String);
+
+/// Tenant and limits configured for one API key
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiKey {
+    pub tenant: String,
+    /// May read the server-wide dashboard data, i.e. every key's usage and
+    /// the requests of every tenant
+    #[serde(default)]
+    pub admin: bool,
+    #[serde(flatten)]
+    pub limits: KeyLimits,
+}
+
+/// A key file entry: either just the tenant name or a full [`ApiKey`]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum ApiKeyEntry {
+    Tenant(String),
+    Detailed(ApiKey),
+}
+
+impl From<ApiKeyEntry> for ApiKey {
+    fn from(entry: ApiKeyEntry) -> Self {
+        match entry {
+            ApiKeyEntry::Tenant(tenant) => ApiKey {
+                tenant,
+                admin: false,
+                limits: KeyLimits::default(),
+            },
+            ApiKeyEntry::Detailed(api_key) => api_key,
+        }
+    }
+}
+
+/// Load an API key file: a JSON object mapping each key to its tenant name,
+/// or to an object with `tenant` and optional limits
+pub fn load_api_keys(path: impl AsRef<Path>) -> std::io::Result<HashMap<String, ApiKey>> {
+    let content = std::fs::read_to_string(path)?;
+    let entries: HashMap<String, ApiKeyEntry> = serde_json::from_str(&content)?;
+    Ok(entries
+        .into_iter()
+        .map(|(key, entry)| (key, entry.into()))
+        .collect())
+}
+
+/// The API key sent as a bearer token or `x-api-key` header. Other
+/// `Authorization` schemes, such as a proxy's `Basic`, are not keys.
+pub fn request_key(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim)
+        .or_else(|| headers.get("x-api-key").and_then(|v| v.to_str().ok()))
+}
+
+#[derive(Deserialize)]
+struct KeyQuery {
+    api_key: Option<String>,
+}
+
+/// The API key from the `api_key` query parameter
+fn query_key(request: &Request) -> Option<String> {
+    Query::<KeyQuery>::try_from_uri(request.uri())
+        .ok()
+        .and_then(|Query(query)| query.api_key)
+}
+
+/// The API key of a WebSocket handshake from the `api_key` query parameter,
+/// since browsers cannot set headers on WebSocket connections
+fn websocket_key(request: &Request) -> Option<String> {
+    let is_websocket = request
+        .headers()
+        .get(UPGRADE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.eq_ignore_ascii_case("websocket"));
+    if !is_websocket {
+        return None;
+    }
+    query_key(request)
+}
+
+/// Resolve the caller's tenant from its API key.
+///
+/// With no keys configured the server stays open and everything belongs to
+/// [`DEFAULT_TENANT`].
+pub async fn require_api_key(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let tenant = if state.auth_enabled() {
+        let key = request_key(request.headers())
+            .map(str::to_string)
+            .or_else(|| websocket_key(&request))
+            .ok_or_else(|| ApiError::unauthorized("Missing API key"))?;
+        let api_key = state
+            .api_key(&key)
+            .ok_or_else(|| ApiError::unauthorized("Invalid API key"))?;
+        let caller = CallerKey {
+            key,
+            tenant: api_key.tenant.clone(),
+            limits: api_key.limits,
+        };
+        request.extensions_mut().insert(caller);
+        api_key.tenant
+    } else {
+        DEFAULT_TENANT.to_string()
+    };
+
+    request.extensions_mut().insert(Tenant(tenant));
+    Ok(next.run(request).await)
+}
+
+/// Keep the server-wide dashboard data to admin keys once API keys are
+/// configured. The key may also come as the `api_key` query parameter, as
+/// the dashboard reads its event stream through `EventSource`, which cannot
+/// set headers.
+pub async fn require_admin_key(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    if state.auth_enabled() {
+        let key = request_key(request.headers())
+            .map(str::to_string)
+            .or_else(|| query_key(&request))
+            .ok_or_else(|| ApiError::unauthorized("Missing API key"))?;
+        let api_key = state
+            .api_key(&key)
+            .ok_or_else(|| ApiError::unauthorized("Invalid API key"))?;
+        if !api_key.admin {
+            return Err(ApiError::forbidden("Admin API key required"));
+        }
+    }
+    Ok(next.run(request).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn test_request_key() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(request_key(&headers), None);
+
+        headers.insert("x-api-key", HeaderValue::from_static("sk-alt"));
+        assert_eq!(request_key(&headers), Some("sk-alt"));
+
+        headers.insert(
+            AUTHORIZATION,
+            HeaderValue::from_static("Basic cHJveHk6cHc="),
+        );
+        assert_eq!(request_key(&headers), Some("sk-alt"));
+
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer sk-test"));
+        assert_eq!(request_key(&headers), Some("sk-test"));
+    }
+}
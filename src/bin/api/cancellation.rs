@@ -0,0 +1,159 @@
+//! Cancelling thread responses while the provider is still answering
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use reverse_api::CancelToken;
+
+use super::error::ApiError;
+use super::types::Response;
+
+struct Entry {
+    tenant: String,
+    response: Response,
+    cancel: CancelToken,
+}
+
+/// Responses being generated, by id
+#[derive(Clone, Default)]
+pub struct ResponseRegistry {
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+impl ResponseRegistry {
+    /// Track `response` until the returned guard is dropped
+    pub fn register(&self, tenant: &str, response: Response) -> ResponseGuard {
+        let cancel = CancelToken::new();
+        let id = response.id.clone();
+        self.entries.lock().unwrap().insert(
+            id.clone(),
+            Entry {
+                tenant: tenant.to_string(),
+                response,
+                cancel: cancel.clone(),
+            },
+        );
+        ResponseGuard {
+            registry: self.clone(),
+            id,
+            cancel,
+        }
+    }
+
+    /// Stop a response; other tenants' and finished responses are reported as
+    /// not found
+    pub fn cancel(&self, tenant: &str, id: &str) -> Result<Response, ApiError> {
+        let entries = self.entries.lock().unwrap();
+        let entry = entries
+            .get(id)
+            .filter(|entry| entry.tenant == tenant)
+            .ok_or_else(|| ApiError::not_found("Response not found"))?;
+        entry.cancel.cancel();
+        Ok(Response {
+            status: "cancelled".to_string(),
+            ..entry.response.clone()
+        })
+    }
+
+    /// Responses still being generated
+    pub fn in_progress(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    /// Stop every response, e.g. when the server shuts down
+    pub fn cancel_all(&self) -> usize {
+        let entries = self.entries.lock().unwrap();
+        for entry in entries.values() {
+            entry.cancel.cancel();
+        }
+        entries.len()
+    }
+}
+
+/// Registration of one response. Dropping it cancels the response, which is
+/// how a client that goes away stops the upstream request.
+pub struct ResponseGuard {
+    registry: ResponseRegistry,
+    id: String,
+    cancel: CancelToken,
+}
+
+impl ResponseGuard {
+    pub fn token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+}
+
+impl Drop for ResponseGuard {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+        self.registry.entries.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Cancels its token when dropped. A streamed one-shot completion keeps it in
+/// the SSE body, so a client that goes away stops the upstream request.
+#[derive(Default)]
+pub struct CancelOnDrop {
+    cancel: CancelToken,
+}
+
+impl CancelOnDrop {
+    pub fn token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+}
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(id: &str) -> Response {
+        Response {
+            id: id.to_string(),
+            object: "thread.response".to_string(),
+            created_at: 0,
+            thread_id: "t1".to_string(),
+            status: "in_progress".to_string(),
+            model: "qwen3-max".to_string(),
+            message_id: None,
+            response: None,
+        }
+    }
+
+    #[test]
+    fn test_cancel_and_disconnect() {
+        let registry = ResponseRegistry::default();
+        let guard = registry.register("acme", response("r1"));
+        let token = guard.token();
+
+        assert!(registry.cancel("other", "r1").is_err());
+        assert!(!token.is_cancelled());
+        assert_eq!(registry.cancel("acme", "r1").unwrap().status, "cancelled");
+        assert!(token.is_cancelled());
+
+        // A client going away drops the guard, which also cancels
+        let guard2 = registry.register("acme", response("r2"));
+        let token2 = guard2.token();
+        drop(guard2);
+        assert!(token2.is_cancelled());
+        assert!(registry.cancel("acme", "r2").is_err());
+        drop(guard);
+        assert!(registry.cancel("acme", "r1").is_err());
+    }
+
+    #[test]
+    fn test_cancel_on_drop() {
+        let guard = CancelOnDrop::default();
+        let token = guard.token();
+        assert!(!token.is_cancelled());
+        drop(guard);
+        assert!(token.is_cancelled());
+    }
+}
@@ -0,0 +1,474 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use axum::http::HeaderValue;
+use serde::Deserialize;
+
+use super::auth::{self, ApiKey, ApiKeyEntry};
+use super::media::RetentionPolicy;
+use super::providers::Provider;
+use super::rate_limit::mask_key;
+use super::state::SHARED_CREDENTIALS;
+use super::telemetry::LogFormat;
+
+/// Server settings loaded from `--config`, overridden by CLI flags and env vars
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub server: ListenConfig,
+    pub storage: StorageConfig,
+    pub media: MediaConfig,
+    pub models: ModelsConfig,
+    pub auth: AuthConfig,
+    pub providers: ProvidersConfig,
+    pub logging: LoggingConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ListenConfig {
+    pub host: String,
+    pub port: u16,
+    /// Explicit listen addresses; when set, `host` and `port` are ignored
+    pub listen: Vec<String>,
+    /// Allowed CORS origins; empty or `"*"` allows any origin
+    pub cors_origins: Vec<String>,
+    /// How long a shutdown waits for in-flight responses and jobs before
+    /// cancelling them; 0 cancels them right away
+    pub shutdown_timeout_secs: u64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub data_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MediaConfig {
+    /// Where generated images and videos are downloaded
+    pub output_dir: PathBuf,
+    /// Delete downloaded media older than this
+    pub max_age_hours: Option<u64>,
+    /// Delete the oldest downloaded media once they take more than this
+    pub max_total_mb: Option<u64>,
+}
+
+impl MediaConfig {
+    pub fn retention(&self) -> RetentionPolicy {
+        RetentionPolicy {
+            max_age: self.max_age_hours.map(|hours| hours * 3600),
+            max_total_bytes: self.max_total_mb.map(|mb| mb * 1024 * 1024),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModelsConfig {
+    /// Model used by threads created without one
+    pub default: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    pub api_keys_file: Option<PathBuf>,
+    /// Keys declared inline, in the same format as the key file
+    pub keys: HashMap<String, ApiKeyEntry>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    pub format: LogFormat,
+    /// `tracing` filter directive; `RUST_LOG` overrides it
+    pub level: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProvidersConfig {
+    pub qwen: QwenConfig,
+    pub deepseek: DeepSeekConfig,
+    pub grok: ProxyConfig,
+    pub chatgpt: ProxyConfig,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QwenConfig {
+    pub tokens: Vec<String>,
+    pub accounts: Vec<QwenAccount>,
+    pub default_model: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QwenAccount {
+    pub email: String,
+    pub password: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeepSeekConfig {
+    pub token: Option<String>,
+    pub default_model: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProxyConfig {
+    pub proxy: Option<String>,
+    pub default_model: Option<String>,
+}
+
+impl Default for ListenConfig {
+    fn default() -> Self {
+        Self {
+            host: "0.0.0.0".to_string(),
+            port: 6969,
+            listen: Vec::new(),
+            cors_origins: Vec::new(),
+            shutdown_timeout_secs: 30,
+        }
+    }
+}
+
+impl Default for MediaConfig {
+    fn default() -> Self {
+        Self {
+            output_dir: PathBuf::from("./generated"),
+            max_age_hours: None,
+            max_total_mb: None,
+        }
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::Text,
+            level: "info".to_string(),
+        }
+    }
+}
+
+impl Default for ModelsConfig {
+    fn default() -> Self {
+        Self {
+            default: "qwen3-max".to_string(),
+        }
+    }
+}
+
+/// Every problem found in a configuration, reported together
+#[derive(Debug)]
+pub struct ConfigError {
+    pub source: String,
+    pub problems: Vec<String>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid configuration in {}", self.source)?;
+        for problem in &self.problems {
+            write!(f, "\n  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl ServerConfig {
+    /// Read a TOML configuration file
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let error = |problem: String| ConfigError {
+            source: path.display().to_string(),
+            problems: vec![problem],
+        };
+        let content = std::fs::read_to_string(path).map_err(|e| error(e.to_string()))?;
+        toml::from_str(&content).map_err(|e| error(e.to_string().trim_end().to_string()))
+    }
+
+    /// Apply `API_*`, `QWEN_TOKEN`, `DEEPSEEK_TOKEN` and `LOG_FORMAT` environment
+    /// variables. `QWEN_TOKEN` adds an account to the configured ones.
+    pub fn apply_env(&mut self) -> Result<(), ConfigError> {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+
+        if let Some(host) = var("API_HOST") {
+            self.server.host = host;
+            self.server.listen.clear();
+        }
+        if let Some(port) = var("API_PORT") {
+            self.server.port = port.parse().map_err(|_| ConfigError {
+                source: "environment".to_string(),
+                problems: vec![format!("API_PORT: invalid port number '{}'", port)],
+            })?;
+            self.server.listen.clear();
+        }
+        if let Some(dir) = var("API_DATA_DIR") {
+            self.storage.data_dir = Some(dir.into());
+        }
+        if let Some(file) = var("API_KEYS_FILE") {
+            self.auth.api_keys_file = Some(file.into());
+        }
+        if let Some(dir) = var("API_MEDIA_DIR") {
+            self.media.output_dir = dir.into();
+        }
+        if let Some(token) = var("QWEN_TOKEN") {
+            if !self.providers.qwen.tokens.contains(&token) {
+                self.providers.qwen.tokens.push(token);
+            }
+        }
+        if let Some(token) = var("DEEPSEEK_TOKEN") {
+            self.providers.deepseek.token = Some(token);
+        }
+        if let Some(format) = var("LOG_FORMAT") {
+            self.logging.format = format.parse().map_err(|e| ConfigError {
+                source: "environment".to_string(),
+                problems: vec![format!("LOG_FORMAT: {}", e)],
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Keys from `auth.api_keys_file` merged with the inline `auth.keys`
+    pub fn api_keys(&self) -> Result<HashMap<String, ApiKey>, String> {
+        let mut api_keys = match &self.auth.api_keys_file {
+            Some(path) => auth::load_api_keys(path)
+                .map_err(|e| format!("auth.api_keys_file {}: {}", path.display(), e))?,
+            None => HashMap::new(),
+        };
+        for (key, entry) in &self.auth.keys {
+            api_keys.insert(key.clone(), entry.clone().into());
+        }
+        Ok(api_keys)
+    }
+
+    /// Addresses to bind, from `listen` or else `host:port`
+    pub fn listen_addrs(&self) -> Vec<String> {
+        if self.server.listen.is_empty() {
+            vec![format!("{}:{}", self.server.host, self.server.port)]
+        } else {
+            self.server.listen.clone()
+        }
+    }
+
+    /// Check everything that can be checked before the server starts
+    pub fn validate(&self, source: &str) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+
+        for addr in self.listen_addrs() {
+            if addr.parse::<SocketAddr>().is_err() {
+                problems.push(format!("server: '{}' is not a valid listen address", addr));
+            }
+        }
+        for origin in &self.server.cors_origins {
+            if origin != "*" && HeaderValue::from_str(origin).is_err() {
+                problems.push(format!("server.cors_origins: invalid origin '{}'", origin));
+            }
+        }
+
+        if let Err(e) = tracing_subscriber::EnvFilter::try_new(&self.logging.level) {
+            problems.push(format!(
+                "logging.level: invalid filter '{}': {}",
+                self.logging.level, e
+            ));
+        }
+        if self.media.output_dir.as_os_str().is_empty() {
+            problems.push("media.output_dir must not be empty".to_string());
+        }
+        if self.media.max_age_hours == Some(0) {
+            problems.push("media.max_age_hours must be greater than 0".to_string());
+        }
+        if self.media.max_total_mb == Some(0) {
+            problems.push("media.max_total_mb must be greater than 0".to_string());
+        }
+        if self.resolve_model(&self.models.default).is_none() {
+            problems.push(format!(
+                "models.default: unsupported model '{}'",
+                self.models.default
+            ));
+        }
+
+        let api_keys = match self.api_keys() {
+            Ok(api_keys) => api_keys,
+            Err(e) => {
+                problems.push(e);
+                HashMap::new()
+            }
+        };
+        for (key, api_key) in &api_keys {
+            if key.trim().is_empty() {
+                problems.push("auth: API keys must not be empty".to_string());
+            }
+            if api_key.tenant.trim().is_empty() || api_key.tenant == SHARED_CREDENTIALS {
+                problems.push(format!(
+                    "auth: invalid tenant '{}' for key {}",
+                    api_key.tenant,
+                    mask_key(key)
+                ));
+            }
+            let limits = &api_key.limits;
+            if limits.requests_per_minute == Some(0)
+                || limits.max_concurrent == Some(0)
+                || limits.daily_requests == Some(0)
+                || limits.daily_characters == Some(0)
+            {
+                problems.push(format!(
+                    "auth: limits for key {} must be greater than zero",
+                    mask_key(key)
+                ));
+            }
+        }
+
+        let providers = &self.providers;
+        if providers.qwen.tokens.iter().any(|t| t.trim().is_empty()) {
+            problems.push("providers.qwen.tokens: tokens must not be empty".to_string());
+        }
+        for account in &providers.qwen.accounts {
+            if account.email.trim().is_empty() || account.password.is_empty() {
+                problems.push(
+                    "providers.qwen.accounts: each account needs an email and password".to_string(),
+                );
+            }
+        }
+        if providers.deepseek.token.as_deref() == Some("") {
+            problems.push("providers.deepseek.token must not be empty".to_string());
+        }
+        for (name, proxy) in [
+            ("grok", &providers.grok.proxy),
+            ("chatgpt", &providers.chatgpt.proxy),
+        ] {
+            if let Some(proxy) = proxy {
+                if url::Url::parse(proxy).is_err() {
+                    problems.push(format!(
+                        "providers.{}.proxy: '{}' is not a valid URL",
+                        name, proxy
+                    ));
+                }
+            }
+        }
+        for (name, provider, model) in [
+            ("qwen", Provider::Qwen, &providers.qwen.default_model),
+            (
+                "deepseek",
+                Provider::DeepSeek,
+                &providers.deepseek.default_model,
+            ),
+            ("grok", Provider::Grok, &providers.grok.default_model),
+            (
+                "chatgpt",
+                Provider::ChatGpt,
+                &providers.chatgpt.default_model,
+            ),
+        ] {
+            if let Some(model) = model {
+                if Provider::from_model(model) != Some(provider) {
+                    problems.push(format!(
+                        "providers.{}.default_model: '{}' is not a {} model",
+                        name, model, name
+                    ));
+                }
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError {
+                source: source.to_string(),
+                problems,
+            })
+        }
+    }
+
+    /// Models named in the configuration, as the default or a provider's default
+    pub fn configured_models(&self) -> impl Iterator<Item = &str> {
+        let providers = &self.providers;
+        [
+            &providers.qwen.default_model,
+            &providers.deepseek.default_model,
+            &providers.grok.default_model,
+            &providers.chatgpt.default_model,
+        ]
+        .into_iter()
+        .flatten()
+        .chain([&self.models.default])
+        .map(String::as_str)
+    }
+
+    /// Map a bare provider name such as `grok` to that provider's default
+    /// model; other model ids are returned unchanged if supported
+    pub fn resolve_model(&self, model: &str) -> Option<String> {
+        let providers = &self.providers;
+        let default = match model {
+            "qwen" => providers.qwen.default_model.as_ref(),
+            "deepseek" => providers.deepseek.default_model.as_ref(),
+            "grok" => providers.grok.default_model.as_ref(),
+            "chatgpt" => providers.chatgpt.default_model.as_ref(),
+            _ => None,
+        };
+        let model = default.map_or(model, String::as_str);
+        Provider::from_model(model).map(|_| model.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_validate() {
+        let config: ServerConfig = toml::from_str(
+            r#"
+            [server]
+            listen = ["127.0.0.1:8080", "[::1]:8080"]
+            cors_origins = ["https://app.example.com"]
+
+            [auth.keys]
+            "sk-team-a" = "team-a"
+            "sk-team-b" = { tenant = "team-b", requests_per_minute = 30 }
+
+            [providers.grok]
+            proxy = "http://127.0.0.1:7890"
+            default_model = "grok-3-auto"
+            "#,
+        )
+        .unwrap();
+        config.validate("test.toml").unwrap();
+        assert_eq!(config.listen_addrs().len(), 2);
+        assert_eq!(config.media.output_dir, PathBuf::from("./generated"));
+        assert_eq!(config.resolve_model("grok").unwrap(), "grok-3-auto");
+        assert_eq!(config.resolve_model("qwen3-max").unwrap(), "qwen3-max");
+        assert_eq!(config.resolve_model("llama"), None);
+    }
+
+    #[test]
+    fn test_reports_every_problem() {
+        assert!(toml::from_str::<ServerConfig>("[server]\nhots = \"x\"").is_err());
+
+        let config: ServerConfig = toml::from_str(
+            r#"
+            [server]
+            listen = ["localhost"]
+
+            [auth.keys]
+            "sk-zero" = { tenant = "t", daily_requests = 0 }
+
+            [providers.chatgpt]
+            proxy = "not a url"
+            default_model = "grok-4"
+            "#,
+        )
+        .unwrap();
+        let err = config.validate("bad.toml").unwrap_err();
+        assert_eq!(err.problems.len(), 4, "{}", err);
+    }
+}
@@ -0,0 +1,496 @@
+//! Thread export (`GET /v1/threads/{thread_id}/export`) and import
+//! (`POST /v1/threads/import`) for archiving threads and moving them between
+//! servers
+
+use std::collections::HashSet;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use reverse_api::qwen::models::QwenFile;
+use reverse_api::{CancelToken, Logger};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use super::auth::Tenant;
+use super::error::{ApiError, ErrorResponse};
+use super::message_tree::{self, MessageNode};
+use super::providers::{self, PromptMessage, TurnRequest};
+use super::state::{AppState, ThreadState};
+use super::stats::RequestModel;
+use super::types::{Thread, ThreadPath};
+
+/// Bumped when an export can no longer be read by older servers
+pub const EXPORT_VERSION: u32 = 1;
+
+/// Sent ahead of the history when an import re-seeds a provider session
+const RESEED_INSTRUCTION: &str = "This conversation was moved here from another session. \
+Read the transcript and reply only with \"OK\"; the next messages continue it.";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Json,
+    /// The thread without its messages on the first line, then one message
+    /// per line
+    Jsonl,
+    /// The active branch for reading; cannot be imported
+    Markdown,
+}
+
+impl ExportFormat {
+    fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Json => "application/json",
+            ExportFormat::Jsonl => "application/x-ndjson",
+            ExportFormat::Markdown => "text/markdown; charset=utf-8",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            ExportFormat::Jsonl => "jsonl",
+            ExportFormat::Markdown => "md",
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportQuery {
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImportQuery {
+    /// Replay the history into a new provider session instead of continuing
+    /// the exported one
+    #[serde(default)]
+    pub reseed: bool,
+}
+
+/// Provider session handles of a thread
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ThreadSession {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub qwen_chat_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub qwen_parent_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub qwen_account: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deepseek_session_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deepseek_message_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grok_extra_data: Option<reverse_api::ExtraData>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chatgpt_parent_message_id: Option<String>,
+}
+
+/// Everything needed to recreate a thread on another server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThreadExport {
+    pub object: String,
+    pub version: u32,
+    pub id: String,
+    pub created_at: u64,
+    #[serde(default)]
+    pub metadata: Option<serde_json::Value>,
+    pub model: String,
+    #[serde(default)]
+    pub active_message_id: Option<String>,
+    /// All branches, in the order the messages were added
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub messages: Vec<MessageNode>,
+    /// Uploaded files the messages refer to
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<QwenFile>,
+    #[serde(default)]
+    pub session: ThreadSession,
+}
+
+impl ThreadExport {
+    pub fn new(thread_id: &str, thread: &ThreadState, attachments: Vec<QwenFile>) -> Self {
+        Self {
+            object: "thread.export".to_string(),
+            version: EXPORT_VERSION,
+            id: thread_id.to_string(),
+            created_at: thread.created_at,
+            metadata: thread.metadata.clone(),
+            model: thread.model.clone(),
+            active_message_id: thread.active_message_id.clone(),
+            messages: thread.messages.clone(),
+            attachments,
+            session: ThreadSession {
+                qwen_chat_id: thread.qwen_chat_id.clone(),
+                qwen_parent_id: thread.qwen_parent_id.clone(),
+                qwen_account: thread.qwen_account.clone(),
+                deepseek_session_id: thread.deepseek_session_id.clone(),
+                deepseek_message_id: thread.deepseek_message_id.clone(),
+                grok_extra_data: thread.grok_extra_data.clone(),
+                chatgpt_parent_message_id: thread.chatgpt_parent_message_id.clone(),
+            },
+        }
+    }
+
+    fn into_thread(self, tenant: &str, model: String) -> ThreadState {
+        let session = self.session;
+        ThreadState {
+            tenant: tenant.to_string(),
+            created_at: self.created_at,
+            metadata: self.metadata,
+            messages: self.messages,
+            active_message_id: self.active_message_id,
+            model,
+            deepseek_session_id: session.deepseek_session_id,
+            deepseek_message_id: session.deepseek_message_id,
+            qwen_chat_id: session.qwen_chat_id,
+            qwen_parent_id: session.qwen_parent_id,
+            qwen_account: session.qwen_account,
+            grok_extra_data: session.grok_extra_data,
+            chatgpt_parent_message_id: session.chatgpt_parent_message_id,
+        }
+    }
+
+    /// Reject exports whose message tree does not hold together
+    pub fn validate(&self) -> Result<(), String> {
+        if self.object != "thread.export" {
+            return Err(format!(
+                "Expected object 'thread.export', got '{}'",
+                self.object
+            ));
+        }
+        if self.version > EXPORT_VERSION {
+            return Err(format!(
+                "Export version {} is newer than the supported version {}",
+                self.version, EXPORT_VERSION
+            ));
+        }
+        let mut ids = HashSet::new();
+        for message in &self.messages {
+            if !message.id.is_empty() && !ids.insert(message.id.as_str()) {
+                return Err(format!("Duplicate message id '{}'", message.id));
+            }
+        }
+        for message in &self.messages {
+            if let Some(parent_id) = &message.parent_id {
+                if !ids.contains(parent_id.as_str()) {
+                    return Err(format!(
+                        "Message '{}' refers to unknown parent '{}'",
+                        message.id, parent_id
+                    ));
+                }
+            }
+        }
+        if let Some(active) = &self.active_message_id {
+            if !ids.contains(active.as_str()) {
+                return Err(format!("Unknown active message '{}'", active));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn to_jsonl(&self) -> String {
+        let header = Self {
+            messages: Vec::new(),
+            ..self.clone()
+        };
+        let mut out = serde_json::to_string(&header).unwrap();
+        for message in &self.messages {
+            out.push('\n');
+            out.push_str(&serde_json::to_string(message).unwrap());
+        }
+        out.push('\n');
+        out
+    }
+
+    /// Read a JSON or JSONL export
+    pub fn parse(body: &str) -> Result<Self, String> {
+        if let Ok(export) = serde_json::from_str(body) {
+            return Ok(export);
+        }
+        let mut lines = body
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty());
+        let (_, header) = lines.next().ok_or("Export is empty")?;
+        let mut export: Self =
+            serde_json::from_str(header).map_err(|e| format!("Invalid export: {}", e))?;
+        for (idx, line) in lines {
+            let message =
+                serde_json::from_str(line).map_err(|e| format!("Line {}: {}", idx + 1, e))?;
+            export.messages.push(message);
+        }
+        Ok(export)
+    }
+
+    /// The active branch as a readable transcript
+    pub fn to_markdown(&self) -> String {
+        let mut out = format!("# Thread {}\n\n", self.id);
+        out.push_str(&format!("- Model: `{}`\n", self.model));
+        if let Some(created) = chrono::DateTime::from_timestamp(self.created_at as i64, 0) {
+            out.push_str(&format!("- Created: {}\n", created.to_rfc3339()));
+        }
+        if let Some(metadata) = &self.metadata {
+            out.push_str(&format!("- Metadata: `{}`\n", metadata));
+        }
+
+        for message in message_tree::branch(&self.messages, self.active_message_id.as_deref()) {
+            let mut role = message.role.clone();
+            if let Some(first) = role.get_mut(..1) {
+                first.make_ascii_uppercase();
+            }
+            out.push_str(&format!(
+                "\n## {}\n\n{}\n",
+                role,
+                message.content.trim_end()
+            ));
+            if !message.file_ids.is_empty() {
+                out.push('\n');
+            }
+            for file_id in &message.file_ids {
+                match self.attachments.iter().find(|file| file.id == *file_id) {
+                    Some(file) => {
+                        out.push_str(&format!("- Attachment: [{}]({})\n", file.name, file.url))
+                    }
+                    None => out.push_str(&format!("- Attachment: `{}`\n", file_id)),
+                }
+            }
+        }
+        out
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/threads/{thread_id}/export",
+    tag = "threads",
+    summary = "导出线程",
+    description = "导出所有分支的消息、元数据、模型、附件和上游会话信息；markdown 只包含当前分支，不能导入",
+    params(ThreadPath, ExportQuery),
+    responses(
+        (status = 200, description = "导出内容", content(
+            ("application/json"),
+            ("application/x-ndjson"),
+            ("text/markdown")
+        )),
+        (status = 404, description = "线程不存在", body = ErrorResponse)
+    )
+)]
+pub async fn export_thread(
+    State(state): State<AppState>,
+    Extension(Tenant(tenant)): Extension<Tenant>,
+    Path(params): Path<ThreadPath>,
+    Query(query): Query<ExportQuery>,
+) -> Result<Response, ApiError> {
+    let thread = state.get_thread(&tenant, &params.thread_id).await?;
+
+    let mut file_ids: Vec<String> = Vec::new();
+    for message in &thread.messages {
+        for file_id in &message.file_ids {
+            if !file_ids.contains(file_id) {
+                file_ids.push(file_id.clone());
+            }
+        }
+    }
+    let attachments = state.get_uploaded_files(&tenant, &file_ids).await;
+    let export = ThreadExport::new(&params.thread_id, &thread, attachments);
+
+    let body = match query.format {
+        ExportFormat::Json => serde_json::to_string_pretty(&export).unwrap(),
+        ExportFormat::Jsonl => export.to_jsonl(),
+        ExportFormat::Markdown => export.to_markdown(),
+    };
+    let disposition = format!(
+        "attachment; filename=\"thread-{}.{}\"",
+        params.thread_id,
+        query.format.extension()
+    );
+
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                query.format.content_type().to_string(),
+            ),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+/// Recreate a thread from a JSON or JSONL export under a new id
+#[utoipa::path(
+    post,
+    path = "/v1/threads/import",
+    tag = "threads",
+    summary = "导入线程",
+    description = "以 json 或 jsonl 格式的导出内容创建新线程",
+    params(ImportQuery),
+    request_body(description = "导出内容", content(("application/json"), ("application/x-ndjson"))),
+    responses(
+        (status = 200, description = "新线程", body = Thread),
+        (status = 400, description = "导出内容无效", body = ErrorResponse),
+        (status = 409, description = "附件 ID 已被其他租户使用", body = ErrorResponse)
+    )
+)]
+pub async fn import_thread(
+    State(state): State<AppState>,
+    Extension(Tenant(tenant)): Extension<Tenant>,
+    Query(query): Query<ImportQuery>,
+    body: String,
+) -> Result<Response, ApiError> {
+    let export = ThreadExport::parse(&body).map_err(ApiError::bad_request)?;
+    export.validate().map_err(ApiError::bad_request)?;
+    let model = state
+        .config()
+        .resolve_model(&export.model)
+        .ok_or_else(|| providers::unsupported_model(&export.model))?;
+
+    let thread_id = uuid::Uuid::new_v4().to_string();
+    Logger::info(&format!(
+        "Importing thread {} as {} with {} messages",
+        export.id,
+        thread_id,
+        export.messages.len()
+    ));
+    let attachments = export.attachments.clone();
+    let mut thread = export.into_thread(&tenant, model);
+    thread.normalize(&thread_id);
+    if thread.active_message_id.is_none() {
+        thread.active_message_id = thread.messages.last().map(|node| node.id.clone());
+    }
+
+    if query.reseed {
+        reseed(&state, &thread_id, &mut thread, None).await?;
+    }
+    state.import_uploaded_files(&tenant, attachments).await?;
+
+    let response = Thread {
+        id: thread_id.clone(),
+        object: "thread".to_string(),
+        created_at: thread.created_at,
+        metadata: thread.metadata.clone(),
+    };
+    let request_model = RequestModel(thread.model.clone());
+    state.insert_thread(&thread_id, thread).await?;
+
+    let mut response = Json(response).into_response();
+    if query.reseed {
+        response.extensions_mut().insert(request_model);
+    }
+    Ok(response)
+}
+
+/// Replay the active branch into a new upstream conversation, so the next
+/// turn continues with the imported history. Other branches start fresh
+/// conversations when they are continued. A cancelled replay leaves no usable
+/// conversation and fails.
+pub async fn reseed(
+    state: &AppState,
+    thread_id: &str,
+    thread: &mut ThreadState,
+    cancel: Option<&CancelToken>,
+) -> Result<(), ApiError> {
+    thread.clear_upstream();
+
+    let branch = thread.get_messages();
+    let Some(last_reply) = branch.iter().rposition(|m| m.role == "assistant") else {
+        return Ok(());
+    };
+    let reply_id = branch[last_reply].id.clone();
+    let mut history = vec![PromptMessage {
+        role: "system".to_string(),
+        content: RESEED_INSTRUCTION.to_string(),
+    }];
+    history.extend(branch[..=last_reply].iter().map(|message| PromptMessage {
+        role: message.role.clone(),
+        content: message.content.clone(),
+    }));
+    let prompt = providers::flatten_messages(&history);
+
+    let turn = TurnRequest {
+        thread_id,
+        message: &prompt,
+        instructions: None,
+        file_ids: None,
+        cancel,
+    };
+    let completion = providers::run_thread_turn(state, thread, turn, |_| {}).await?;
+    if completion.incomplete {
+        return Err(ApiError::bad_request("Replaying the history was cancelled"));
+    }
+    thread.record_upstream(&reply_id);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn export() -> ThreadExport {
+        let mut thread = ThreadState {
+            tenant: "default".to_string(),
+            created_at: 0,
+            metadata: None,
+            messages: Vec::new(),
+            active_message_id: None,
+            model: "deepseek".to_string(),
+            deepseek_session_id: Some("s1".to_string()),
+            deepseek_message_id: None,
+            qwen_chat_id: None,
+            qwen_parent_id: None,
+            qwen_account: None,
+            grok_extra_data: None,
+            chatgpt_parent_message_id: None,
+        };
+        thread.add_message("user".to_string(), "hi".to_string(), vec![]);
+        thread.add_reply("hello".to_string(), false);
+        ThreadExport::new("t1", &thread, vec![])
+    }
+
+    #[test]
+    fn test_jsonl_roundtrip() {
+        let export = export();
+        let jsonl = export.to_jsonl();
+        assert_eq!(jsonl.lines().count(), 3);
+
+        let parsed = ThreadExport::parse(&jsonl).unwrap();
+        assert_eq!(parsed.messages.len(), 2);
+        assert_eq!(
+            parsed.messages[1].parent_id,
+            Some(parsed.messages[0].id.clone())
+        );
+        assert_eq!(parsed.session.deepseek_session_id.as_deref(), Some("s1"));
+        assert!(parsed.validate().is_ok());
+
+        let json = serde_json::to_string_pretty(&export).unwrap();
+        assert_eq!(ThreadExport::parse(&json).unwrap().messages.len(), 2);
+        assert!(ThreadExport::parse("# Thread t1").is_err());
+    }
+
+    #[test]
+    fn test_validate_and_markdown() {
+        let mut export = export();
+        assert_eq!(
+            export.to_markdown(),
+            "# Thread t1\n\n- Model: `deepseek`\n- Created: 1970-01-01T00:00:00+00:00\n\
+             \n## User\n\nhi\n\n## Assistant\n\nhello\n"
+        );
+
+        export.messages[1].parent_id = Some("missing".to_string());
+        assert!(export.validate().is_err());
+        export.messages[1].parent_id = None;
+        export.version = EXPORT_VERSION + 1;
+        assert!(export.validate().is_err());
+    }
+}
@@ -1,30 +1,72 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+
 use axum::{
-    extract::{Multipart, State},
-    response::{IntoResponse, Response as AxumResponse},
-    Json,
+    body::Body,
+    extract::{Multipart, Query, Request, State},
+    http::StatusCode,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response as AxumResponse,
+    },
+    Extension, Json,
 };
-use reverse_api::{Logger, QwenClient};
-
-use super::error::ApiError;
-use super::state::AppState;
+use base64::{engine::general_purpose, Engine as _};
+use futures_util::StreamExt;
+use reverse_api::qwen::client::modules::media_downloader::MediaDownloader;
+use reverse_api::qwen::models::{StreamEvent, TaskProgress};
+use reverse_api::{CancelToken, Grok, Logger, QwenClient};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tower::ServiceExt;
+use tower_http::services::ServeFile;
+use tracing::Instrument;
+
+use super::auth::Tenant;
+use super::cancellation::{CancelOnDrop, ResponseGuard};
+use super::error::{ApiError, ErrorResponse};
+use super::images;
+use super::jobs::{Job, JobResult};
+use super::media::{MediaFile, MediaKind};
+use super::message_tree::MessageNode;
+use super::pagination::{self, PageRequest};
+use super::providers::{self, CompletionOptions, PromptMessage, Provider, TurnRequest};
+use super::qwen_pool;
+use super::state::{AppState, ThreadState};
+use super::stats::RequestModel;
 use super::types::*;
 
+#[utoipa::path(
+    post,
+    path = "/v1/threads",
+    tag = "threads",
+    summary = "创建线程",
+    description = "创建新的对话线程，可带初始消息、元数据和模型；省略 model 时使用配置的默认模型",
+    request_body = CreateThreadRequest,
+    responses(
+        (status = 200, description = "新线程", body = Thread),
+        (status = 400, description = "不支持的模型", body = ErrorResponse)
+    )
+)]
 pub async fn create_thread(
     State(state): State<AppState>,
+    Extension(Tenant(tenant)): Extension<Tenant>,
     Json(payload): Json<CreateThreadRequest>,
 ) -> std::result::Result<AxumResponse, ApiError> {
-    let start_time = std::time::Instant::now();
     Logger::info(&format!(
         "Creating new thread with {} initial messages",
         payload.messages.len()
     ));
 
+    let model = payload
+        .model
+        .unwrap_or_else(|| state.config().models.default.clone());
+    let model = state
+        .config()
+        .resolve_model(&model)
+        .ok_or_else(|| providers::unsupported_model(&model))?;
     let (thread_id, thread_state) = state
-        .create_thread(
-            payload.messages,
-            payload.metadata,
-            &payload.model,
-        )
+        .create_thread(&tenant, payload.messages, payload.metadata, &model)
         .await?;
 
     let response = Thread {
@@ -34,18 +76,29 @@ pub async fn create_thread(
         metadata: thread_state.metadata,
     };
 
-    state
-        .record_request("POST", "/v1/threads", 200, start_time.elapsed(), "")
-        .await;
-    Ok(Json(response).into_response())
+    let mut response = Json(response).into_response();
+    response.extensions_mut().insert(RequestModel(model));
+    Ok(response)
 }
 
+#[utoipa::path(
+    get,
+    path = "/v1/threads/{thread_id}",
+    tag = "threads",
+    summary = "获取线程",
+    params(ThreadPath),
+    responses(
+        (status = 200, description = "线程", body = Thread),
+        (status = 404, description = "线程不存在", body = ErrorResponse)
+    )
+)]
 pub async fn get_thread(
     State(state): State<AppState>,
+    Extension(Tenant(tenant)): Extension<Tenant>,
     axum::extract::Path(params): axum::extract::Path<ThreadPath>,
 ) -> std::result::Result<impl IntoResponse, ApiError> {
     let thread_id = params.thread_id;
-    let thread_state = state.get_thread(&thread_id).await?;
+    let thread_state = state.get_thread(&tenant, &thread_id).await?;
 
     let response = Thread {
         id: thread_id,
@@ -57,47 +110,122 @@ pub async fn get_thread(
     Ok(Json(response).into_response())
 }
 
+fn thread_matches(query: &ListThreadsQuery, thread: &ThreadState) -> bool {
+    if query.model.as_ref().is_some_and(|m| *m != thread.model) {
+        return false;
+    }
+    if query.created_after.is_some_and(|t| thread.created_at <= t)
+        || query.created_before.is_some_and(|t| thread.created_at >= t)
+    {
+        return false;
+    }
+    let Some(key) = &query.metadata_key else {
+        return true;
+    };
+    match thread.metadata.as_ref().and_then(|m| m.get(key)) {
+        None => false,
+        Some(value) => query
+            .metadata_value
+            .as_ref()
+            .is_none_or(|expected| match value {
+                serde_json::Value::String(s) => s == expected,
+                other => serde_json::from_str::<serde_json::Value>(expected)
+                    .is_ok_and(|parsed| parsed == *other),
+            }),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/threads",
+    tag = "threads",
+    summary = "列出线程",
+    description = "分页列出当前租户的线程，默认最新的在前，可按模型、元数据和创建时间过滤",
+    params(ListThreadsQuery),
+    responses(
+        (status = 200, description = "一页线程", body = ListThreadsResponse),
+        (status = 400, description = "分页或过滤参数无效", body = ErrorResponse)
+    )
+)]
 pub async fn list_threads(
     State(state): State<AppState>,
+    Extension(Tenant(tenant)): Extension<Tenant>,
+    Query(query): Query<ListThreadsQuery>,
 ) -> std::result::Result<AxumResponse, ApiError> {
-    let threads = state.list_threads().await;
-
-    let data: Vec<Thread> = threads
-        .into_iter()
-        .map(|(id, state)| Thread {
-            id,
-            object: "thread".to_string(),
-            created_at: state.created_at,
-            metadata: state.metadata,
-        })
-        .collect();
+    if query.metadata_value.is_some() && query.metadata_key.is_none() {
+        return Err(ApiError::bad_request(
+            "'metadata_value' requires 'metadata_key'",
+        ));
+    }
+    let threads = state
+        .list_threads(&tenant, |thread| thread_matches(&query, thread))
+        .await;
+    let page = pagination::paginate(
+        threads,
+        |thread| thread.id.as_str(),
+        PageRequest {
+            limit: query.limit,
+            order: query.order,
+            after: query.after.as_deref(),
+            before: query.before.as_deref(),
+        },
+    )?;
 
     let response = ListThreadsResponse {
         object: "list".to_string(),
-        data,
-        has_more: false,
+        first_id: page.data.first().map(|t| t.id.clone()),
+        last_id: page.data.last().map(|t| t.id.clone()),
+        data: page.data,
+        has_more: page.has_more,
     };
 
     Ok(Json(response).into_response())
 }
 
+#[utoipa::path(
+    delete,
+    path = "/v1/threads/{thread_id}",
+    tag = "threads",
+    summary = "删除线程",
+    params(ThreadPath),
+    responses(
+        (status = 200, description = "线程已删除", body = ThreadDeleted),
+        (status = 404, description = "线程不存在", body = ErrorResponse)
+    )
+)]
 pub async fn delete_thread(
     State(state): State<AppState>,
+    Extension(Tenant(tenant)): Extension<Tenant>,
     axum::extract::Path(params): axum::extract::Path<ThreadPath>,
 ) -> std::result::Result<AxumResponse, ApiError> {
     let thread_id = params.thread_id;
-    state.delete_thread(&thread_id).await?;
+    state.delete_thread(&tenant, &thread_id).await?;
 
-    Ok(Json(serde_json::json!({
-        "id": thread_id,
-        "object": "thread.deleted",
-        "deleted": true
-    }))
+    Ok(Json(ThreadDeleted {
+        id: thread_id,
+        object: "thread.deleted".to_string(),
+        deleted: true,
+    })
     .into_response())
 }
 
+#[utoipa::path(
+    post,
+    path = "/v1/threads/{thread_id}/messages",
+    tag = "threads",
+    summary = "添加消息",
+    description = "向线程的当前分支追加一条消息，不会生成回答",
+    params(ThreadPath),
+    request_body = AddMessageRequest,
+    responses(
+        (status = 200, description = "新消息", body = Message),
+        (status = 400, description = "消息内容为空", body = ErrorResponse),
+        (status = 404, description = "线程不存在", body = ErrorResponse)
+    )
+)]
 pub async fn add_message(
     State(state): State<AppState>,
+    Extension(Tenant(tenant)): Extension<Tenant>,
     axum::extract::Path(params): axum::extract::Path<ThreadPath>,
     Json(payload): Json<AddMessageRequest>,
 ) -> std::result::Result<AxumResponse, ApiError> {
@@ -106,63 +234,237 @@ pub async fn add_message(
         return Err(ApiError::bad_request("Message content cannot be empty"));
     }
 
-    state
-        .add_message_to_thread(&thread_id, payload.role.clone(), payload.content.clone())
+    let node = state
+        .add_message_to_thread(
+            &tenant,
+            &thread_id,
+            payload.role,
+            payload.content,
+            payload.files.unwrap_or_default(),
+        )
         .await?;
 
-    let message_id = uuid::Uuid::new_v4().to_string();
-    let created_at = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
+    Ok(Json(thread_message(&thread_id, 0, &node)).into_response())
+}
 
-    let response = Message {
-        id: message_id,
+pub fn thread_message(thread_id: &str, thread_created_at: u64, node: &MessageNode) -> Message {
+    Message {
+        id: node.id.clone(),
         object: "thread.message".to_string(),
-        created_at,
-        thread_id,
-        role: payload.role,
+        created_at: node.created_at.unwrap_or(thread_created_at),
+        thread_id: thread_id.to_string(),
+        parent_id: node.parent_id.clone(),
+        status: node.incomplete.then(|| "incomplete".to_string()),
+        role: node.role.clone(),
         content: vec![ContentPart {
             content_type: "text".to_string(),
             text: TextContent {
-                value: payload.content,
+                value: node.content.clone(),
                 annotations: vec![],
             },
         }],
-    };
-
-    Ok(Json(response).into_response())
+    }
 }
 
+#[utoipa::path(
+    get,
+    path = "/v1/threads/{thread_id}/messages",
+    tag = "threads",
+    summary = "列出消息",
+    description = "分页列出线程当前分支的消息，默认按时间正序，每条消息带有 parent_id",
+    params(ThreadPath, ListMessagesQuery),
+    responses(
+        (status = 200, description = "一页消息", body = ListMessagesResponse),
+        (status = 404, description = "线程不存在", body = ErrorResponse)
+    )
+)]
 pub async fn list_messages(
     State(state): State<AppState>,
+    Extension(Tenant(tenant)): Extension<Tenant>,
     axum::extract::Path(params): axum::extract::Path<ThreadPath>,
+    Query(query): Query<ListMessagesQuery>,
 ) -> std::result::Result<AxumResponse, ApiError> {
     let thread_id = params.thread_id;
-    let thread_state = state.get_thread(&thread_id).await?;
+    let thread_state = state.get_thread(&tenant, &thread_id).await?;
 
     let data: Vec<Message> = thread_state
         .get_messages()
+        .into_iter()
+        .map(|node| thread_message(&thread_id, thread_state.created_at, node))
+        .collect();
+    let page = pagination::paginate(
+        data,
+        |message| message.id.as_str(),
+        PageRequest {
+            limit: query.limit,
+            order: query.order,
+            after: query.after.as_deref(),
+            before: query.before.as_deref(),
+        },
+    )?;
+
+    let response = ListMessagesResponse {
+        object: "list".to_string(),
+        first_id: page.data.first().map(|m| m.id.clone()),
+        last_id: page.data.last().map(|m| m.id.clone()),
+        data: page.data,
+        has_more: page.has_more,
+    };
+
+    Ok(Json(response).into_response())
+}
+
+/// Replace a user message with a new version on its own branch and answer it
+#[utoipa::path(
+    post,
+    path = "/v1/threads/{thread_id}/messages/{message_id}/edit",
+    tag = "threads",
+    summary = "编辑消息",
+    description = "在同一父消息下创建用户消息的新版本并生成回答；stream 为 true 时返回与 /v1/responses 相同的 SSE 事件流",
+    params(MessagePath),
+    request_body = EditMessageRequest,
+    responses(
+        (status = 200, description = "生成的响应", content((Response = "application/json"), ("text/event-stream"))),
+        (status = 400, description = "内容为空或不是用户消息", body = ErrorResponse),
+        (status = 404, description = "线程或消息不存在", body = ErrorResponse)
+    )
+)]
+pub async fn edit_message(
+    State(state): State<AppState>,
+    Extension(Tenant(tenant)): Extension<Tenant>,
+    axum::extract::Path(params): axum::extract::Path<MessagePath>,
+    Json(payload): Json<EditMessageRequest>,
+) -> std::result::Result<AxumResponse, ApiError> {
+    if payload.content.trim().is_empty() {
+        return Err(ApiError::bad_request("Message content cannot be empty"));
+    }
+    // Keep the edit even if the provider then fails, like add_message does
+    let file_ids = payload.file_ids.clone().unwrap_or_default();
+    let thread_state = state
+        .update_thread(&tenant, &params.thread_id, |thread| {
+            thread.edit_message(&params.message_id, payload.content, file_ids)?;
+            Ok(thread.clone())
+        })
+        .await?;
+
+    let request = CreateResponseRequest {
+        thread_id: params.thread_id,
+        model: thread_state.model.clone(),
+        instructions: payload.instructions,
+        stream: payload.stream,
+        file_ids: payload.file_ids,
+    };
+    respond(state, thread_state, request).await
+}
+
+/// Answer the user message behind an assistant message again, as a sibling
+/// of that message
+#[utoipa::path(
+    post,
+    path = "/v1/threads/{thread_id}/messages/{message_id}/regenerate",
+    tag = "threads",
+    summary = "重新生成回答",
+    description = "重新回答助手消息之前的用户消息，新回答作为原回答的兄弟消息；请求体可省略",
+    params(MessagePath),
+    request_body = Option<RegenerateMessageRequest>,
+    responses(
+        (status = 200, description = "生成的响应", content((Response = "application/json"), ("text/event-stream"))),
+        (status = 400, description = "不是助手消息", body = ErrorResponse),
+        (status = 404, description = "线程或消息不存在", body = ErrorResponse)
+    )
+)]
+pub async fn regenerate_message(
+    State(state): State<AppState>,
+    Extension(Tenant(tenant)): Extension<Tenant>,
+    axum::extract::Path(params): axum::extract::Path<MessagePath>,
+    payload: Option<Json<RegenerateMessageRequest>>,
+) -> std::result::Result<AxumResponse, ApiError> {
+    let Json(payload) = payload.unwrap_or_default();
+    let mut thread_state = state.get_thread(&tenant, &params.thread_id).await?;
+    thread_state.rewind_reply(&params.message_id)?;
+
+    let request = CreateResponseRequest {
+        thread_id: params.thread_id,
+        model: thread_state.model.clone(),
+        instructions: payload.instructions,
+        stream: payload.stream,
+        file_ids: payload.file_ids,
+    };
+    respond(state, thread_state, request).await
+}
+
+/// Every version of a message, i.e. the messages sharing its parent
+#[utoipa::path(
+    get,
+    path = "/v1/threads/{thread_id}/messages/{message_id}/branches",
+    tag = "threads",
+    summary = "列出消息版本",
+    description = "列出与该消息同一父消息的所有版本，active_id 为当前分支上的版本",
+    params(MessagePath),
+    responses(
+        (status = 200, description = "消息的所有版本", body = ListBranchesResponse),
+        (status = 404, description = "线程或消息不存在", body = ErrorResponse)
+    )
+)]
+pub async fn list_branches(
+    State(state): State<AppState>,
+    Extension(Tenant(tenant)): Extension<Tenant>,
+    axum::extract::Path(params): axum::extract::Path<MessagePath>,
+) -> std::result::Result<AxumResponse, ApiError> {
+    let thread_state = state.get_thread(&tenant, &params.thread_id).await?;
+    let siblings = thread_state.siblings(&params.message_id)?;
+    let active = thread_state.get_messages();
+    let active_id = siblings
         .iter()
-        .enumerate()
-        .map(|(idx, msg)| Message {
-            id: format!("msg_{}_{}", thread_id, idx),
-            object: "thread.message".to_string(),
-            created_at: msg.created_at.unwrap_or(thread_state.created_at),
-            thread_id: thread_id.clone(),
-            role: msg.role.clone(),
-            content: vec![ContentPart {
-                content_type: "text".to_string(),
-                text: TextContent {
-                    value: msg.content.clone(),
-                    annotations: vec![],
-                },
-            }],
+        .find(|sibling| active.iter().any(|node| node.id == sibling.id))
+        .map(|sibling| sibling.id.clone());
+
+    let response = ListBranchesResponse {
+        object: "list".to_string(),
+        data: siblings
+            .into_iter()
+            .map(|node| thread_message(&params.thread_id, thread_state.created_at, node))
+            .collect(),
+        active_id,
+    };
+
+    Ok(Json(response).into_response())
+}
+
+/// Continue the thread from the newest branch through a message, returning
+/// the messages of that branch
+#[utoipa::path(
+    post,
+    path = "/v1/threads/{thread_id}/messages/{message_id}/activate",
+    tag = "threads",
+    summary = "切换分支",
+    description = "切换到经过该消息的最新分支，返回新的当前分支",
+    params(MessagePath),
+    responses(
+        (status = 200, description = "当前分支的消息", body = ListMessagesResponse),
+        (status = 404, description = "线程或消息不存在", body = ErrorResponse)
+    )
+)]
+pub async fn activate_message(
+    State(state): State<AppState>,
+    Extension(Tenant(tenant)): Extension<Tenant>,
+    axum::extract::Path(params): axum::extract::Path<MessagePath>,
+) -> std::result::Result<AxumResponse, ApiError> {
+    let data: Vec<Message> = state
+        .update_thread(&tenant, &params.thread_id, |thread| {
+            thread.activate(&params.message_id)?;
+            Ok(thread
+                .get_messages()
+                .into_iter()
+                .map(|node| thread_message(&params.thread_id, thread.created_at, node))
+                .collect())
         })
-        .collect();
+        .await?;
 
     let response = ListMessagesResponse {
         object: "list".to_string(),
+        first_id: data.first().map(|m| m.id.clone()),
+        last_id: data.last().map(|m| m.id.clone()),
         data,
         has_more: false,
     };
@@ -170,229 +472,640 @@ pub async fn list_messages(
     Ok(Json(response).into_response())
 }
 
-
+#[utoipa::path(
+    post,
+    path = "/v1/config/qwen",
+    tag = "config",
+    summary = "添加 Qwen 账号",
+    description = "向当前租户的账号池追加 token 或邮箱/密码账号，相同 token/邮箱会替换原账号",
+    request_body = ConfigureQwenRequest,
+    responses(
+        (status = 200, description = "已登记的账号", body = ConfigureResponse),
+        (status = 400, description = "没有提供账号", body = ErrorResponse)
+    )
+)]
 pub async fn configure_qwen(
     State(state): State<AppState>,
-    Json(payload): Json<serde_json::Value>,
+    Extension(Tenant(tenant)): Extension<Tenant>,
+    Json(payload): Json<ConfigureQwenRequest>,
 ) -> std::result::Result<AxumResponse, ApiError> {
-    let token = payload["token"]
-        .as_str()
-        .ok_or_else(|| ApiError::bad_request("Missing 'token' field"))?;
-
-    state.set_qwen_token(token.to_string()).await;
+    // Accept a single token, a list of tokens and/or email/password accounts
+    let mut accounts = Vec::new();
+    for token in payload.token.iter().chain(&payload.tokens) {
+        let client = QwenClient::with_token(token.clone()).map_err(|e| {
+            ApiError::internal_error(format!("Could not create Qwen client: {}", e))
+        })?;
+        accounts.push((qwen_pool::account_id(token), client));
+    }
+    for account in payload.accounts {
+        let (Some(email), Some(password)) = (account.email, account.password) else {
+            return Err(ApiError::bad_request(
+                "Each entry in 'accounts' needs 'email' and 'password'",
+            ));
+        };
+        let id = qwen_pool::account_id(&email);
+        let client = QwenClient::new(email, password).map_err(|e| {
+            ApiError::internal_error(format!("Could not create Qwen client: {}", e))
+        })?;
+        accounts.push((id, client));
+    }
+    if accounts.is_empty() {
+        return Err(ApiError::bad_request(
+            "Missing 'token', 'tokens' or 'accounts' field",
+        ));
+    }
 
     // Try to fetch and cache models
-    match QwenClient::with_token(token.to_string()) {
-        Ok(client) => match client.get_models().await {
-            Ok(models) => {
-                Logger::info(&format!("✅ Fetched {} Qwen models", models.len()));
-                state.set_qwen_models(models).await;
-            }
-            Err(e) => {
-                Logger::info(&format!("⚠️  Could not fetch Qwen models: {}", e));
-            }
-        },
+    match accounts[0].1.get_models().await {
+        Ok(models) => {
+            Logger::info(&format!("✅ Fetched {} Qwen models", models.len()));
+            state.set_qwen_models(&tenant, models).await;
+        }
         Err(e) => {
-            Logger::info(&format!("⚠️  Could not create Qwen client: {}", e));
+            Logger::info(&format!("⚠️  Could not fetch Qwen models: {}", e));
         }
     }
 
-    Ok(Json(serde_json::json!({
-        "status": "success",
-        "message": "Qwen token configured"
-    }))
+    let mut ids = Vec::new();
+    for (id, client) in accounts {
+        state.add_qwen_account(&tenant, id.clone(), client).await;
+        ids.push(id);
+    }
+    Logger::info(&format!("✅ Registered {} Qwen accounts", ids.len()));
+
+    Ok(Json(ConfigureResponse {
+        status: "success".to_string(),
+        message: "Qwen token configured".to_string(),
+        accounts: Some(ids),
+    })
     .into_response())
 }
 
-pub async fn create_response(
+#[utoipa::path(
+    get,
+    path = "/v1/config/qwen",
+    tag = "config",
+    summary = "查看 Qwen 账号池",
+    responses(
+        (status = 200, description = "账号及其冷却状态", body = ListAccountsResponse)
+    )
+)]
+pub async fn list_qwen_accounts(
     State(state): State<AppState>,
-    Json(payload): Json<CreateResponseRequest>,
+    Extension(Tenant(tenant)): Extension<Tenant>,
 ) -> std::result::Result<AxumResponse, ApiError> {
-    let start_time = std::time::Instant::now();
-    let thread_id = payload.thread_id.clone();
+    Ok(Json(ListAccountsResponse {
+        object: "list".to_string(),
+        data: state.qwen_accounts(&tenant).await,
+    })
+    .into_response())
+}
 
-    Logger::info(&format!("Creating response for thread: {}", thread_id));
+#[utoipa::path(
+    post,
+    path = "/v1/config/deepseek",
+    tag = "config",
+    summary = "配置 DeepSeek token",
+    request_body = ConfigureDeepSeekRequest,
+    responses(
+        (status = 200, description = "token 已保存", body = ConfigureResponse),
+        (status = 400, description = "缺少 token", body = ErrorResponse)
+    )
+)]
+pub async fn configure_deepseek(
+    State(state): State<AppState>,
+    Extension(Tenant(tenant)): Extension<Tenant>,
+    Json(payload): Json<ConfigureDeepSeekRequest>,
+) -> std::result::Result<AxumResponse, ApiError> {
+    let token = payload
+        .token
+        .ok_or_else(|| ApiError::bad_request("Missing 'token' field"))?;
 
-    let mut thread_state = state.get_thread(&thread_id).await?;
+    state.set_deepseek_token(&tenant, token).await?;
+    Logger::info("✅ DeepSeek client configured");
 
-    let last_user_message = thread_state
-        .get_messages()
-        .iter()
-        .rev()
-        .find(|m| m.role == "user")
-        .ok_or_else(|| ApiError::bad_request("No user message found in thread"))?;
+    Ok(Json(ConfigureResponse {
+        status: "success".to_string(),
+        message: "DeepSeek token configured".to_string(),
+        accounts: None,
+    })
+    .into_response())
+}
 
-    let message_content = last_user_message.content.clone();
-    if message_content.trim().is_empty() {
-        return Err(ApiError::bad_request("Last user message content is empty"));
+#[utoipa::path(
+    post,
+    path = "/v1/chat/completions",
+    tag = "compat",
+    summary = "OpenAI 对话补全",
+    description = "无状态接口，根据 model 前缀分发到 Qwen、DeepSeek、Grok 或 ChatGPT；stream 为 true 时以 SSE 推送 chat.completion.chunk，最后发送 [DONE]。image_url 只支持 ChatGPT 模型且最多一张；temperature、top_p 和 max_tokens 会被忽略",
+    request_body = ChatCompletionRequest,
+    responses(
+        (status = 200, description = "补全结果", content(
+            (ChatCompletion = "application/json"),
+            (ChatCompletionChunk = "text/event-stream")
+        )),
+        (status = 400, description = "消息为空、模型不支持，或图片多于一张或模型不支持图片", body = ErrorResponse)
+    )
+)]
+pub async fn chat_completions(
+    State(state): State<AppState>,
+    Extension(Tenant(tenant)): Extension<Tenant>,
+    Json(mut payload): Json<ChatCompletionRequest>,
+) -> std::result::Result<AxumResponse, ApiError> {
+    if payload.messages.is_empty() {
+        return Err(ApiError::bad_request("'messages' must not be empty"));
     }
+    payload.model = state
+        .config()
+        .resolve_model(&payload.model)
+        .ok_or_else(|| providers::unsupported_model(&payload.model))?;
 
+    let messages: Vec<PromptMessage> = payload
+        .messages
+        .iter()
+        .map(|m| PromptMessage {
+            role: m.role.clone(),
+            content: m.content.text(),
+        })
+        .collect();
+    let prompt = providers::flatten_messages(&messages);
+    if prompt.trim().is_empty() {
+        return Err(ApiError::bad_request("Message content cannot be empty"));
+    }
 
-    let model = thread_state.model.clone();
+    let images = payload
+        .messages
+        .iter()
+        .flat_map(|m| m.content.image_urls())
+        .collect();
+    let image = providers::completion_image(&payload.model, images)?;
 
-    Logger::info(&format!("Using model: {}", model));
+    Logger::info(&format!(
+        "Chat completion with model {} ({} messages)",
+        payload.model,
+        payload.messages.len()
+    ));
+
+    let id = format!("chatcmpl-{}", uuid::Uuid::new_v4().simple());
+    let created = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let request_model = RequestModel(payload.model.clone());
+
+    if payload.stream {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let model = payload.model.clone();
+        let guard = CancelOnDrop::default();
+        let cancel = guard.token();
+
+        tokio::spawn(
+            async move {
+                let role = ChatDelta {
+                    role: Some("assistant".to_string()),
+                    ..Default::default()
+                };
+                let _ = tx.send(chat_chunk(&id, created, &model, role, None));
+
+                let delta_tx = tx.clone();
+                let (delta_id, delta_model) = (id.clone(), model.clone());
+                let result = providers::complete(
+                    &state,
+                    &tenant,
+                    &model,
+                    &prompt,
+                    CompletionOptions {
+                        image: image.as_deref(),
+                        cancel: Some(&cancel),
+                        ..Default::default()
+                    },
+                    move |event| {
+                        let delta = match event {
+                            StreamEvent::Answer(text) => ChatDelta {
+                                content: Some(text),
+                                ..Default::default()
+                            },
+                            StreamEvent::Thinking(text) => ChatDelta {
+                                reasoning_content: Some(text),
+                                ..Default::default()
+                            },
+                            StreamEvent::WebSearch(_) => return,
+                        };
+                        let _ = delta_tx.send(chat_chunk(
+                            &delta_id,
+                            created,
+                            &delta_model,
+                            delta,
+                            None,
+                        ));
+                    },
+                )
+                .await;
+
+                match result {
+                    Ok(_) => {
+                        let _ = tx.send(chat_chunk(
+                            &id,
+                            created,
+                            &model,
+                            ChatDelta::default(),
+                            Some("stop"),
+                        ));
+                        let _ = tx.send(Event::default().data("[DONE]"));
+                    }
+                    Err(e) => {
+                        Logger::error(&format!("Chat completion stream failed: {}", e.message));
+                        let _ = tx.send(
+                            Event::default()
+                                .json_data(serde_json::json!({
+                                    "error": {
+                                        "message": e.message,
+                                        "type": "server_error"
+                                    }
+                                }))
+                                .unwrap(),
+                        );
+                    }
+                }
+            }
+            .instrument(tracing::Span::current()),
+        );
 
-    // Determine which client to use based on model name
-    let answer = if model.starts_with("qwen") {
-        Logger::info("Starting Qwen conversation");
+        let mut response = sse_response_with_guard(rx, guard);
+        response.extensions_mut().insert(request_model);
+        return Ok(response);
+    }
 
-        // Get cached Qwen client from state (reuse same instance for continuous conversation)
-        let client = state.get_qwen_client().await.ok_or_else(|| {
-            ApiError::bad_request(
-                "Qwen token not configured. Please configure it via POST /v1/config/qwen",
-            )
-        })?;
-        // Check for special instructions
-        let use_search = payload
-            .instructions
-            .as_ref()
-            .map(|s| s.contains("search"))
-            .unwrap_or(false);
-        let use_thinking = payload
-            .instructions
-            .as_ref()
-            .map(|s| s.contains("thinking"))
-            .unwrap_or(false);
-
-        // Build extra_data for continuous conversation
-        let extra_data = if let (Some(chat_id), Some(parent_id)) =
-            (&thread_state.qwen_chat_id, &thread_state.qwen_parent_id)
-        {
-            Some(reverse_api::qwen::models::ExtraData {
-                chat_id: chat_id.clone(),
-                model_id: model.clone(),
-                parent_id: Some(parent_id.clone()),
-            })
-        } else {
-            None
-        };
+    let completion = providers::complete(
+        &state,
+        &tenant,
+        &payload.model,
+        &prompt,
+        CompletionOptions {
+            image: image.as_deref(),
+            ..Default::default()
+        },
+        |_| {},
+    )
+    .await?;
+
+    let response = ChatCompletion {
+        id,
+        object: "chat.completion".to_string(),
+        created,
+        model: payload.model,
+        usage: Usage::estimate(&prompt, &completion.content),
+        choices: vec![ChatChoice {
+            index: 0,
+            message: ChatCompletionMessage {
+                role: "assistant".to_string(),
+                content: completion.content,
+                reasoning_content: completion.thinking,
+            },
+            finish_reason: "stop".to_string(),
+        }],
+    };
 
-        let result = if let Some(file_ids) = &payload.file_ids {
-            if !file_ids.is_empty() {
-                Logger::info(&format!("Using {} files with Qwen", file_ids.len()));
+    let mut response = Json(response).into_response();
+    response.extensions_mut().insert(request_model);
+    Ok(response)
+}
 
-                // Get uploaded files from state
-                let files = state.get_uploaded_files(file_ids).await;
+fn chat_chunk(
+    id: &str,
+    created: u64,
+    model: &str,
+    delta: ChatDelta,
+    finish_reason: Option<&str>,
+) -> Event {
+    let chunk = ChatCompletionChunk {
+        id: id.to_string(),
+        object: "chat.completion.chunk".to_string(),
+        created,
+        model: model.to_string(),
+        choices: vec![ChatChunkChoice {
+            index: 0,
+            delta,
+            finish_reason: finish_reason.map(|s| s.to_string()),
+        }],
+    };
+    Event::default().json_data(chunk).unwrap()
+}
 
-                if files.is_empty() {
-                    return Err(ApiError::bad_request(
-                        "No valid files found for provided file_ids",
-                    ));
-                }
+pub fn sse_response(rx: mpsc::UnboundedReceiver<Event>) -> AxumResponse {
+    sse_response_with_guard(rx, ())
+}
 
-                // Execute conversation with files
-                client
-                    .start_convo_with_files(
-                        &message_content,
-                        files,
-                        None, // Auto-select model
-                        extra_data.as_ref(),
-                    )
-                    .await
-                    .map_err(|e| {
-                        ApiError::internal_error(format!("Qwen multimodal error: {}", e))
-                    })?
-            } else if use_search {
-                Logger::info("Using Qwen with search");
-                client
-                    .start_convo_with_search(&message_content, Some(&model), extra_data.as_ref())
-                    .await
-                    .map_err(|e| ApiError::internal_error(format!("Qwen search error: {}", e)))?
-            } else if use_thinking {
-                Logger::info("Using Qwen with thinking");
-                client
-                    .start_convo_with_thinking(
-                        &message_content,
-                        Some(&model),
-                        extra_data.as_ref(),
-                        None, // Use default thinking budget
-                    )
-                    .await
-                    .map_err(|e| ApiError::internal_error(format!("Qwen thinking error: {}", e)))?
-            } else {
-                // Normal text conversation
-                Logger::info(&format!("Calling start_convo with model: {}", model));
-                client
-                    .start_convo(&message_content, Some(&model), extra_data.as_ref())
-                    .await
-                    .map_err(|e| ApiError::internal_error(format!("Qwen error: {}", e)))?
-            }
-        } else if use_search {
-            Logger::info("Using Qwen with search");
-            client
-                .start_convo_with_search(&message_content, Some(&model), extra_data.as_ref())
-                .await
-                .map_err(|e| ApiError::internal_error(format!("Qwen search error: {}", e)))?
-        } else if use_thinking {
-            Logger::info("Using Qwen with thinking");
-            client
-                .start_convo_with_thinking(
-                    &message_content,
-                    Some(&model),
-                    extra_data.as_ref(),
-                    None, // Use default thinking budget
-                )
-                .await
-                .map_err(|e| ApiError::internal_error(format!("Qwen thinking error: {}", e)))?
-        } else {
-            // Normal text conversation with context
-            Logger::info(&format!("Calling start_convo, model: {}", model));
-            client
-                .start_convo(&message_content, Some(&model), extra_data.as_ref())
-                .await
-                .map_err(|e| ApiError::internal_error(format!("Qwen error: {}", e)))?
-        };
+/// Like `sse_response`, keeping `guard` alive until the client stops reading
+pub fn sse_response_with_guard<G: Send + Sync + 'static>(
+    rx: mpsc::UnboundedReceiver<Event>,
+    guard: G,
+) -> AxumResponse {
+    let stream = UnboundedReceiverStream::new(rx).map(move |event| {
+        let _ = &guard;
+        Ok::<_, Infallible>(event)
+    });
+    Sse::new(stream)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
 
-        // Update thread state with Qwen session info for continuous conversation
-        if let Some(chat_id) = &result.chat_id {
-            thread_state.qwen_chat_id = Some(chat_id.clone());
-            thread_state.qwen_parent_id = Some(result.response_id.clone());
-        }
+#[utoipa::path(
+    post,
+    path = "/v1/responses",
+    tag = "responses",
+    summary = "生成响应",
+    description = "回答线程中最后一条用户消息；stream 为 true 时依次推送 response.created、response.output_text.delta 等事件，最后为 response.completed 或 response.cancelled",
+    request_body = CreateResponseRequest,
+    responses(
+        (status = 200, description = "生成的响应", content((Response = "application/json"), ("text/event-stream"))),
+        (status = 400, description = "线程中没有用户消息", body = ErrorResponse),
+        (status = 404, description = "线程不存在", body = ErrorResponse)
+    )
+)]
+pub async fn create_response(
+    State(state): State<AppState>,
+    Extension(Tenant(tenant)): Extension<Tenant>,
+    Json(payload): Json<CreateResponseRequest>,
+) -> std::result::Result<AxumResponse, ApiError> {
+    let thread_state = state.get_thread(&tenant, &payload.thread_id).await?;
+    respond(state, thread_state, payload).await
+}
 
-        result.content
-    } else {
-        return Err(ApiError::bad_request(format!(
-            "Unsupported model: {}. Use 'qwen-*'",
-            model
-        )));
-    };
+/// A response registered for the last user message of a thread
+pub struct PendingResponse {
+    pub message: String,
+    /// Cancels the response once dropped
+    pub guard: ResponseGuard,
+    /// The response while it is in progress
+    pub response: Response,
+}
 
-    thread_state.add_message("assistant".to_string(), answer.clone());
+/// Register a response to the last user message on the thread's active branch
+pub fn begin_response(
+    state: &AppState,
+    thread_state: &ThreadState,
+    payload: &mut CreateResponseRequest,
+) -> std::result::Result<PendingResponse, ApiError> {
+    let thread_id = payload.thread_id.clone();
+
+    Logger::info(&format!("Creating response for thread: {}", thread_id));
 
-    let model = thread_state.model.clone();
-    state.update_thread(&thread_id, thread_state).await?;
+    let (message_content, message_files) = thread_state
+        .get_messages()
+        .into_iter()
+        .rev()
+        .find(|m| m.role == "user")
+        .map(|m| (m.content.clone(), m.file_ids.clone()))
+        .ok_or_else(|| ApiError::bad_request("No user message found in thread"))?;
+    // Files attached to the message are sent unless the request names its own
+    if payload.file_ids.is_none() && !message_files.is_empty() {
+        payload.file_ids = Some(message_files);
+    }
+    if message_content.trim().is_empty() {
+        return Err(ApiError::bad_request("Last user message content is empty"));
+    }
 
-    let response_id = uuid::Uuid::new_v4().to_string();
     let created_at = std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
         .unwrap()
         .as_secs();
-
     let response = Response {
-        id: response_id,
+        id: uuid::Uuid::new_v4().to_string(),
         object: "thread.response".to_string(),
         created_at,
         thread_id,
-        status: "completed".to_string(),
-        model,
-        response: Some(answer),
+        status: "in_progress".to_string(),
+        model: thread_state.model.clone(),
+        message_id: None,
+        response: None,
     };
+    // Lives as long as the client waits for the answer; dropping it when the
+    // client goes away cancels the upstream request
+    let guard = state
+        .responses()
+        .register(&thread_state.tenant, response.clone());
+    Ok(PendingResponse {
+        message: message_content,
+        guard,
+        response,
+    })
+}
 
-    state
-        .record_request("POST", "/v1/responses", 200, start_time.elapsed(), "")
-        .await;
+/// Answer the last user message on the thread's active branch
+async fn respond(
+    state: AppState,
+    mut thread_state: ThreadState,
+    mut payload: CreateResponseRequest,
+) -> std::result::Result<AxumResponse, ApiError> {
+    let PendingResponse {
+        message: message_content,
+        guard,
+        response,
+    } = begin_response(&state, &thread_state, &mut payload)?;
+
+    let request_model = RequestModel(thread_state.model.clone());
+    if payload.stream {
+        let mut response = stream_response(
+            state,
+            payload,
+            thread_state,
+            message_content,
+            guard,
+            response,
+        );
+        response.extensions_mut().insert(request_model);
+        return Ok(response);
+    }
+
+    // The turn runs in its own task so a partial answer is still recorded
+    // when the client disconnects
+    let cancel = guard.token();
+    let turn = tokio::spawn(
+        async move {
+            let turn = TurnRequest {
+                thread_id: &payload.thread_id,
+                message: &message_content,
+                instructions: payload.instructions.as_deref(),
+                file_ids: payload.file_ids.as_deref(),
+                cancel: Some(&cancel),
+            };
+            let completion =
+                providers::run_thread_turn(&state, &mut thread_state, turn, |_| {}).await?;
+            let reply = thread_state.add_reply(completion.content.clone(), completion.incomplete);
+            let tenant = thread_state.tenant.clone();
+            state
+                .save_turn(&tenant, &payload.thread_id, thread_state)
+                .await?;
+            Ok::<_, ApiError>((reply, completion))
+        }
+        .instrument(tracing::Span::current()),
+    );
+    let (reply, completion) = turn
+        .await
+        .map_err(|e| ApiError::internal_error(format!("Response task failed: {}", e)))??;
+
+    let status = if completion.incomplete {
+        "cancelled"
+    } else {
+        "completed"
+    };
+    let response = response.finish(status, reply.id, completion.content);
+
+    Ok((Extension(request_model), Json(response)).into_response())
+}
+
+/// Stream a thread response as server-sent events. The upstream call runs in
+/// its own task so the assistant message is still appended to the thread once
+/// the provider finishes or the response is cancelled, even if the client has
+/// gone away.
+fn stream_response(
+    state: AppState,
+    payload: CreateResponseRequest,
+    thread_state: ThreadState,
+    message_content: String,
+    guard: ResponseGuard,
+    response: Response,
+) -> AxumResponse {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let cancel = guard.token();
+
+    tokio::spawn(
+        stream_turn(
+            state,
+            payload,
+            thread_state,
+            message_content,
+            cancel,
+            response,
+            move |name, data| {
+                let _ = tx.send(Event::default().event(name).json_data(data).unwrap());
+            },
+        )
+        .instrument(tracing::Span::current()),
+    );
+
+    sse_response_with_guard(rx, guard)
+}
+
+/// Answer a thread turn, reporting it through `emit` as the named events of a
+/// streamed response, and append the answer to the thread
+pub async fn stream_turn<E>(
+    state: AppState,
+    payload: CreateResponseRequest,
+    mut thread_state: ThreadState,
+    message_content: String,
+    cancel: CancelToken,
+    response: Response,
+    emit: E,
+) where
+    E: Fn(&str, serde_json::Value) + Send + Sync,
+{
+    let thread_id = payload.thread_id.clone();
+    let response_id = response.id.clone();
+    emit("response.created", serde_json::to_value(&response).unwrap());
+
+    let turn = TurnRequest {
+        thread_id: &thread_id,
+        message: &message_content,
+        instructions: payload.instructions.as_deref(),
+        file_ids: payload.file_ids.as_deref(),
+        cancel: Some(&cancel),
+    };
+    let result = providers::run_thread_turn(&state, &mut thread_state, turn, |event| {
+        let (name, data) = match event {
+            StreamEvent::Answer(delta) => (
+                "response.output_text.delta",
+                serde_json::json!({ "response_id": response_id, "delta": delta }),
+            ),
+            StreamEvent::Thinking(delta) => (
+                "response.thinking.delta",
+                serde_json::json!({ "response_id": response_id, "delta": delta }),
+            ),
+            StreamEvent::WebSearch(results) => (
+                "response.web_search.results",
+                serde_json::json!({ "response_id": response_id, "results": results }),
+            ),
+        };
+        emit(name, data);
+    })
+    .await;
+
+    match result {
+        Ok(completion) => {
+            let reply = thread_state.add_reply(completion.content.clone(), completion.incomplete);
+            let tenant = thread_state.tenant.clone();
+            if let Err(e) = state.save_turn(&tenant, &thread_id, thread_state).await {
+                Logger::error(&format!(
+                    "Failed to save thread {}: {}",
+                    thread_id, e.message
+                ));
+            }
+            let (event, status) = if completion.incomplete {
+                ("response.cancelled", "cancelled")
+            } else {
+                ("response.completed", "completed")
+            };
+            emit(
+                event,
+                serde_json::to_value(response.finish(status, reply.id, completion.content))
+                    .unwrap(),
+            );
+        }
+        Err(e) => {
+            Logger::error(&format!("Streaming response failed: {}", e.message));
+            emit(
+                "error",
+                serde_json::json!({
+                    "status": "error",
+                    "detail": e.message
+                }),
+            );
+        }
+    }
+}
+
+/// Stop a response that is still being generated. The partial answer is kept
+/// on the thread as an incomplete assistant message.
+#[utoipa::path(
+    post,
+    path = "/v1/responses/{response_id}/cancel",
+    tag = "responses",
+    summary = "取消响应",
+    description = "取消生成中的响应，已生成的部分作为 status 为 incomplete 的助手消息保存；客户端断开连接时同样会取消",
+    params(ResponsePath),
+    responses(
+        (status = 200, description = "已取消的响应", body = Response),
+        (status = 404, description = "响应不存在或已结束", body = ErrorResponse)
+    )
+)]
+pub async fn cancel_response(
+    State(state): State<AppState>,
+    Extension(Tenant(tenant)): Extension<Tenant>,
+    axum::extract::Path(params): axum::extract::Path<ResponsePath>,
+) -> std::result::Result<AxumResponse, ApiError> {
+    let response = state.responses().cancel(&tenant, &params.response_id)?;
     Ok(Json(response).into_response())
 }
 
+#[utoipa::path(
+    post,
+    path = "/v1/files/upload",
+    tag = "media",
+    summary = "上传文件",
+    description = "上传图片、视频、音频或文档到 Qwen（需先配置 Qwen 账号），返回的 id 可用作 file_ids",
+    request_body(description = "file 字段为要上传的文件", content(("multipart/form-data"))),
+    responses(
+        (status = 200, description = "已上传的文件", body = FileUploadResponse),
+        (status = 429, description = "没有可用的 Qwen 账号", body = ErrorResponse)
+    )
+)]
 pub async fn upload_file_for_qwen(
     State(state): State<AppState>,
+    Extension(Tenant(tenant)): Extension<Tenant>,
     mut multipart: Multipart,
 ) -> std::result::Result<AxumResponse, ApiError> {
-    let client = state.get_qwen_client().await.ok_or_else(|| {
-        ApiError::bad_request(
-            "Qwen token not configured. Please configure it via POST /v1/config/qwen",
-        )
-    })?;
+    let (account, client) = state.checkout_qwen(&tenant, None).await?;
 
     let mut file_data: Option<(String, Vec<u8>)> = None;
 
@@ -423,16 +1136,18 @@ pub async fn upload_file_for_qwen(
         .map_err(|e| ApiError::internal_error(format!("Failed to save file: {}", e)))?;
 
     // Upload to Qwen
-    let file = client
-        .upload_file(&temp_path)
-        .await
-        .map_err(|e| ApiError::internal_error(format!("File upload failed: {}", e)))?;
+    let file = client.upload_file(&temp_path).await;
+    state.report_qwen(&tenant, &account, &file).await;
+    let file = file.map_err(|e| ApiError::internal_error(format!("File upload failed: {}", e)))?;
 
     // Clean up temp file
     let _ = std::fs::remove_file(&temp_path);
 
     // Store file in state for later use
-    let file_id = state.store_uploaded_file(file.clone()).await;
+    let file_id = state.store_uploaded_file(&tenant, file.clone()).await;
+    if file.file_type.starts_with("image/") {
+        state.store_uploaded_image(&file_id, data).await;
+    }
 
     let response = FileUploadResponse {
         id: file_id,
@@ -444,38 +1159,121 @@ pub async fn upload_file_for_qwen(
     Ok(Json(response).into_response())
 }
 
-pub async fn generate_image(
-    State(state): State<AppState>,
-    Json(payload): Json<GenerateImageRequest>,
-) -> std::result::Result<AxumResponse, ApiError> {
-    Logger::info(&format!("Generating image with prompt: {}", payload.prompt));
+/// Account id, its client, and the Qwen conversation to continue
+type MediaSession = (
+    String,
+    Arc<QwenClient>,
+    Option<reverse_api::qwen::models::ExtraData>,
+);
+
+/// Qwen account and session for a generation. Naming a thread continues its
+/// Qwen conversation, on the account that conversation belongs to.
+pub async fn media_session(
+    state: &AppState,
+    tenant: &str,
+    thread_id: Option<&str>,
+    model: &str,
+) -> std::result::Result<MediaSession, ApiError> {
+    let thread = match thread_id {
+        Some(thread_id) => Some(state.get_thread(tenant, thread_id).await?),
+        None => None,
+    };
+    let pinned = thread.as_ref().and_then(|t| t.qwen_account.as_deref());
+    let (account, client) = state.checkout_qwen(tenant, pinned).await?;
+    let extra_data = thread.and_then(|thread| {
+        Some(reverse_api::qwen::models::ExtraData {
+            chat_id: thread.qwen_chat_id?,
+            model_id: model.to_string(),
+            parent_id: thread.qwen_parent_id,
+        })
+    });
+    Ok((account, client, extra_data))
+}
 
-    let client = state.get_qwen_client().await.ok_or_else(|| {
-        ApiError::bad_request(
-            "Qwen token not configured. Please configure it via POST /v1/config/qwen",
-        )
+/// Download generated media into the media store
+async fn save_media(
+    state: &AppState,
+    tenant: &str,
+    client: &QwenClient,
+    url: &str,
+    kind: MediaKind,
+) -> std::result::Result<(MediaFile, String), ApiError> {
+    let (id, path) = state
+        .media()
+        .reserve(kind)
+        .map_err(|e| ApiError::internal_error(format!("Failed to create directory: {}", e)))?;
+    let filepath = path.to_string_lossy().into_owned();
+
+    Logger::info(&format!("Downloading {} to: {}", kind.as_str(), filepath));
+    client.download_media(url, &filepath).await.map_err(|e| {
+        ApiError::internal_error(format!("Failed to download {}: {}", kind.as_str(), e))
     })?;
 
-    // Get thread state if provided for continuous generation
-    let extra_data = if let Some(thread_id) = &payload.thread_id {
-        let thread_state = state.get_thread(thread_id).await?;
-
-        // Get chat_id and parent_id from thread
-        if let Some(last_msg) = thread_state.messages.last() {
-            // Parse metadata if it contains qwen session info
-            Some(reverse_api::qwen::models::ExtraData {
-                chat_id: thread_id.clone(),
-                model_id: payload.model.clone(),
-                parent_id: Some(last_msg.content.clone()),
-            })
-        } else {
-            None
-        }
+    let file = state
+        .media()
+        .register(tenant, &id, kind, &path, Some(url.to_string()))
+        .await
+        .map_err(|e| ApiError::internal_error(format!("Failed to store media: {}", e)))?;
+    Logger::info(&format!("Saved {} as {}", filepath, file.id));
+    Ok((file, filepath))
+}
+
+/// Result of a generation, downloading it first if requested
+async fn generation_result(
+    state: &AppState,
+    tenant: &str,
+    client: &QwenClient,
+    result: reverse_api::QwenResponse,
+    kind: MediaKind,
+    download: bool,
+) -> std::result::Result<JobResult, ApiError> {
+    let saved = if download {
+        Some(save_media(state, tenant, client, &result.content, kind).await?)
     } else {
         None
     };
+    let (media, local_path) = saved.unzip();
+
+    Ok(JobResult {
+        url: result.content,
+        chat_id: result.chat_id,
+        response_id: result.response_id,
+        media_id: media.as_ref().map(|file| file.id.clone()),
+        media_url: media.map(|file| file.url),
+        local_path,
+    })
+}
 
-    // Generate image
+/// Run a generation in the background as `job`, so it can be polled and
+/// cancelled through `/v1/jobs`
+pub fn spawn_job<F>(state: &AppState, job: &Job, work: F)
+where
+    F: std::future::Future<Output = std::result::Result<JobResult, ApiError>> + Send + 'static,
+{
+    let jobs = state.jobs().clone();
+    let job_id = job.id.clone();
+    let handle = tokio::spawn(
+        async move {
+            jobs.start(&job_id);
+            let outcome = work.await.map_err(|e| e.message);
+            if let Err(message) = &outcome {
+                Logger::error(&format!("Job {} failed: {}", job_id, message));
+            }
+            jobs.finish(&job_id, outcome);
+        }
+        .instrument(tracing::Span::current()),
+    );
+    state.jobs().attach(&job.id, handle.abort_handle());
+}
+
+pub async fn render_image(
+    state: AppState,
+    tenant: String,
+    account: String,
+    client: Arc<QwenClient>,
+    extra_data: Option<reverse_api::qwen::models::ExtraData>,
+    payload: GenerateImageRequest,
+) -> std::result::Result<JobResult, ApiError> {
     let result = client
         .generate_image(
             &payload.prompt,
@@ -483,128 +1281,498 @@ pub async fn generate_image(
             Some(&payload.model),
             extra_data.as_ref(),
         )
-        .await
-        .map_err(|e| ApiError::internal_error(format!("Image generation failed: {}", e)))?;
+        .await;
+    state.report_qwen(&tenant, &account, &result).await;
+    let result =
+        result.map_err(|e| ApiError::internal_error(format!("Image generation failed: {}", e)))?;
 
     Logger::info(&format!("Image generated: {}", result.content));
+    generation_result(
+        &state,
+        &tenant,
+        &client,
+        result,
+        MediaKind::Image,
+        payload.download,
+    )
+    .await
+}
 
-    // Download image if requested
-    let local_path = if payload.download {
-        let timestamp = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-        let filename = format!("generated_image_{}.png", timestamp);
-        let filepath = format!("./generated/{}", filename);
+pub async fn render_video(
+    state: AppState,
+    tenant: String,
+    account: String,
+    client: Arc<QwenClient>,
+    extra_data: Option<reverse_api::qwen::models::ExtraData>,
+    payload: GenerateVideoRequest,
+    on_status: impl Fn(&TaskProgress) + Send + Sync,
+) -> std::result::Result<JobResult, ApiError> {
+    Logger::info("Starting video generation (this may take 1-3 minutes)...");
+    let result = client
+        .generate_video_with_status(
+            &payload.prompt,
+            payload.size.as_deref(),
+            Some(&payload.model),
+            extra_data.as_ref(),
+            on_status,
+        )
+        .await;
+    state.report_qwen(&tenant, &account, &result).await;
+    let result =
+        result.map_err(|e| ApiError::internal_error(format!("Video generation failed: {}", e)))?;
 
-        // Create directory if it doesn't exist
-        std::fs::create_dir_all("./generated")
-            .map_err(|e| ApiError::internal_error(format!("Failed to create directory: {}", e)))?;
+    Logger::info(&format!("Video generated: {}", result.content));
+    generation_result(
+        &state,
+        &tenant,
+        &client,
+        result,
+        MediaKind::Video,
+        payload.download,
+    )
+    .await
+}
 
-        Logger::info(&format!("Downloading image to: {}", filepath));
-        client
-            .download_media(&result.content, &filepath)
-            .await
-            .map_err(|e| ApiError::internal_error(format!("Failed to download image: {}", e)))?;
+#[utoipa::path(
+    post,
+    path = "/v1/images/generate",
+    tag = "media",
+    summary = "生成图片",
+    description = "使用 Qwen 生成图片；download 为 true 时保存到服务器并返回 media_url，background 为 true 时立即返回任务",
+    request_body = GenerateImageRequest,
+    responses(
+        (status = 200, description = "生成的图片", body = GenerateImageResponse),
+        (status = 202, description = "后台任务", body = Job)
+    )
+)]
+pub async fn generate_image(
+    State(state): State<AppState>,
+    Extension(Tenant(tenant)): Extension<Tenant>,
+    Json(payload): Json<GenerateImageRequest>,
+) -> std::result::Result<AxumResponse, ApiError> {
+    Logger::info(&format!("Generating image with prompt: {}", payload.prompt));
+    let request_model = RequestModel(payload.model.clone());
+
+    let (account, client, extra_data) = media_session(
+        &state,
+        &tenant,
+        payload.thread_id.as_deref(),
+        &payload.model,
+    )
+    .await?;
+
+    if payload.background {
+        let job = state
+            .jobs()
+            .create(&tenant, MediaKind::Image, &payload.prompt, &payload.model);
+        let work = render_image(state.clone(), tenant, account, client, extra_data, payload);
+        spawn_job(&state, &job, work);
+        let mut response = (StatusCode::ACCEPTED, Json(job)).into_response();
+        response.extensions_mut().insert(request_model);
+        return Ok(response);
+    }
 
-        Logger::info(&format!("Image saved to: {}", filepath));
-        Some(filepath)
-    } else {
-        None
-    };
+    let prompt = payload.prompt.clone();
+    let result = render_image(state, tenant, account, client, extra_data, payload).await?;
 
     let response = GenerateImageResponse {
-        image_url: result.content,
-        prompt: payload.prompt,
+        image_url: result.url,
+        prompt,
         chat_id: result.chat_id,
         response_id: result.response_id,
-        local_path,
+        media_id: result.media_id,
+        media_url: result.media_url,
+        local_path: result.local_path,
     };
 
-    Ok(Json(response).into_response())
+    let mut response = Json(response).into_response();
+    response.extensions_mut().insert(request_model);
+    Ok(response)
 }
 
-pub async fn generate_video(
-    State(state): State<AppState>,
-    Json(payload): Json<GenerateVideoRequest>,
-) -> std::result::Result<AxumResponse, ApiError> {
-    Logger::info(&format!("Generating video with prompt: {}", payload.prompt));
-
-    let client = state.get_qwen_client().await.ok_or_else(|| {
-        ApiError::bad_request(
-            "Qwen token not configured. Please configure it via POST /v1/config/qwen",
-        )
-    })?;
+/// Generate `n` images with Qwen, one upstream conversation each
+async fn qwen_images(
+    state: &AppState,
+    tenant: &str,
+    model: &str,
+    prompt: &str,
+    size: Option<&str>,
+    n: u32,
+) -> std::result::Result<Vec<String>, ApiError> {
+    let mut urls = Vec::new();
+    for _ in 0..n {
+        let (account, client) = state.checkout_qwen(tenant, None).await?;
+        let result = client.generate_image(prompt, size, Some(model), None).await;
+        state.report_qwen(tenant, &account, &result).await;
+        let result = result
+            .map_err(|e| ApiError::internal_error(format!("Image generation failed: {}", e)))?;
+        urls.push(result.content);
+    }
+    Ok(urls)
+}
 
-    // Get thread state if provided for continuous generation
-    let extra_data = if let Some(thread_id) = &payload.thread_id {
-        let thread_state = state.get_thread(thread_id).await?;
+/// Generate at least `n` images with Grok, which usually returns a few per turn
+async fn grok_images(
+    state: &AppState,
+    model: &str,
+    prompt: &str,
+    n: u32,
+) -> std::result::Result<Vec<String>, ApiError> {
+    let n = n as usize;
+    let mut urls = Vec::new();
+    for _ in 0..n {
+        let mut grok = Grok::new(model, state.config().providers.grok.proxy.as_deref())
+            .map_err(|e| ApiError::internal_error(format!("Grok error: {}", e)))?;
+        let result = grok
+            .start_convo(&format!("Generate an image: {}", prompt), None)
+            .await
+            .map_err(|e| ApiError::internal_error(format!("Grok error: {}", e)))?;
+        urls.extend(
+            result
+                .images
+                .unwrap_or_default()
+                .iter()
+                .map(|path| images::grok_image_url(path)),
+        );
+        if urls.len() >= n {
+            break;
+        }
+    }
+    if urls.is_empty() {
+        return Err(ApiError::internal_error("Grok did not return any images"));
+    }
+    urls.truncate(n);
+    Ok(urls)
+}
 
-        // Get chat_id and parent_id from thread
-        if let Some(last_msg) = thread_state.messages.last() {
-            Some(reverse_api::qwen::models::ExtraData {
-                chat_id: thread_id.clone(),
-                model_id: payload.model.clone(),
-                parent_id: Some(last_msg.content.clone()),
+/// OpenAI-compatible `POST /v1/images/generations`
+#[utoipa::path(
+    post,
+    path = "/v1/images/generations",
+    tag = "compat",
+    summary = "OpenAI 图片生成",
+    description = "默认使用 Qwen，grok-* 模型使用 Grok；n 最大为 4，size 映射到最接近的 1:1、16:9 或 9:16，Grok 会忽略 size",
+    request_body = ImageGenerationRequest,
+    responses(
+        (status = 200, description = "生成的图片", body = ImageGenerationResponse),
+        (status = 400, description = "n 超出范围或模型不支持", body = ErrorResponse)
+    )
+)]
+pub async fn create_image_generations(
+    State(state): State<AppState>,
+    Extension(Tenant(tenant)): Extension<Tenant>,
+    Json(payload): Json<ImageGenerationRequest>,
+) -> std::result::Result<AxumResponse, ApiError> {
+    let n = payload.n.unwrap_or(1);
+    if n == 0 || n > images::MAX_IMAGES {
+        return Err(ApiError::bad_request(format!(
+            "'n' must be between 1 and {}",
+            images::MAX_IMAGES
+        )));
+    }
+    let size = payload
+        .size
+        .as_deref()
+        .map(|size| {
+            images::qwen_image_size(size).ok_or_else(|| {
+                ApiError::bad_request(format!(
+                    "Unsupported size: {}. Use WIDTHxHEIGHT or 1:1, 16:9, 9:16",
+                    size
+                ))
             })
-        } else {
-            None
-        }
+        })
+        .transpose()?;
+    let model = images::image_model(state.config(), payload.model.as_deref())?;
+    Logger::info(&format!(
+        "Generating {} image(s) with {}: {}",
+        n, model, payload.prompt
+    ));
+
+    let urls = if Provider::from_model(&model) == Some(Provider::Grok) {
+        grok_images(&state, &model, &payload.prompt, n).await?
     } else {
-        None
+        qwen_images(&state, &tenant, &model, &payload.prompt, size, n).await?
     };
 
-    // Generate video with progress logging
-    Logger::info("Starting video generation (this may take 1-3 minutes)...");
-    let result = client
-        .generate_video_with_progress(
-            &payload.prompt,
-            payload.size.as_deref(),
-            Some(&payload.model),
-            extra_data.as_ref(),
-            |status, percent| {
-                if percent % 20 == 0 || status == "success" {
-                    Logger::info(&format!("Video generation: {} - {}%", status, percent));
-                }
-            },
-        )
-        .await
-        .map_err(|e| ApiError::internal_error(format!("Video generation failed: {}", e)))?;
-
-    Logger::info(&format!("Video generated: {}", result.content));
+    let mut data = Vec::with_capacity(urls.len());
+    match payload.response_format {
+        ImageResponseFormat::Url => {
+            data.extend(urls.into_iter().map(|url| ImageData {
+                url: Some(url),
+                b64_json: None,
+            }));
+        }
+        ImageResponseFormat::B64Json => {
+            let downloader = MediaDownloader::standalone().map_err(|e| {
+                ApiError::internal_error(format!("Could not create downloader: {}", e))
+            })?;
+            for url in urls {
+                let bytes = downloader.fetch_media(&url).await.map_err(|e| {
+                    ApiError::internal_error(format!("Failed to download image: {}", e))
+                })?;
+                data.push(ImageData {
+                    url: None,
+                    b64_json: Some(general_purpose::STANDARD.encode(bytes)),
+                });
+            }
+        }
+    }
 
-    // Download video if requested
-    let local_path = if payload.download {
-        let timestamp = std::time::SystemTime::now()
+    let response = ImageGenerationResponse {
+        created: std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap()
-            .as_secs();
-        let filename = format!("generated_video_{}.mp4", timestamp);
-        let filepath = format!("./generated/{}", filename);
-
-        // Create directory if it doesn't exist
-        std::fs::create_dir_all("./generated")
-            .map_err(|e| ApiError::internal_error(format!("Failed to create directory: {}", e)))?;
+            .as_secs(),
+        data,
+    };
+    let mut response = Json(response).into_response();
+    response.extensions_mut().insert(RequestModel(model));
+    Ok(response)
+}
 
-        Logger::info(&format!("Downloading video to: {}", filepath));
-        client
-            .download_media(&result.content, &filepath)
-            .await
-            .map_err(|e| ApiError::internal_error(format!("Failed to download video: {}", e)))?;
+#[utoipa::path(
+    post,
+    path = "/v1/videos/generate",
+    tag = "media",
+    summary = "生成视频",
+    description = "使用 Qwen 生成视频，通常需要 1-3 分钟；background 为 true 时立即返回任务",
+    request_body = GenerateVideoRequest,
+    responses(
+        (status = 200, description = "生成的视频", body = GenerateVideoResponse),
+        (status = 202, description = "后台任务", body = Job)
+    )
+)]
+pub async fn generate_video(
+    State(state): State<AppState>,
+    Extension(Tenant(tenant)): Extension<Tenant>,
+    Json(payload): Json<GenerateVideoRequest>,
+) -> std::result::Result<AxumResponse, ApiError> {
+    Logger::info(&format!("Generating video with prompt: {}", payload.prompt));
+    let request_model = RequestModel(payload.model.clone());
+
+    let (account, client, extra_data) = media_session(
+        &state,
+        &tenant,
+        payload.thread_id.as_deref(),
+        &payload.model,
+    )
+    .await?;
+
+    if payload.background {
+        let job = state
+            .jobs()
+            .create(&tenant, MediaKind::Video, &payload.prompt, &payload.model);
+        let jobs = state.jobs().clone();
+        let job_id = job.id.clone();
+        let work = render_video(
+            state.clone(),
+            tenant,
+            account,
+            client,
+            extra_data,
+            payload,
+            move |progress| jobs.progress(&job_id, progress),
+        );
+        spawn_job(&state, &job, work);
+        let mut response = (StatusCode::ACCEPTED, Json(job)).into_response();
+        response.extensions_mut().insert(request_model);
+        return Ok(response);
+    }
 
-        Logger::info(&format!("Video saved to: {}", filepath));
-        Some(filepath)
-    } else {
-        None
-    };
+    let prompt = payload.prompt.clone();
+    let result = render_video(
+        state,
+        tenant,
+        account,
+        client,
+        extra_data,
+        payload,
+        |progress| {
+            if progress.percent % 20 == 0 || progress.status == "success" {
+                Logger::info(&format!(
+                    "Video generation: {} - {}%",
+                    progress.status, progress.percent
+                ));
+            }
+        },
+    )
+    .await?;
 
     let response = GenerateVideoResponse {
-        video_url: result.content,
-        prompt: payload.prompt,
+        video_url: result.url,
+        prompt,
         chat_id: result.chat_id,
         response_id: result.response_id,
-        local_path,
+        media_id: result.media_id,
+        media_url: result.media_url,
+        local_path: result.local_path,
+    };
+
+    let mut response = Json(response).into_response();
+    response.extensions_mut().insert(request_model);
+    Ok(response)
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/jobs",
+    tag = "jobs",
+    summary = "列出任务",
+    description = "分页列出图片和视频生成任务，默认最新的在前",
+    params(ListJobsQuery),
+    responses(
+        (status = 200, description = "一页任务", body = ListJobsResponse)
+    )
+)]
+pub async fn list_jobs(
+    State(state): State<AppState>,
+    Extension(Tenant(tenant)): Extension<Tenant>,
+    Query(query): Query<ListJobsQuery>,
+) -> std::result::Result<AxumResponse, ApiError> {
+    let jobs: Vec<Job> = state
+        .jobs()
+        .list(&tenant)
+        .into_iter()
+        .filter(|job| query.status.is_none_or(|status| job.status == status))
+        .filter(|job| query.kind.is_none_or(|kind| job.kind == kind))
+        .collect();
+    let page = pagination::paginate(
+        jobs,
+        |job| job.id.as_str(),
+        PageRequest {
+            limit: query.limit,
+            order: query.order,
+            after: query.after.as_deref(),
+            before: query.before.as_deref(),
+        },
+    )?;
+
+    let response = ListJobsResponse {
+        object: "list".to_string(),
+        first_id: page.data.first().map(|j| j.id.clone()),
+        last_id: page.data.last().map(|j| j.id.clone()),
+        data: page.data,
+        has_more: page.has_more,
     };
 
     Ok(Json(response).into_response())
 }
+
+#[utoipa::path(
+    get,
+    path = "/v1/jobs/{job_id}",
+    tag = "jobs",
+    summary = "查询任务",
+    params(JobPath),
+    responses(
+        (status = 200, description = "任务状态、进度和结果", body = Job),
+        (status = 404, description = "任务不存在", body = ErrorResponse)
+    )
+)]
+pub async fn get_job(
+    State(state): State<AppState>,
+    Extension(Tenant(tenant)): Extension<Tenant>,
+    axum::extract::Path(params): axum::extract::Path<JobPath>,
+) -> std::result::Result<AxumResponse, ApiError> {
+    let job = state.jobs().get(&tenant, &params.job_id)?;
+    Ok(Json(job).into_response())
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/jobs/{job_id}/cancel",
+    tag = "jobs",
+    summary = "取消任务",
+    params(JobPath),
+    responses(
+        (status = 200, description = "已取消的任务", body = Job),
+        (status = 400, description = "任务已结束", body = ErrorResponse),
+        (status = 404, description = "任务不存在", body = ErrorResponse)
+    )
+)]
+pub async fn cancel_job(
+    State(state): State<AppState>,
+    Extension(Tenant(tenant)): Extension<Tenant>,
+    axum::extract::Path(params): axum::extract::Path<JobPath>,
+) -> std::result::Result<AxumResponse, ApiError> {
+    let job = state.jobs().cancel(&tenant, &params.job_id)?;
+    Logger::info(&format!("Job {} cancelled", job.id));
+    Ok(Json(job).into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/media",
+    tag = "media",
+    summary = "列出媒体文件",
+    description = "分页列出已下载的图片和视频",
+    params(ListMediaQuery),
+    responses(
+        (status = 200, description = "一页媒体文件", body = ListMediaResponse)
+    )
+)]
+pub async fn list_media(
+    State(state): State<AppState>,
+    Extension(Tenant(tenant)): Extension<Tenant>,
+    Query(query): Query<ListMediaQuery>,
+) -> std::result::Result<AxumResponse, ApiError> {
+    let files: Vec<MediaFile> = state
+        .media()
+        .list(&tenant)
+        .await
+        .into_iter()
+        .filter(|file| query.kind.is_none_or(|kind| file.kind == kind))
+        .collect();
+    let page = pagination::paginate(
+        files,
+        |file| file.id.as_str(),
+        PageRequest {
+            limit: query.limit,
+            order: query.order,
+            after: query.after.as_deref(),
+            before: query.before.as_deref(),
+        },
+    )?;
+
+    let response = ListMediaResponse {
+        object: "list".to_string(),
+        first_id: page.data.first().map(|f| f.id.clone()),
+        last_id: page.data.last().map(|f| f.id.clone()),
+        data: page.data,
+        has_more: page.has_more,
+    };
+
+    Ok(Json(response).into_response())
+}
+
+/// Serve a stored file, honouring `Range` so videos can be seeked
+#[utoipa::path(
+    get,
+    path = "/v1/media/{media_id}",
+    tag = "media",
+    summary = "下载媒体文件",
+    description = "返回文件内容，支持 Range 请求",
+    params(MediaPath),
+    responses(
+        (status = 200, description = "文件内容", content(("image/png"), ("video/mp4"))),
+        (status = 206, description = "Range 请求的部分内容"),
+        (status = 404, description = "文件不存在", body = ErrorResponse)
+    )
+)]
+pub async fn get_media(
+    State(state): State<AppState>,
+    Extension(Tenant(tenant)): Extension<Tenant>,
+    axum::extract::Path(params): axum::extract::Path<MediaPath>,
+    request: Request,
+) -> std::result::Result<AxumResponse, ApiError> {
+    let (_, path) = state.media().get(&tenant, &params.media_id).await?;
+    // Content type is guessed from the extension, as when the file was stored
+    let response = ServeFile::new(path)
+        .oneshot(request)
+        .await
+        .map_err(|e| ApiError::internal_error(format!("Failed to read media: {}", e)))?;
+    if response.status() == StatusCode::NOT_FOUND {
+        return Err(ApiError::not_found("Media file is missing on disk"));
+    }
+    Ok(response.map(Body::new))
+}
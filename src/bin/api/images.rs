@@ -0,0 +1,98 @@
+use super::config::ServerConfig;
+use super::error::ApiError;
+use super::providers::Provider;
+
+/// Most images one `/v1/images/generations` request may ask for
+pub const MAX_IMAGES: u32 = 4;
+
+/// Aspect ratios accepted by Qwen image generation
+const QWEN_SIZES: [(&str, f64); 3] = [("1:1", 1.0), ("16:9", 16.0 / 9.0), ("9:16", 9.0 / 16.0)];
+
+/// Host serving the relative paths in Grok's `generatedImageUrls`
+const GROK_ASSETS: &str = "https://assets.grok.com";
+
+/// Map an OpenAI size such as `1024x1792` to the Qwen aspect ratio closest to
+/// it. Qwen ratios such as `16:9` are accepted as they are.
+pub fn qwen_image_size(size: &str) -> Option<&'static str> {
+    if let Some((ratio, _)) = QWEN_SIZES.iter().find(|(ratio, _)| *ratio == size) {
+        return Some(ratio);
+    }
+    let (width, height) = size.split_once('x')?;
+    let width: f64 = width.trim().parse().ok()?;
+    let height: f64 = height.trim().parse().ok()?;
+    if width <= 0.0 || height <= 0.0 {
+        return None;
+    }
+    let wanted = (width / height).ln();
+    QWEN_SIZES
+        .iter()
+        .min_by(|a, b| {
+            let distance = |ratio: f64| (ratio.ln() - wanted).abs();
+            distance(a.1).total_cmp(&distance(b.1))
+        })
+        .map(|(ratio, _)| *ratio)
+}
+
+/// Grok reports generated images as paths on its asset host
+pub fn grok_image_url(path: &str) -> String {
+    if path.starts_with("http://") || path.starts_with("https://") {
+        path.to_string()
+    } else {
+        format!("{}/{}", GROK_ASSETS, path.trim_start_matches('/'))
+    }
+}
+
+/// Model serving an images request. Grok models go to Grok; anything else,
+/// including OpenAI ids such as `dall-e-3`, goes to the default Qwen model.
+pub fn image_model(config: &ServerConfig, requested: Option<&str>) -> Result<String, ApiError> {
+    let qwen_default = || {
+        config
+            .providers
+            .qwen
+            .default_model
+            .clone()
+            .unwrap_or_else(|| "qwen3-max".to_string())
+    };
+    let Some(model) = requested.and_then(|model| config.resolve_model(model)) else {
+        return Ok(qwen_default());
+    };
+    match Provider::from_model(&model) {
+        Some(Provider::Qwen) if model == "qwen" => Ok(qwen_default()),
+        Some(Provider::Qwen) | Some(Provider::Grok) => Ok(model),
+        _ => Err(ApiError::bad_request(format!(
+            "Model {} does not support image generation, use a 'qwen-*' or 'grok-*' model",
+            model
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_qwen_image_size() {
+        assert_eq!(qwen_image_size("1024x1024"), Some("1:1"));
+        assert_eq!(qwen_image_size("1792x1024"), Some("16:9"));
+        assert_eq!(qwen_image_size("1024x1792"), Some("9:16"));
+        assert_eq!(qwen_image_size("1536x1024"), Some("16:9"));
+        assert_eq!(qwen_image_size("9:16"), Some("9:16"));
+        assert_eq!(qwen_image_size("0x1024"), None);
+        assert_eq!(qwen_image_size("large"), None);
+    }
+
+    #[test]
+    fn test_image_model_and_grok_urls() {
+        let config = ServerConfig::default();
+        assert_eq!(image_model(&config, None).unwrap(), "qwen3-max");
+        assert_eq!(image_model(&config, Some("dall-e-3")).unwrap(), "qwen3-max");
+        assert_eq!(image_model(&config, Some("grok-4")).unwrap(), "grok-4");
+        assert!(image_model(&config, Some("deepseek")).is_err());
+
+        assert_eq!(
+            grok_image_url("users/u1/generated/i1/image.jpg"),
+            "https://assets.grok.com/users/u1/generated/i1/image.jpg"
+        );
+        assert_eq!(grok_image_url("https://cdn/x.png"), "https://cdn/x.png");
+    }
+}
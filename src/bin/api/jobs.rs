@@ -0,0 +1,349 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use reverse_api::qwen::models::TaskProgress;
+use serde::{Deserialize, Serialize};
+use tokio::task::AbortHandle;
+use utoipa::ToSchema;
+
+use super::error::ApiError;
+use super::media::MediaKind;
+
+/// Finished jobs kept for polling before the oldest are forgotten
+const MAX_FINISHED_JOBS: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JobStatus::Queued => "queued",
+            JobStatus::Running => "running",
+            JobStatus::Succeeded => "succeeded",
+            JobStatus::Failed => "failed",
+            JobStatus::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn is_finished(self) -> bool {
+        matches!(
+            self,
+            JobStatus::Succeeded | JobStatus::Failed | JobStatus::Cancelled
+        )
+    }
+}
+
+/// Output of a finished generation job
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct JobResult {
+    pub url: String,
+    pub chat_id: Option<String>,
+    pub response_id: String,
+    /// Id in the media store, when the result was downloaded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub media_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub media_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub local_path: Option<String>,
+}
+
+/// A background image or video generation
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Job {
+    pub id: String,
+    pub object: String,
+    pub kind: MediaKind,
+    pub status: JobStatus,
+    /// 0-100
+    pub progress: u8,
+    /// Qwen's own estimate, e.g. while a video renders
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remaining_time: Option<String>,
+    /// Upstream Qwen task id, once the task has started
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub task_id: Option<String>,
+    pub prompt: String,
+    pub model: String,
+    pub created_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub completed_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<JobResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+struct JobEntry {
+    tenant: String,
+    job: Job,
+    abort: Option<AbortHandle>,
+}
+
+/// In-memory registry of generation jobs. Jobs do not survive a restart.
+///
+/// Uses a blocking mutex because progress arrives through the synchronous
+/// Qwen status callback; the lock is never held across an await.
+#[derive(Clone, Default)]
+pub struct JobStore {
+    jobs: Arc<Mutex<HashMap<String, JobEntry>>>,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+impl JobStore {
+    pub fn create(&self, tenant: &str, kind: MediaKind, prompt: &str, model: &str) -> Job {
+        let job = Job {
+            id: format!("job_{}", uuid::Uuid::new_v4().simple()),
+            object: "job".to_string(),
+            kind,
+            status: JobStatus::Queued,
+            progress: 0,
+            remaining_time: None,
+            task_id: None,
+            prompt: prompt.to_string(),
+            model: model.to_string(),
+            created_at: now_secs(),
+            completed_at: None,
+            result: None,
+            error: None,
+        };
+        self.jobs.lock().unwrap().insert(
+            job.id.clone(),
+            JobEntry {
+                tenant: tenant.to_string(),
+                job: job.clone(),
+                abort: None,
+            },
+        );
+        job
+    }
+
+    /// Remember the task running `id` so the job can be cancelled
+    pub fn attach(&self, id: &str, abort: AbortHandle) {
+        if let Some(entry) = self.jobs.lock().unwrap().get_mut(id) {
+            if !entry.job.status.is_finished() {
+                entry.abort = Some(abort);
+            }
+        }
+    }
+
+    pub fn start(&self, id: &str) {
+        self.update(id, |job| job.status = JobStatus::Running);
+    }
+
+    /// Apply a status update from the Qwen task poller
+    pub fn progress(&self, id: &str, progress: &TaskProgress) {
+        self.update(id, |job| {
+            job.status = JobStatus::Running;
+            job.progress = progress.percent.min(99);
+            job.task_id = Some(progress.task_id.clone());
+            job.remaining_time =
+                Some(progress.remaining_time.clone()).filter(|time| !time.is_empty());
+        });
+    }
+
+    pub fn finish(&self, id: &str, outcome: Result<JobResult, String>) {
+        self.update(id, |job| {
+            job.completed_at = Some(now_secs());
+            job.remaining_time = None;
+            match outcome {
+                Ok(result) => {
+                    job.status = JobStatus::Succeeded;
+                    job.progress = 100;
+                    job.result = Some(result);
+                }
+                Err(error) => {
+                    job.status = JobStatus::Failed;
+                    job.error = Some(error);
+                }
+            }
+        });
+        self.prune();
+    }
+
+    /// Stop a queued or running job; finished jobs are left as they are
+    pub fn cancel(&self, tenant: &str, id: &str) -> Result<Job, ApiError> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let entry = jobs
+            .get_mut(id)
+            .filter(|entry| entry.tenant == tenant)
+            .ok_or_else(|| ApiError::not_found("Job not found"))?;
+        if entry.job.status.is_finished() {
+            return Err(ApiError::bad_request(format!(
+                "Job already finished with status '{}'",
+                entry.job.status.as_str()
+            )));
+        }
+        if let Some(abort) = entry.abort.take() {
+            abort.abort();
+        }
+        entry.job.status = JobStatus::Cancelled;
+        entry.job.completed_at = Some(now_secs());
+        entry.job.remaining_time = None;
+        let job = entry.job.clone();
+        drop(jobs);
+        self.prune();
+        Ok(job)
+    }
+
+    /// Jobs still queued or running
+    pub fn unfinished(&self) -> usize {
+        self.jobs
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|entry| !entry.job.status.is_finished())
+            .count()
+    }
+
+    /// Stop every unfinished job, e.g. when the server shuts down
+    pub fn cancel_all(&self) -> usize {
+        let mut jobs = self.jobs.lock().unwrap();
+        let mut cancelled = 0;
+        for entry in jobs.values_mut() {
+            if entry.job.status.is_finished() {
+                continue;
+            }
+            if let Some(abort) = entry.abort.take() {
+                abort.abort();
+            }
+            entry.job.status = JobStatus::Cancelled;
+            entry.job.completed_at = Some(now_secs());
+            entry.job.remaining_time = None;
+            cancelled += 1;
+        }
+        cancelled
+    }
+
+    /// Other tenants' jobs are reported as not found
+    pub fn get(&self, tenant: &str, id: &str) -> Result<Job, ApiError> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .get(id)
+            .filter(|entry| entry.tenant == tenant)
+            .map(|entry| entry.job.clone())
+            .ok_or_else(|| ApiError::not_found("Job not found"))
+    }
+
+    /// The tenant's jobs, oldest first
+    pub fn list(&self, tenant: &str) -> Vec<Job> {
+        let jobs = self.jobs.lock().unwrap();
+        let mut list: Vec<Job> = jobs
+            .values()
+            .filter(|entry| entry.tenant == tenant)
+            .map(|entry| entry.job.clone())
+            .collect();
+        list.sort_by(|a, b| a.created_at.cmp(&b.created_at).then(a.id.cmp(&b.id)));
+        list
+    }
+
+    /// Updates for jobs that were cancelled in the meantime are dropped
+    fn update(&self, id: &str, apply: impl FnOnce(&mut Job)) {
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some(entry) = jobs.get_mut(id) {
+            if !entry.job.status.is_finished() {
+                apply(&mut entry.job);
+            }
+            if entry.job.status.is_finished() {
+                entry.abort = None;
+            }
+        }
+    }
+
+    fn prune(&self) {
+        let mut jobs = self.jobs.lock().unwrap();
+        let mut finished: Vec<(u64, String)> = jobs
+            .values()
+            .filter(|entry| entry.job.status.is_finished())
+            .map(|entry| (entry.job.completed_at.unwrap_or(0), entry.job.id.clone()))
+            .collect();
+        if finished.len() <= MAX_FINISHED_JOBS {
+            return;
+        }
+        finished.sort();
+        let excess = finished.len() - MAX_FINISHED_JOBS;
+        for (_, id) in finished.into_iter().take(excess) {
+            jobs.remove(&id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn progress(percent: u8, remaining_time: &str) -> TaskProgress {
+        TaskProgress {
+            task_id: "task-1".to_string(),
+            status: "running".to_string(),
+            percent,
+            remaining_time: remaining_time.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_job_lifecycle() {
+        let store = JobStore::default();
+        let job = store.create("acme", MediaKind::Video, "a cat", "qwen3-max");
+        assert_eq!(job.status, JobStatus::Queued);
+        assert!(store.get("other", &job.id).is_err());
+
+        store.progress(&job.id, &progress(40, "30s"));
+        let running = store.get("acme", &job.id).unwrap();
+        assert_eq!(running.status, JobStatus::Running);
+        assert_eq!(running.progress, 40);
+        assert_eq!(running.remaining_time.as_deref(), Some("30s"));
+        assert_eq!(running.task_id.as_deref(), Some("task-1"));
+
+        store.finish(
+            &job.id,
+            Ok(JobResult {
+                url: "https://example.com/v.mp4".to_string(),
+                chat_id: None,
+                response_id: "r1".to_string(),
+                media_id: None,
+                media_url: None,
+                local_path: None,
+            }),
+        );
+        let done = store.get("acme", &job.id).unwrap();
+        assert_eq!(done.status, JobStatus::Succeeded);
+        assert_eq!(done.progress, 100);
+        assert!(done.remaining_time.is_none());
+        assert!(store.cancel("acme", &job.id).is_err());
+    }
+
+    #[test]
+    fn test_cancel_ignores_late_updates() {
+        let store = JobStore::default();
+        let job = store.create("acme", MediaKind::Image, "a dog", "qwen3-max");
+        assert!(store.cancel("other", &job.id).is_err());
+        assert_eq!(
+            store.cancel("acme", &job.id).unwrap().status,
+            JobStatus::Cancelled
+        );
+
+        store.progress(&job.id, &progress(80, ""));
+        store.finish(&job.id, Err("boom".to_string()));
+        let job = store.get("acme", &job.id).unwrap();
+        assert_eq!(job.status, JobStatus::Cancelled);
+        assert!(job.error.is_none());
+        assert_eq!(store.list("acme").len(), 1);
+    }
+}
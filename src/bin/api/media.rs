@@ -0,0 +1,311 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use reverse_api::Logger;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use utoipa::ToSchema;
+
+use super::error::ApiError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaKind {
+    Image,
+    Video,
+}
+
+impl MediaKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MediaKind::Image => "image",
+            MediaKind::Video => "video",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            MediaKind::Image => "png",
+            MediaKind::Video => "mp4",
+        }
+    }
+}
+
+/// A generated image or video kept by the server
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct MediaFile {
+    pub id: String,
+    pub object: String,
+    pub kind: MediaKind,
+    pub filename: String,
+    pub content_type: String,
+    pub bytes: u64,
+    pub created_at: u64,
+    /// Where the server can serve the file, relative to its base URL
+    pub url: String,
+    /// Upstream URL the file was downloaded from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_url: Option<String>,
+}
+
+/// A media file and the tenant that owns it, as written next to the file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredMedia {
+    tenant: String,
+    file: MediaFile,
+}
+
+/// When stored media is deleted; unset limits keep files forever
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// Seconds after creation
+    pub max_age: Option<u64>,
+    /// Total size of all stored media
+    pub max_total_bytes: Option<u64>,
+}
+
+impl RetentionPolicy {
+    pub fn is_unlimited(&self) -> bool {
+        self.max_age.is_none() && self.max_total_bytes.is_none()
+    }
+}
+
+/// Files to delete under `policy`: everything past `max_age`, then the oldest
+/// of the rest until they fit in `max_total_bytes`
+pub fn expired(files: &[&MediaFile], now: u64, policy: RetentionPolicy) -> Vec<String> {
+    let mut files: Vec<&MediaFile> = files.to_vec();
+    files.sort_by(|a, b| a.created_at.cmp(&b.created_at).then(a.id.cmp(&b.id)));
+
+    let mut total: u64 = files.iter().map(|file| file.bytes).sum();
+    let mut doomed = Vec::new();
+    for file in files {
+        let too_old = policy
+            .max_age
+            .is_some_and(|max_age| now.saturating_sub(file.created_at) > max_age);
+        let too_big = policy.max_total_bytes.is_some_and(|max| total > max);
+        if too_old || too_big {
+            total -= file.bytes;
+            doomed.push(file.id.clone());
+        }
+    }
+    doomed
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Generated media under the configured output directory, indexed by id.
+///
+/// Each file is stored as `<id>.<ext>` with its metadata in `<id>.json`, so
+/// the index survives restarts. Files written there by other means are left
+/// alone.
+#[derive(Clone)]
+pub struct MediaStore {
+    dir: PathBuf,
+    retention: RetentionPolicy,
+    files: Arc<RwLock<HashMap<String, StoredMedia>>>,
+}
+
+impl MediaStore {
+    /// Store in `dir` without touching the disk until the first file arrives
+    pub fn new(dir: impl Into<PathBuf>, retention: RetentionPolicy) -> Self {
+        Self {
+            dir: dir.into(),
+            retention,
+            files: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Open `dir`, restoring the media recorded there
+    pub fn open(dir: impl Into<PathBuf>, retention: RetentionPolicy) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        let mut files = HashMap::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            let stored: StoredMedia = match fs::read(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|data| serde_json::from_slice(&data).map_err(|e| e.to_string()))
+            {
+                Ok(stored) => stored,
+                Err(e) => {
+                    Logger::error(&format!("Skipping media record {}: {}", path.display(), e));
+                    continue;
+                }
+            };
+            if dir.join(&stored.file.filename).exists() {
+                files.insert(stored.file.id.clone(), stored);
+            }
+        }
+        Logger::info(&format!(
+            "Restored {} media files from {}",
+            files.len(),
+            dir.display()
+        ));
+        Ok(Self {
+            dir,
+            retention,
+            files: Arc::new(RwLock::new(files)),
+        })
+    }
+
+    pub fn retention(&self) -> RetentionPolicy {
+        self.retention
+    }
+
+    /// Pick an id and the path its file should be written to
+    pub fn reserve(&self, kind: MediaKind) -> io::Result<(String, PathBuf)> {
+        fs::create_dir_all(&self.dir)?;
+        let id = format!("media_{}", uuid::Uuid::new_v4().simple());
+        let path = self.dir.join(format!("{}.{}", id, kind.extension()));
+        Ok((id, path))
+    }
+
+    /// Index a file written to a path from [`MediaStore::reserve`]
+    pub async fn register(
+        &self,
+        tenant: &str,
+        id: &str,
+        kind: MediaKind,
+        path: &Path,
+        source_url: Option<String>,
+    ) -> io::Result<MediaFile> {
+        let filename = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default()
+            .to_string();
+        let file = MediaFile {
+            id: id.to_string(),
+            object: "media".to_string(),
+            kind,
+            content_type: mime_guess::from_path(path)
+                .first_or_octet_stream()
+                .to_string(),
+            filename,
+            bytes: fs::metadata(path)?.len(),
+            created_at: now_secs(),
+            url: format!("/v1/media/{}", id),
+            source_url,
+        };
+        let stored = StoredMedia {
+            tenant: tenant.to_string(),
+            file: file.clone(),
+        };
+        let record = serde_json::to_vec_pretty(&stored).map_err(io::Error::other)?;
+        fs::write(self.dir.join(format!("{}.json", id)), record)?;
+        self.files.write().await.insert(id.to_string(), stored);
+
+        self.enforce_retention().await;
+        Ok(file)
+    }
+
+    /// A media file and its location on disk; other tenants' files are
+    /// reported as not found
+    pub async fn get(&self, tenant: &str, id: &str) -> Result<(MediaFile, PathBuf), ApiError> {
+        self.files
+            .read()
+            .await
+            .get(id)
+            .filter(|stored| stored.tenant == tenant)
+            .map(|stored| (stored.file.clone(), self.dir.join(&stored.file.filename)))
+            .ok_or_else(|| ApiError::not_found("Media not found"))
+    }
+
+    /// The tenant's media, oldest first
+    pub async fn list(&self, tenant: &str) -> Vec<MediaFile> {
+        let files = self.files.read().await;
+        let mut list: Vec<MediaFile> = files
+            .values()
+            .filter(|stored| stored.tenant == tenant)
+            .map(|stored| stored.file.clone())
+            .collect();
+        list.sort_by(|a, b| a.created_at.cmp(&b.created_at).then(a.id.cmp(&b.id)));
+        list
+    }
+
+    /// Delete the files the retention policy no longer allows, returning how
+    /// many were removed
+    pub async fn enforce_retention(&self) -> usize {
+        if self.retention.is_unlimited() {
+            return 0;
+        }
+        let mut files = self.files.write().await;
+        let doomed = {
+            let all: Vec<&MediaFile> = files.values().map(|stored| &stored.file).collect();
+            expired(&all, now_secs(), self.retention)
+        };
+        for id in &doomed {
+            let Some(stored) = files.remove(id) else {
+                continue;
+            };
+            for path in [
+                self.dir.join(&stored.file.filename),
+                self.dir.join(format!("{}.json", id)),
+            ] {
+                if let Err(e) = fs::remove_file(&path) {
+                    if e.kind() != io::ErrorKind::NotFound {
+                        Logger::error(&format!("Failed to delete {}: {}", path.display(), e));
+                    }
+                }
+            }
+        }
+        if !doomed.is_empty() {
+            Logger::info(&format!("Retention removed {} media files", doomed.len()));
+        }
+        doomed.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(id: &str, created_at: u64, bytes: u64) -> MediaFile {
+        MediaFile {
+            id: id.to_string(),
+            object: "media".to_string(),
+            kind: MediaKind::Image,
+            filename: format!("{}.png", id),
+            content_type: "image/png".to_string(),
+            bytes,
+            created_at,
+            url: format!("/v1/media/{}", id),
+            source_url: None,
+        }
+    }
+
+    #[test]
+    fn test_expired_by_age_and_size() {
+        let files = [file("a", 100, 50), file("b", 200, 50), file("c", 300, 50)];
+        let refs: Vec<&MediaFile> = files.iter().collect();
+
+        assert!(expired(&refs, 1_000, RetentionPolicy::default()).is_empty());
+        let by_age = RetentionPolicy {
+            max_age: Some(750),
+            ..Default::default()
+        };
+        assert_eq!(expired(&refs, 1_000, by_age), vec!["a", "b"]);
+        let by_size = RetentionPolicy {
+            max_total_bytes: Some(100),
+            ..Default::default()
+        };
+        assert_eq!(expired(&refs, 1_000, by_size), vec!["a"]);
+        let both = RetentionPolicy {
+            max_age: Some(850),
+            max_total_bytes: Some(60),
+        };
+        assert_eq!(expired(&refs, 1_000, both), vec!["a", "b"]);
+    }
+}
@@ -0,0 +1,177 @@
+use serde::{Deserialize, Serialize};
+
+/// Provider conversation handles right after a message was answered, so a
+/// later turn can continue the upstream conversation from that message
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpstreamCursor {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub qwen_chat_id: Option<String>,
+    /// Qwen `parent_id` of the next message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub qwen_parent_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub qwen_account: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deepseek_session_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deepseek_message_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grok_conversation_id: Option<String>,
+    /// Grok `parentResponseId` of the next message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grok_parent_response_id: Option<String>,
+    /// ChatGPT `parent_message_id` of the next message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chatgpt_parent_message_id: Option<String>,
+}
+
+/// One message of a thread. Messages form a tree: editing or regenerating a
+/// message adds a sibling under the same parent.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageNode {
+    /// Empty for threads stored before messages had ids, see [`normalize`]
+    #[serde(default)]
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<String>,
+    pub role: String,
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<u64>,
+    /// Uploaded files sent along with the message
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub file_ids: Vec<String>,
+    /// The answer was cut short by a cancelled response
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub incomplete: bool,
+    /// Set on assistant messages answered by a provider
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upstream: Option<UpstreamCursor>,
+}
+
+impl MessageNode {
+    pub fn new(parent_id: Option<String>, role: String, content: String) -> Self {
+        Self {
+            id: format!("msg_{}", uuid::Uuid::new_v4().simple()),
+            parent_id,
+            role,
+            content,
+            created_at: Some(
+                std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs(),
+            ),
+            file_ids: Vec::new(),
+            incomplete: false,
+            upstream: None,
+        }
+    }
+}
+
+/// Give the messages of a thread stored as a flat list the ids they were
+/// listed under (`msg_<thread>_<index>`) and chain each to the one before it.
+/// Returns whether anything changed.
+pub fn normalize(nodes: &mut [MessageNode], thread_id: &str) -> bool {
+    let mut changed = false;
+    for idx in 0..nodes.len() {
+        if !nodes[idx].id.is_empty() {
+            continue;
+        }
+        nodes[idx].id = format!("msg_{}_{}", thread_id, idx);
+        if idx > 0 && nodes[idx].parent_id.is_none() {
+            nodes[idx].parent_id = Some(nodes[idx - 1].id.clone());
+        }
+        changed = true;
+    }
+    changed
+}
+
+pub fn find<'a>(nodes: &'a [MessageNode], id: &str) -> Option<&'a MessageNode> {
+    nodes.iter().find(|node| node.id == id)
+}
+
+/// Messages from the root down to `leaf`
+pub fn branch<'a>(nodes: &'a [MessageNode], leaf: Option<&str>) -> Vec<&'a MessageNode> {
+    let mut path = Vec::new();
+    let mut next = leaf;
+    // Bounded so a corrupted record cannot loop forever
+    while let Some(id) = next.filter(|_| path.len() < nodes.len()) {
+        let Some(node) = find(nodes, id) else {
+            break;
+        };
+        path.push(node);
+        next = node.parent_id.as_deref();
+    }
+    path.reverse();
+    path
+}
+
+/// Messages sharing `parent_id`, oldest first
+pub fn children<'a>(nodes: &'a [MessageNode], parent_id: Option<&str>) -> Vec<&'a MessageNode> {
+    nodes
+        .iter()
+        .filter(|node| node.parent_id.as_deref() == parent_id)
+        .collect()
+}
+
+/// Leaf reached from `id` by always following the newest reply
+pub fn latest_leaf<'a>(nodes: &'a [MessageNode], id: &'a str) -> &'a str {
+    let mut leaf = id;
+    for _ in 0..nodes.len() {
+        match children(nodes, Some(leaf)).last() {
+            Some(child) => leaf = &child.id,
+            None => break,
+        }
+    }
+    leaf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(id: &str, parent_id: Option<&str>, role: &str) -> MessageNode {
+        MessageNode {
+            id: id.to_string(),
+            parent_id: parent_id.map(str::to_string),
+            role: role.to_string(),
+            content: id.to_string(),
+            created_at: None,
+            file_ids: Vec::new(),
+            incomplete: false,
+            upstream: None,
+        }
+    }
+
+    #[test]
+    fn test_normalize_flat_thread() {
+        let mut nodes = vec![node("", None, "user"), node("", None, "assistant")];
+        assert!(normalize(&mut nodes, "t1"));
+        assert_eq!(nodes[0].id, "msg_t1_0");
+        assert_eq!(nodes[0].parent_id, None);
+        assert_eq!(nodes[1].id, "msg_t1_1");
+        assert_eq!(nodes[1].parent_id.as_deref(), Some("msg_t1_0"));
+        assert!(!normalize(&mut nodes, "t1"));
+    }
+
+    #[test]
+    fn test_branches() {
+        // u1 -> a1, and u1 -> a2 -> u2 after regenerating a1
+        let nodes = vec![
+            node("u1", None, "user"),
+            node("a1", Some("u1"), "assistant"),
+            node("a2", Some("u1"), "assistant"),
+            node("u2", Some("a2"), "user"),
+        ];
+        let ids = |path: Vec<&MessageNode>| -> Vec<String> {
+            path.into_iter().map(|node| node.id.clone()).collect()
+        };
+        assert_eq!(ids(branch(&nodes, Some("u2"))), ["u1", "a2", "u2"]);
+        assert_eq!(ids(branch(&nodes, Some("a1"))), ["u1", "a1"]);
+        assert!(branch(&nodes, None).is_empty());
+        assert_eq!(ids(children(&nodes, Some("u1"))), ["a1", "a2"]);
+        assert_eq!(latest_leaf(&nodes, "u1"), "u2");
+        assert_eq!(latest_leaf(&nodes, "a1"), "a1");
+    }
+}
@@ -0,0 +1,638 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::{extract::State, http::header::CONTENT_TYPE, response::IntoResponse};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use super::error::ApiError;
+use super::logger::PerformanceMetric;
+use super::state::AppState;
+
+/// Upper bounds, in seconds, of the latency histogram buckets
+const BUCKETS: [f64; 12] = [
+    0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 20.0, 30.0, 60.0, 120.0,
+];
+
+#[derive(Default, Clone)]
+struct Histogram {
+    counts: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        for (bound, count) in BUCKETS.iter().zip(self.counts.iter_mut()) {
+            if secs <= *bound {
+                *count += 1;
+            }
+        }
+        self.sum += secs;
+        self.count += 1;
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    /// (route, method, status)
+    http_requests: BTreeMap<(String, String, u16), u64>,
+    /// (route, method)
+    http_duration: BTreeMap<(String, String), Histogram>,
+    http_in_flight: BTreeMap<String, i64>,
+    /// (provider, model, outcome)
+    upstream_requests: BTreeMap<(String, String, &'static str), u64>,
+    /// (provider, model)
+    upstream_duration: BTreeMap<(String, String), Histogram>,
+    /// (provider, kind)
+    upstream_errors: BTreeMap<(String, &'static str), u64>,
+    upstream_in_flight: BTreeMap<String, i64>,
+    /// (provider, model)
+    time_to_first_token: BTreeMap<(String, String), Histogram>,
+    /// Models that get their own label; any other model is counted as `other`
+    known_models: BTreeSet<String>,
+}
+
+/// Upstream calls to one model since startup
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ModelUsage {
+    pub provider: String,
+    pub model: String,
+    pub requests: u64,
+    pub errors: u64,
+    pub average_ms: u64,
+    /// Only for calls that streamed tokens
+    pub average_first_token_ms: Option<u64>,
+}
+
+/// Upstream calls to one provider since startup
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ProviderUsage {
+    pub provider: String,
+    pub requests: u64,
+    pub errors: u64,
+    pub in_flight: i64,
+    pub average_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ErrorCount {
+    /// Provider for upstream errors, route for HTTP errors
+    pub source: String,
+    /// Error kind for upstream errors, status code for HTTP errors
+    pub kind: String,
+    pub count: u64,
+}
+
+/// What the dashboard shows of the metrics
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct MetricsSummary {
+    pub providers: Vec<ProviderUsage>,
+    pub models: Vec<ModelUsage>,
+    pub upstream_errors: Vec<ErrorCount>,
+    pub http_errors: Vec<ErrorCount>,
+}
+
+fn provider_usage<'a>(
+    providers: &'a mut BTreeMap<String, ProviderUsage>,
+    provider: &str,
+) -> &'a mut ProviderUsage {
+    providers
+        .entry(provider.to_string())
+        .or_insert_with(|| ProviderUsage {
+            provider: provider.to_string(),
+            requests: 0,
+            errors: 0,
+            in_flight: 0,
+            average_ms: 0,
+        })
+}
+
+/// Counters and histograms exported on `/metrics`, kept by the `StatsCollector`
+#[derive(Clone, Default)]
+pub struct Metrics {
+    registry: Arc<Mutex<Registry>>,
+}
+
+/// Group an upstream failure into a small, fixed set of label values
+pub fn error_kind(error: &ApiError) -> &'static str {
+    let message = error.message.to_lowercase();
+    if error.status.as_u16() == 429
+        || ["(429", "rate limit", "ratelimit", "too many requests"]
+            .iter()
+            .any(|n| message.contains(n))
+    {
+        "rate_limit"
+    } else if ["(401", "(403", "unauthorized", "login failed"]
+        .iter()
+        .any(|n| message.contains(n))
+    {
+        "auth"
+    } else if message.contains("timed out") || message.contains("timeout") {
+        "timeout"
+    } else if error.status.is_client_error() {
+        "client"
+    } else {
+        "upstream"
+    }
+}
+
+impl Metrics {
+    fn registry(&self) -> std::sync::MutexGuard<'_, Registry> {
+        self.registry.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Count an HTTP request as started on `route`
+    pub fn http_started(&self, route: &str) {
+        *self
+            .registry()
+            .http_in_flight
+            .entry(route.to_string())
+            .or_default() += 1;
+    }
+
+    pub fn http_finished(&self, route: &str, method: &str, status: u16, duration: Duration) {
+        let mut registry = self.registry();
+        *registry
+            .http_in_flight
+            .entry(route.to_string())
+            .or_default() -= 1;
+        *registry
+            .http_requests
+            .entry((route.to_string(), method.to_string(), status))
+            .or_default() += 1;
+        registry
+            .http_duration
+            .entry((route.to_string(), method.to_string()))
+            .or_default()
+            .observe(duration);
+    }
+
+    /// Upstream calls currently running, across providers
+    pub fn upstream_in_flight(&self) -> i64 {
+        self.registry().upstream_in_flight.values().sum()
+    }
+
+    /// Give these models their own `model` label
+    pub fn register_models<'a>(&self, models: impl IntoIterator<Item = &'a str>) {
+        self.registry()
+            .known_models
+            .extend(models.into_iter().map(str::to_string));
+    }
+
+    /// Start timing one upstream call; finish it with [`UpstreamCall::finish`].
+    /// `model` comes from the caller, so unregistered models share the
+    /// `other` label.
+    pub fn upstream_call(&self, provider: &str, model: &str) -> UpstreamCall {
+        let mut registry = self.registry();
+        *registry
+            .upstream_in_flight
+            .entry(provider.to_string())
+            .or_default() += 1;
+        let model = if registry.known_models.contains(model) {
+            model
+        } else {
+            "other"
+        };
+        UpstreamCall {
+            metrics: self.clone(),
+            provider: provider.to_string(),
+            model: model.to_string(),
+            started: Instant::now(),
+            first_token: Mutex::new(None),
+        }
+    }
+
+    /// Totals per provider and model, and errors by kind
+    pub fn summary(&self) -> MetricsSummary {
+        let registry = self.registry();
+        let millis = |histogram: &Histogram| {
+            (histogram.sum * 1000.0 / histogram.count.max(1) as f64).round() as u64
+        };
+
+        let mut models: BTreeMap<(String, String), ModelUsage> = BTreeMap::new();
+        for ((provider, model, outcome), count) in &registry.upstream_requests {
+            let usage = models
+                .entry((provider.clone(), model.clone()))
+                .or_insert_with(|| ModelUsage {
+                    provider: provider.clone(),
+                    model: model.clone(),
+                    requests: 0,
+                    errors: 0,
+                    average_ms: 0,
+                    average_first_token_ms: None,
+                });
+            usage.requests += count;
+            if *outcome == "error" {
+                usage.errors += count;
+            }
+        }
+        for (key, usage) in models.iter_mut() {
+            usage.average_ms = registry.upstream_duration.get(key).map_or(0, millis);
+            usage.average_first_token_ms = registry.time_to_first_token.get(key).map(millis);
+        }
+
+        let mut providers: BTreeMap<String, ProviderUsage> = BTreeMap::new();
+        let mut total_ms: BTreeMap<String, f64> = BTreeMap::new();
+        for ((provider, model), usage) in &models {
+            let entry = provider_usage(&mut providers, provider);
+            entry.requests += usage.requests;
+            entry.errors += usage.errors;
+            if let Some(histogram) = registry
+                .upstream_duration
+                .get(&(provider.clone(), model.clone()))
+            {
+                *total_ms.entry(provider.clone()).or_default() += histogram.sum * 1000.0;
+            }
+        }
+        for (provider, in_flight) in &registry.upstream_in_flight {
+            provider_usage(&mut providers, provider).in_flight = *in_flight;
+        }
+        for usage in providers.values_mut() {
+            let total = total_ms.get(&usage.provider).copied().unwrap_or(0.0);
+            usage.average_ms = (total / usage.requests.max(1) as f64).round() as u64;
+        }
+
+        let upstream_errors = registry
+            .upstream_errors
+            .iter()
+            .map(|((provider, kind), count)| ErrorCount {
+                source: provider.clone(),
+                kind: kind.to_string(),
+                count: *count,
+            })
+            .collect();
+        let mut http_errors: BTreeMap<(String, u16), u64> = BTreeMap::new();
+        for ((route, _, status), count) in &registry.http_requests {
+            if *status >= 400 {
+                *http_errors.entry((route.clone(), *status)).or_default() += count;
+            }
+        }
+
+        MetricsSummary {
+            providers: providers.into_values().collect(),
+            models: models.into_values().collect(),
+            upstream_errors,
+            http_errors: http_errors
+                .into_iter()
+                .map(|((route, status), count)| ErrorCount {
+                    source: route,
+                    kind: status.to_string(),
+                    count,
+                })
+                .collect(),
+        }
+    }
+
+    /// Render every metric in the Prometheus text exposition format
+    pub fn render(&self, performance: &[PerformanceMetric]) -> String {
+        let registry = self.registry();
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "chat2api_http_requests_total",
+            "counter",
+            "HTTP requests by route, method and status",
+        );
+        for ((route, method, status), value) in &registry.http_requests {
+            let labels = labels(&[
+                ("route", route),
+                ("method", method),
+                ("status", &status.to_string()),
+            ]);
+            let _ = writeln!(out, "chat2api_http_requests_total{{{}}} {}", labels, value);
+        }
+
+        header(
+            &mut out,
+            "chat2api_http_request_duration_seconds",
+            "histogram",
+            "HTTP request latency by route and method",
+        );
+        for ((route, method), histogram) in &registry.http_duration {
+            let base = labels(&[("route", route), ("method", method)]);
+            write_histogram(
+                &mut out,
+                "chat2api_http_request_duration_seconds",
+                &base,
+                histogram,
+            );
+        }
+
+        header(
+            &mut out,
+            "chat2api_http_requests_in_flight",
+            "gauge",
+            "HTTP requests currently being served",
+        );
+        for (route, value) in &registry.http_in_flight {
+            let labels = labels(&[("route", route)]);
+            let _ = writeln!(
+                out,
+                "chat2api_http_requests_in_flight{{{}}} {}",
+                labels, value
+            );
+        }
+
+        header(
+            &mut out,
+            "chat2api_http_success_ratio",
+            "gauge",
+            "Share of non-error responses per endpoint since startup",
+        );
+        for metric in performance {
+            let labels = labels(&[("route", &metric.endpoint), ("method", &metric.method)]);
+            let _ = writeln!(
+                out,
+                "chat2api_http_success_ratio{{{}}} {}",
+                labels,
+                metric.success_rate / 100.0
+            );
+        }
+
+        header(
+            &mut out,
+            "chat2api_upstream_requests_total",
+            "counter",
+            "Upstream provider calls by provider, model and outcome",
+        );
+        for ((provider, model, outcome), value) in &registry.upstream_requests {
+            let labels = labels(&[
+                ("provider", provider),
+                ("model", model),
+                ("outcome", outcome),
+            ]);
+            let _ = writeln!(
+                out,
+                "chat2api_upstream_requests_total{{{}}} {}",
+                labels, value
+            );
+        }
+
+        header(
+            &mut out,
+            "chat2api_upstream_request_duration_seconds",
+            "histogram",
+            "Upstream provider call latency by provider and model",
+        );
+        for ((provider, model), histogram) in &registry.upstream_duration {
+            let base = labels(&[("provider", provider), ("model", model)]);
+            write_histogram(
+                &mut out,
+                "chat2api_upstream_request_duration_seconds",
+                &base,
+                histogram,
+            );
+        }
+
+        header(
+            &mut out,
+            "chat2api_upstream_errors_total",
+            "counter",
+            "Failed upstream provider calls by provider and error kind",
+        );
+        for ((provider, kind), value) in &registry.upstream_errors {
+            let labels = labels(&[("provider", provider), ("kind", kind)]);
+            let _ = writeln!(
+                out,
+                "chat2api_upstream_errors_total{{{}}} {}",
+                labels, value
+            );
+        }
+
+        header(
+            &mut out,
+            "chat2api_upstream_requests_in_flight",
+            "gauge",
+            "Upstream provider calls currently running",
+        );
+        for (provider, value) in &registry.upstream_in_flight {
+            let labels = labels(&[("provider", provider)]);
+            let _ = writeln!(
+                out,
+                "chat2api_upstream_requests_in_flight{{{}}} {}",
+                labels, value
+            );
+        }
+
+        header(
+            &mut out,
+            "chat2api_time_to_first_token_seconds",
+            "histogram",
+            "Time from dispatching a call until the first streamed token",
+        );
+        for ((provider, model), histogram) in &registry.time_to_first_token {
+            let base = labels(&[("provider", provider), ("model", model)]);
+            write_histogram(
+                &mut out,
+                "chat2api_time_to_first_token_seconds",
+                &base,
+                histogram,
+            );
+        }
+
+        out
+    }
+}
+
+/// One in-progress upstream call; it leaves the in-flight gauge when dropped
+pub struct UpstreamCall {
+    metrics: Metrics,
+    provider: String,
+    model: String,
+    started: Instant,
+    first_token: Mutex<Option<Duration>>,
+}
+
+impl UpstreamCall {
+    /// Note that a token arrived; only the first one is kept
+    pub fn token(&self) {
+        let mut first = self.first_token.lock().unwrap_or_else(|e| e.into_inner());
+        if first.is_none() {
+            *first = Some(self.started.elapsed());
+        }
+    }
+
+    pub fn finish(self, error: Option<&ApiError>) {
+        let key = (self.provider.clone(), self.model.clone());
+        let first_token = *self.first_token.lock().unwrap_or_else(|e| e.into_inner());
+        let mut registry = self.metrics.registry();
+        let outcome = if error.is_some() { "error" } else { "success" };
+        *registry
+            .upstream_requests
+            .entry((self.provider.clone(), self.model.clone(), outcome))
+            .or_default() += 1;
+        if let Some(error) = error {
+            *registry
+                .upstream_errors
+                .entry((self.provider.clone(), error_kind(error)))
+                .or_default() += 1;
+        }
+        registry
+            .upstream_duration
+            .entry(key.clone())
+            .or_default()
+            .observe(self.started.elapsed());
+        if let Some(ttft) = first_token {
+            registry
+                .time_to_first_token
+                .entry(key)
+                .or_default()
+                .observe(ttft);
+        }
+    }
+}
+
+impl Drop for UpstreamCall {
+    fn drop(&mut self) {
+        *self
+            .metrics
+            .registry()
+            .upstream_in_flight
+            .entry(self.provider.clone())
+            .or_default() -= 1;
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn labels(pairs: &[(&str, &str)]) -> String {
+    pairs
+        .iter()
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", name, value)
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn write_histogram(out: &mut String, name: &str, base: &str, histogram: &Histogram) {
+    for (bound, count) in BUCKETS.iter().zip(histogram.counts.iter()) {
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{},le=\"{}\"}} {}",
+            name, base, bound, count
+        );
+    }
+    let _ = writeln!(
+        out,
+        "{}_bucket{{{},le=\"+Inf\"}} {}",
+        name, base, histogram.count
+    );
+    let _ = writeln!(out, "{}_sum{{{}}} {}", name, base, histogram.sum);
+    let _ = writeln!(out, "{}_count{{{}}} {}", name, base, histogram.count);
+}
+
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "monitoring",
+    summary = "Prometheus 指标",
+    security(()),
+    responses(
+        (status = 200, description = "Prometheus 文本格式的指标", content_type = "text/plain")
+    )
+)]
+pub async fn metrics(State(state): State<AppState>) -> impl IntoResponse {
+    let body = state.render_metrics().await;
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8")],
+        body,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+
+    #[test]
+    fn test_render_prometheus_text() {
+        let metrics = Metrics::default();
+        metrics.register_models(["qwen3-max"]);
+        metrics.http_started("/v1/chat/completions");
+        metrics.http_finished(
+            "/v1/chat/completions",
+            "POST",
+            200,
+            Duration::from_millis(300),
+        );
+
+        let call = metrics.upstream_call("qwen", "qwen3-max");
+        call.token();
+        call.finish(Some(&ApiError::internal_error(
+            "Chat completion failed (429 Too Many Requests)",
+        )));
+
+        let text = metrics.render(&[]);
+        assert!(text.contains(
+            "chat2api_http_requests_total{route=\"/v1/chat/completions\",method=\"POST\",status=\"200\"} 1"
+        ));
+        assert!(text.contains(
+            "chat2api_http_request_duration_seconds_bucket{route=\"/v1/chat/completions\",method=\"POST\",le=\"0.25\"} 0"
+        ));
+        assert!(text.contains(
+            "chat2api_http_request_duration_seconds_bucket{route=\"/v1/chat/completions\",method=\"POST\",le=\"0.5\"} 1"
+        ));
+        assert!(text.contains("chat2api_http_requests_in_flight{route=\"/v1/chat/completions\"} 0"));
+        assert!(text
+            .contains("chat2api_upstream_errors_total{provider=\"qwen\",kind=\"rate_limit\"} 1"));
+        assert!(text.contains("chat2api_upstream_requests_in_flight{provider=\"qwen\"} 0"));
+        assert!(text.contains(
+            "chat2api_time_to_first_token_seconds_count{provider=\"qwen\",model=\"qwen3-max\"} 1"
+        ));
+
+        let summary = metrics.summary();
+        assert_eq!(summary.providers.len(), 1);
+        assert_eq!(summary.providers[0].requests, 1);
+        assert_eq!(summary.providers[0].errors, 1);
+        assert_eq!(summary.models[0].model, "qwen3-max");
+        assert!(summary.models[0].average_first_token_ms.is_some());
+        assert_eq!(summary.upstream_errors[0].kind, "rate_limit");
+        assert!(summary.http_errors.is_empty());
+
+        metrics.upstream_call("qwen", "qwen-made-up").finish(None);
+        let text = metrics.render(&[]);
+        assert!(!text.contains("qwen-made-up"));
+        assert!(text.contains(
+            "chat2api_upstream_requests_total{provider=\"qwen\",model=\"other\",outcome=\"success\"} 1"
+        ));
+    }
+
+    #[test]
+    fn test_error_kind() {
+        let error = |status, message: &str| ApiError {
+            status,
+            message: message.to_string(),
+        };
+        assert_eq!(
+            error_kind(&error(StatusCode::BAD_REQUEST, "Unsupported model")),
+            "client"
+        );
+        assert_eq!(
+            error_kind(&error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Login failed (401)"
+            )),
+            "auth"
+        );
+        assert_eq!(
+            error_kind(&error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Grok error: boom"
+            )),
+            "upstream"
+        );
+    }
+}
@@ -1,9 +1,28 @@
+pub mod anthropic;
+pub mod auth;
+pub mod cancellation;
+pub mod config;
 pub mod dashboard;
 pub mod docs;
 pub mod error;
+pub mod export;
 pub mod handlers;
+pub mod images;
+pub mod jobs;
 pub mod logger;
+pub mod media;
+pub mod message_tree;
+pub mod metrics;
+pub mod openapi;
+pub mod pagination;
+pub mod providers;
+pub mod qwen_pool;
+pub mod rate_limit;
 pub mod server;
+pub mod shutdown;
 pub mod state;
 pub mod stats;
+pub mod storage;
+pub mod telemetry;
 pub mod types;
+pub mod websocket;
@@ -0,0 +1,55 @@
+//! OpenAPI document of the server. Operations come from the
+//! `#[utoipa::path]` attribute of each handler as it is routed, so the
+//! document cannot miss or misplace a route.
+
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::openapi::OpenApi as OpenApiDocument;
+use utoipa::{Modify, OpenApi};
+
+use super::export::ExportFormat;
+use super::types::SortOrder;
+
+/// Everything but the paths, which the router fills in
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Reverse-API",
+        description = "统一的多模型 AI API 接口（Qwen、DeepSeek、Grok、ChatGPT）。\n\n配置了 API Key 时，`/v1` 下的接口需要 `Authorization: Bearer <key>` 或 `x-api-key` 头，缺少或无效的 Key 返回 401，超出限额返回 429 并带有 `Retry-After` 头；请求体超过 2 MB 返回 413；未配置 Key 时所有接口都无需认证。错误响应的格式为 `{\"status\": \"error\", \"detail\": \"...\"}`。"
+    ),
+    // Only referenced from query parameters, which do not collect schemas
+    components(schemas(SortOrder, ExportFormat)),
+    modifiers(&ApiKeyAuth),
+    security(("bearer" = []), ("api_key" = [])),
+    tags(
+        (name = "threads", description = "线程与消息"),
+        (name = "responses", description = "响应生成"),
+        (name = "compat", description = "OpenAI / Anthropic 兼容接口"),
+        (name = "media", description = "多模态与媒体文件"),
+        (name = "jobs", description = "后台任务"),
+        (name = "config", description = "账号配置与模型"),
+        (name = "monitoring", description = "监控和统计")
+    )
+)]
+pub struct ApiDoc;
+
+/// The ways a client can send its API key
+struct ApiKeyAuth;
+
+impl Modify for ApiKeyAuth {
+    fn modify(&self, openapi: &mut OpenApiDocument) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("x-api-key"))),
+        );
+        // Only for the dashboard event stream and data, see `require_admin_key`
+        components.add_security_scheme(
+            "api_key_query",
+            SecurityScheme::ApiKey(ApiKey::Query(ApiKeyValue::new("api_key"))),
+        );
+    }
+}
@@ -0,0 +1,148 @@
+use super::error::ApiError;
+use super::types::SortOrder;
+
+pub const DEFAULT_LIMIT: usize = 20;
+pub const MAX_LIMIT: usize = 100;
+
+/// OpenAI-style list parameters shared by the list endpoints
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PageRequest<'a> {
+    pub limit: Option<usize>,
+    pub order: SortOrder,
+    /// Return items following this id, in list order
+    pub after: Option<&'a str>,
+    /// Return items preceding this id, in list order
+    pub before: Option<&'a str>,
+}
+
+/// One page of a list plus whether more items follow in the paging direction
+#[derive(Debug)]
+pub struct Page<T> {
+    pub data: Vec<T>,
+    pub has_more: bool,
+}
+
+/// Cut one page out of `items`, which must be sorted oldest first.
+///
+/// Cursors are item ids; an id that is not in the list is rejected rather
+/// than silently restarting from the top.
+pub fn paginate<T>(
+    mut items: Vec<T>,
+    id: impl Fn(&T) -> &str,
+    request: PageRequest<'_>,
+) -> Result<Page<T>, ApiError> {
+    let limit = request.limit.unwrap_or(DEFAULT_LIMIT);
+    if limit == 0 || limit > MAX_LIMIT {
+        return Err(ApiError::bad_request(format!(
+            "'limit' must be between 1 and {}",
+            MAX_LIMIT
+        )));
+    }
+    if request.order == SortOrder::Desc {
+        items.reverse();
+    }
+
+    let position = |cursor: &str, items: &[T]| {
+        items
+            .iter()
+            .position(|item| id(item) == cursor)
+            .ok_or_else(|| ApiError::bad_request(format!("Unknown cursor: {}", cursor)))
+    };
+
+    if let Some(after) = request.after {
+        let start = position(after, &items)? + 1;
+        items.drain(..start);
+    }
+    if let Some(before) = request.before {
+        let end = position(before, &items)?;
+        items.truncate(end);
+        // Paging backwards: keep the items closest to the cursor
+        let has_more = items.len() > limit;
+        let data = items.split_off(items.len().saturating_sub(limit));
+        return Ok(Page { data, has_more });
+    }
+
+    let has_more = items.len() > limit;
+    items.truncate(limit);
+    Ok(Page {
+        data: items,
+        has_more,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(n: usize) -> Vec<String> {
+        (1..=n).map(|i| format!("t{}", i)).collect()
+    }
+
+    fn page(request: PageRequest<'_>) -> (Vec<String>, bool) {
+        let page = paginate(ids(5), |s| s.as_str(), request).unwrap();
+        (page.data, page.has_more)
+    }
+
+    #[test]
+    fn test_forward_pages() {
+        let request = PageRequest {
+            limit: Some(2),
+            order: SortOrder::Asc,
+            ..Default::default()
+        };
+        assert_eq!(page(request), (vec!["t1".into(), "t2".into()], true));
+        assert_eq!(
+            page(PageRequest {
+                after: Some("t4"),
+                ..request
+            }),
+            (vec!["t5".into()], false)
+        );
+        // Newest first by default
+        assert_eq!(
+            page(PageRequest {
+                limit: Some(2),
+                ..Default::default()
+            }),
+            (vec!["t5".into(), "t4".into()], true)
+        );
+    }
+
+    #[test]
+    fn test_before_and_errors() {
+        let request = PageRequest {
+            limit: Some(2),
+            order: SortOrder::Asc,
+            before: Some("t4"),
+            ..Default::default()
+        };
+        assert_eq!(page(request), (vec!["t2".into(), "t3".into()], true));
+        assert_eq!(
+            page(PageRequest {
+                after: Some("t1"),
+                before: Some("t3"),
+                ..request
+            }),
+            (vec!["t2".into()], false)
+        );
+
+        assert!(paginate(
+            ids(5),
+            |s| s.as_str(),
+            PageRequest {
+                after: Some("gone"),
+                ..Default::default()
+            }
+        )
+        .is_err());
+        assert!(paginate(
+            ids(5),
+            |s| s.as_str(),
+            PageRequest {
+                limit: Some(0),
+                ..Default::default()
+            }
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,607 @@
+use std::sync::Arc;
+
+use reverse_api::qwen::models::{ConvoOptions, ExtraData as QwenExtraData, StreamEvent};
+use reverse_api::{CancelToken, ChatGptClient, DeepSeekClient, DeepSeekExtraData, Grok, Logger};
+use tokio::sync::Mutex;
+use tracing::Instrument;
+
+use super::error::ApiError;
+use super::state::{AppState, ThreadState};
+
+/// Upstream backend selected from a model id
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Provider {
+    Qwen,
+    DeepSeek,
+    Grok,
+    ChatGpt,
+}
+
+impl Provider {
+    pub fn from_model(model: &str) -> Option<Self> {
+        if model.starts_with("qwen") {
+            Some(Provider::Qwen)
+        } else if model.starts_with("deepseek") {
+            Some(Provider::DeepSeek)
+        } else if model.starts_with("grok") {
+            Some(Provider::Grok)
+        } else if model.starts_with("chatgpt") || model.starts_with("gpt-") {
+            Some(Provider::ChatGpt)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Provider::Qwen => "qwen",
+            Provider::DeepSeek => "deepseek",
+            Provider::Grok => "grok",
+            Provider::ChatGpt => "chatgpt",
+        }
+    }
+}
+
+pub fn unsupported_model(model: &str) -> ApiError {
+    ApiError::bad_request(format!(
+        "Unsupported model: {}. Use 'qwen-*', 'deepseek*', 'grok-*' or 'chatgpt-*'",
+        model
+    ))
+}
+
+/// The image sent along with a one-shot completion. Only ChatGPT takes
+/// images, one per request; anything else is rejected rather than dropped.
+pub fn completion_image(model: &str, mut images: Vec<String>) -> Result<Option<String>, ApiError> {
+    if images.is_empty() {
+        return Ok(None);
+    }
+    let provider = Provider::from_model(model).ok_or_else(|| unsupported_model(model))?;
+    if provider != Provider::ChatGpt {
+        return Err(ApiError::bad_request(format!(
+            "Image input is not supported for {} models",
+            provider.name()
+        )));
+    }
+    if images.len() > 1 {
+        return Err(ApiError::bad_request(
+            "Only one image per request is supported",
+        ));
+    }
+    Ok(images.pop())
+}
+
+/// A single conversation turn handed to a provider
+#[derive(Debug, Clone)]
+pub struct PromptMessage {
+    pub role: String,
+    pub content: String,
+}
+
+/// Final answer produced by a provider
+#[derive(Debug, Clone, Default)]
+pub struct Completion {
+    pub content: String,
+    pub thinking: Option<String>,
+    /// Cut short by a cancelled request
+    pub incomplete: bool,
+}
+
+/// Collapse a chat history into one prompt, since the upstream web clients
+/// only accept a single user message per turn.
+pub fn flatten_messages(messages: &[PromptMessage]) -> String {
+    if let [only] = messages {
+        if only.role == "user" {
+            return only.content.clone();
+        }
+    }
+
+    let mut prompt = String::new();
+    for message in messages {
+        let label = match message.role.as_str() {
+            "system" | "developer" => "System",
+            "assistant" => "Assistant",
+            "tool" => "Tool",
+            _ => "User",
+        };
+        if !prompt.is_empty() {
+            prompt.push_str("\n\n");
+        }
+        prompt.push_str(label);
+        prompt.push_str(": ");
+        prompt.push_str(&message.content);
+    }
+    prompt
+}
+
+async fn deepseek_client(state: &AppState, tenant: &str) -> Result<Arc<DeepSeekClient>, ApiError> {
+    state.get_deepseek_client(tenant).await.ok_or_else(|| {
+        ApiError::bad_request(
+            "DeepSeek token not configured. Please configure it via POST /v1/config/deepseek",
+        )
+    })
+}
+
+/// Optional inputs of a one-shot completion, ignored by providers that lack them
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CompletionOptions<'a> {
+    /// Image URL or data URL sent along with the prompt (ChatGPT)
+    pub image: Option<&'a str>,
+    /// Ask for reasoning, reported as `StreamEvent::Thinking` (Qwen)
+    pub thinking: bool,
+    pub thinking_budget: Option<u32>,
+    /// Stops the Qwen, DeepSeek and Grok readers early
+    pub cancel: Option<&'a CancelToken>,
+}
+
+/// Run a one-shot completion against a fresh upstream conversation.
+///
+/// Qwen and DeepSeek report deltas through `on_event` as they stream in; the
+/// other providers only return the full answer, which is reported as one delta.
+pub async fn complete<F>(
+    state: &AppState,
+    tenant: &str,
+    model: &str,
+    prompt: &str,
+    options: CompletionOptions<'_>,
+    on_event: F,
+) -> Result<Completion, ApiError>
+where
+    F: Fn(StreamEvent) + Send + Sync,
+{
+    let provider = Provider::from_model(model).ok_or_else(|| unsupported_model(model))?;
+    let call = state.metrics().upstream_call(provider.name(), model);
+    let span = tracing::info_span!("upstream", provider = provider.name(), model);
+    let result = complete_with(state, tenant, provider, model, prompt, options, |event| {
+        if !matches!(event, StreamEvent::WebSearch(_)) {
+            call.token();
+        }
+        on_event(event)
+    })
+    .instrument(span)
+    .await;
+    call.finish(result.as_ref().err());
+    result
+}
+
+async fn complete_with<F>(
+    state: &AppState,
+    tenant: &str,
+    provider: Provider,
+    model: &str,
+    prompt: &str,
+    options: CompletionOptions<'_>,
+    on_event: F,
+) -> Result<Completion, ApiError>
+where
+    F: Fn(StreamEvent) + Send + Sync,
+{
+    Logger::info(&format!(
+        "Dispatching {} completion to {}",
+        model,
+        provider.name()
+    ));
+
+    let completion = match provider {
+        Provider::Qwen => {
+            let (account, client) = state.checkout_qwen(tenant, None).await?;
+            let result = client
+                .start_convo_streaming(
+                    prompt,
+                    vec![],
+                    Some(model),
+                    None,
+                    ConvoOptions {
+                        enable_thinking: options.thinking,
+                        thinking_budget: options.thinking_budget,
+                        cancel: options.cancel.cloned(),
+                        ..Default::default()
+                    },
+                    on_event,
+                )
+                .await;
+            state.report_qwen(tenant, &account, &result).await;
+            let result =
+                result.map_err(|e| ApiError::internal_error(format!("Qwen error: {}", e)))?;
+            return Ok(Completion {
+                content: result.content,
+                thinking: result.thinking_content,
+                incomplete: result.incomplete,
+            });
+        }
+        Provider::DeepSeek => {
+            let client = deepseek_client(state, tenant).await?;
+            let result = client
+                .start_convo_cancellable(prompt, None, options.cancel, |delta| {
+                    on_event(StreamEvent::Answer(delta.to_string()))
+                })
+                .await
+                .map_err(|e| ApiError::internal_error(format!("DeepSeek error: {}", e)))?;
+            return Ok(Completion {
+                content: result.response.unwrap_or_default(),
+                incomplete: result.incomplete,
+                ..Default::default()
+            });
+        }
+        Provider::Grok => {
+            let mut grok = Grok::new(model, state.config().providers.grok.proxy.as_deref())
+                .map_err(|e| ApiError::internal_error(format!("Grok error: {}", e)))?;
+            let result = grok
+                .start_convo_cancellable(prompt, None, options.cancel)
+                .await
+                .map_err(|e| ApiError::internal_error(format!("Grok error: {}", e)))?;
+            Completion {
+                content: result.response.unwrap_or_default(),
+                incomplete: result.incomplete,
+                ..Default::default()
+            }
+        }
+        Provider::ChatGpt => {
+            let mut client = ChatGptClient::new(state.config().providers.chatgpt.proxy.as_deref())
+                .await
+                .map_err(|e| ApiError::internal_error(format!("ChatGPT error: {}", e)))?;
+            let content = match options.image {
+                Some(image) => client.start_with_image(prompt, image).await,
+                None => client.start_conversation(prompt).await,
+            }
+            .map_err(|e| ApiError::internal_error(format!("ChatGPT error: {}", e)))?;
+            Completion {
+                content,
+                ..Default::default()
+            }
+        }
+    };
+
+    on_event(StreamEvent::Answer(completion.content.clone()));
+    Ok(completion)
+}
+
+/// The latest user message of a thread plus per-response options
+pub struct TurnRequest<'a> {
+    pub thread_id: &'a str,
+    pub message: &'a str,
+    pub instructions: Option<&'a str>,
+    pub file_ids: Option<&'a [String]>,
+    /// Stops the Qwen, DeepSeek and Grok readers early
+    pub cancel: Option<&'a CancelToken>,
+}
+
+fn reject_files(turn: &TurnRequest<'_>, provider: &str) -> Result<(), ApiError> {
+    match turn.file_ids {
+        Some(file_ids) if !file_ids.is_empty() => Err(ApiError::bad_request(format!(
+            "file_ids are not supported for {} models",
+            provider
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Continue a thread's upstream conversation from its active branch and
+/// record the provider session handles back onto the thread.
+pub async fn run_thread_turn<F>(
+    state: &AppState,
+    thread: &mut ThreadState,
+    turn: TurnRequest<'_>,
+    on_event: F,
+) -> Result<Completion, ApiError>
+where
+    F: Fn(StreamEvent) + Send + Sync,
+{
+    let Some(provider) = Provider::from_model(&thread.model) else {
+        return Err(unsupported_model(&thread.model));
+    };
+    let call = state
+        .metrics()
+        .upstream_call(provider.name(), &thread.model);
+    let span = tracing::info_span!(
+        "upstream",
+        provider = provider.name(),
+        model = %thread.model,
+        thread_id = turn.thread_id,
+    );
+    thread.restore_cursor();
+    let result = thread_turn(state, thread, turn, |event| {
+        if !matches!(event, StreamEvent::WebSearch(_)) {
+            call.token();
+        }
+        on_event(event)
+    })
+    .instrument(span)
+    .await;
+    call.finish(result.as_ref().err());
+    result
+}
+
+async fn thread_turn<F>(
+    state: &AppState,
+    thread: &mut ThreadState,
+    turn: TurnRequest<'_>,
+    on_event: F,
+) -> Result<Completion, ApiError>
+where
+    F: Fn(StreamEvent) + Send + Sync,
+{
+    let model = thread.model.clone();
+    Logger::info(&format!("Using model: {}", model));
+
+    match Provider::from_model(&model) {
+        Some(Provider::Qwen) => {
+            Logger::info("Starting Qwen conversation");
+            let (account, client) = state
+                .checkout_qwen(&thread.tenant, thread.qwen_account.as_deref())
+                .await?;
+            if thread
+                .qwen_account
+                .as_ref()
+                .is_some_and(|pinned| *pinned != account)
+            {
+                // The chat lives on an account that is no longer registered
+                thread.qwen_chat_id = None;
+                thread.qwen_parent_id = None;
+            }
+
+            // Check for special instructions
+            let use_search = turn
+                .instructions
+                .map(|s| s.contains("search"))
+                .unwrap_or(false);
+            let use_thinking = turn
+                .instructions
+                .map(|s| s.contains("thinking"))
+                .unwrap_or(false);
+
+            // Build extra_data for continuous conversation
+            let extra_data = if let (Some(chat_id), Some(parent_id)) =
+                (&thread.qwen_chat_id, &thread.qwen_parent_id)
+            {
+                Some(QwenExtraData {
+                    chat_id: chat_id.clone(),
+                    model_id: model.clone(),
+                    parent_id: Some(parent_id.clone()),
+                })
+            } else {
+                None
+            };
+
+            let files = match turn.file_ids {
+                Some(file_ids) if !file_ids.is_empty() => {
+                    Logger::info(&format!("Using {} files with Qwen", file_ids.len()));
+                    let files = state.get_uploaded_files(&thread.tenant, file_ids).await;
+                    if files.is_empty() {
+                        return Err(ApiError::bad_request(
+                            "No valid files found for provided file_ids",
+                        ));
+                    }
+                    files
+                }
+                _ => vec![],
+            };
+
+            // Attachments let Qwen pick a capable model; otherwise honour the
+            // requested mode, with search taking precedence over thinking
+            let (model_id, options) = if !files.is_empty() {
+                (
+                    None,
+                    ConvoOptions {
+                        cancel: turn.cancel.cloned(),
+                        ..Default::default()
+                    },
+                )
+            } else {
+                if use_search {
+                    Logger::info("Using Qwen with search");
+                } else if use_thinking {
+                    Logger::info("Using Qwen with thinking");
+                }
+                (
+                    Some(model.as_str()),
+                    ConvoOptions {
+                        enable_search: use_search,
+                        enable_thinking: use_thinking && !use_search,
+                        thinking_budget: None,
+                        cancel: turn.cancel.cloned(),
+                    },
+                )
+            };
+
+            let result = client
+                .start_convo_streaming(
+                    turn.message,
+                    files,
+                    model_id,
+                    extra_data.as_ref(),
+                    options,
+                    on_event,
+                )
+                .await;
+            state.report_qwen(&thread.tenant, &account, &result).await;
+            let result =
+                result.map_err(|e| ApiError::internal_error(format!("Qwen error: {}", e)))?;
+
+            // Update thread state with Qwen session info for continuous
+            // conversation; a cancelled turn may end before Qwen names its reply
+            if let Some(chat_id) = &result.chat_id {
+                thread.qwen_chat_id = Some(chat_id.clone());
+                if !result.response_id.is_empty() {
+                    thread.qwen_parent_id = Some(result.response_id.clone());
+                }
+                thread.qwen_account = Some(account);
+            }
+
+            Ok(Completion {
+                content: result.content,
+                thinking: result.thinking_content,
+                incomplete: result.incomplete,
+            })
+        }
+        Some(Provider::DeepSeek) => {
+            Logger::info("Starting DeepSeek conversation");
+            reject_files(&turn, "DeepSeek")?;
+            let client = deepseek_client(state, &thread.tenant).await?;
+
+            // Continue the stored DeepSeek session, if any
+            let extra_data = if let (Some(session_id), Some(message_id)) =
+                (&thread.deepseek_session_id, &thread.deepseek_message_id)
+            {
+                Some(DeepSeekExtraData {
+                    session_id: session_id.clone(),
+                    message_id: message_id.clone(),
+                })
+            } else {
+                None
+            };
+
+            let result = client
+                .start_convo_cancellable(turn.message, extra_data.as_ref(), turn.cancel, |delta| {
+                    on_event(StreamEvent::Answer(delta.to_string()))
+                })
+                .await
+                .map_err(|e| ApiError::internal_error(format!("DeepSeek error: {}", e)))?;
+
+            thread.deepseek_session_id = Some(result.extra_data.session_id);
+            thread.deepseek_message_id =
+                Some(result.extra_data.message_id).filter(|id| !id.is_empty());
+
+            Ok(Completion {
+                content: result.response.unwrap_or_default(),
+                incomplete: result.incomplete,
+                ..Default::default()
+            })
+        }
+        Some(Provider::Grok) => {
+            Logger::info("Starting Grok conversation");
+            reject_files(&turn, "Grok")?;
+
+            let mut grok = Grok::new(&model, state.config().providers.grok.proxy.as_deref())
+                .map_err(|e| ApiError::internal_error(format!("Grok error: {}", e)))?;
+            let result = grok
+                .start_convo_cancellable(turn.message, thread.grok_extra_data.as_ref(), turn.cancel)
+                .await
+                .map_err(|e| ApiError::internal_error(format!("Grok error: {}", e)))?;
+
+            // Keep the anonymous session so the next turn continues this
+            // conversation; a cancelled turn may end before Grok names its reply
+            let mut extra_data = result.extra_data;
+            if extra_data.parent_response_id.is_none() {
+                extra_data.parent_response_id = thread
+                    .grok_extra_data
+                    .as_ref()
+                    .and_then(|previous| previous.parent_response_id.clone());
+            }
+            thread.grok_extra_data = Some(extra_data);
+
+            let content = result.response.unwrap_or_default();
+            on_event(StreamEvent::Answer(content.clone()));
+            Ok(Completion {
+                content,
+                incomplete: result.incomplete,
+                ..Default::default()
+            })
+        }
+        Some(Provider::ChatGpt) => {
+            Logger::info("Starting ChatGPT conversation");
+
+            let image = match turn.file_ids {
+                Some(file_ids) if !file_ids.is_empty() => {
+                    let mut image = None;
+                    for file_id in file_ids {
+                        image = state.get_uploaded_image(&thread.tenant, file_id).await;
+                        if image.is_some() {
+                            break;
+                        }
+                    }
+                    Some(image.ok_or_else(|| {
+                        ApiError::bad_request("No uploaded image found for provided file_ids")
+                    })?)
+                }
+                _ => None,
+            };
+
+            // Reuse the thread's client so follow-ups stay in the same
+            // conversation, unless the branch starts before its first answer
+            let existing = match thread.chatgpt_parent_message_id {
+                Some(_) => state.get_chatgpt_client(turn.thread_id).await,
+                None => None,
+            };
+            let is_new = existing.is_none();
+            let client = match existing {
+                Some(client) => client,
+                None => Arc::new(Mutex::new(
+                    ChatGptClient::new(state.config().providers.chatgpt.proxy.as_deref())
+                        .await
+                        .map_err(|e| ApiError::internal_error(format!("ChatGPT error: {}", e)))?,
+                )),
+            };
+
+            let content = {
+                let mut client = client.lock().await;
+                if !is_new {
+                    client.set_parent_message_id(thread.chatgpt_parent_message_id.clone());
+                }
+                let content = match image {
+                    Some(image) => client.start_with_image(turn.message, &image).await,
+                    None if is_new => client.start_conversation(turn.message).await,
+                    None => client.hold_conversation(turn.message, false).await,
+                };
+                thread.chatgpt_parent_message_id = client.parent_message_id().map(str::to_string);
+                content
+            }
+            .map_err(|e| ApiError::internal_error(format!("ChatGPT error: {}", e)))?;
+
+            if is_new {
+                state.set_chatgpt_client(turn.thread_id, client).await;
+            }
+
+            on_event(StreamEvent::Answer(content.clone()));
+            Ok(Completion {
+                content,
+                ..Default::default()
+            })
+        }
+        None => Err(unsupported_model(&model)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msg(role: &str, content: &str) -> PromptMessage {
+        PromptMessage {
+            role: role.to_string(),
+            content: content.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_provider_from_model() {
+        assert_eq!(Provider::from_model("qwen3-max"), Some(Provider::Qwen));
+        assert_eq!(Provider::from_model("deepseek"), Some(Provider::DeepSeek));
+        assert_eq!(Provider::from_model("grok-4"), Some(Provider::Grok));
+        assert_eq!(
+            Provider::from_model("chatgpt-auto"),
+            Some(Provider::ChatGpt)
+        );
+        assert_eq!(Provider::from_model("llama"), None);
+    }
+
+    #[test]
+    fn test_flatten_messages() {
+        assert_eq!(flatten_messages(&[msg("user", "hi")]), "hi");
+        assert_eq!(
+            flatten_messages(&[msg("system", "be brief"), msg("user", "hi")]),
+            "System: be brief\n\nUser: hi"
+        );
+    }
+
+    #[test]
+    fn test_completion_image() {
+        let image = || "data:image/png;base64,AA==".to_string();
+        assert_eq!(completion_image("qwen3-max", vec![]).unwrap(), None);
+        assert_eq!(
+            completion_image("chatgpt-auto", vec![image()]).unwrap(),
+            Some(image())
+        );
+        assert!(completion_image("qwen3-max", vec![image()]).is_err());
+        assert!(completion_image("chatgpt-auto", vec![image(), image()]).is_err());
+    }
+}
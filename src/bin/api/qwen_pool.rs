@@ -0,0 +1,234 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+
+/// How long an account sits out after an auth or rate-limit error
+pub const COOLDOWN: Duration = Duration::from_secs(300);
+
+/// Stable id for an account, derived from its token or email so that threads
+/// pinned to it survive a restart as long as the same credential is registered
+pub fn account_id(credential: &str) -> String {
+    let digest = Sha256::digest(credential.as_bytes());
+    let hex: String = digest[..6].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("qwen-{}", hex)
+}
+
+/// Whether an upstream error means the account itself is unusable for now
+pub fn is_account_error(message: &str) -> bool {
+    let message = message.to_lowercase();
+    [
+        "(401",
+        "(403",
+        "(429",
+        "login failed",
+        "unauthorized",
+        "ratelimit",
+        "rate limit",
+        "too many requests",
+    ]
+    .iter()
+    .any(|needle| message.contains(needle))
+}
+
+struct Account<C> {
+    id: String,
+    client: Arc<C>,
+    last_used: Option<Instant>,
+    cooldown_until: Option<Instant>,
+    consecutive_failures: u32,
+    total_requests: u64,
+}
+
+impl<C> Account<C> {
+    fn available(&self, now: Instant) -> bool {
+        self.cooldown_until.is_none_or(|until| until <= now)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct AccountStatus {
+    pub id: String,
+    pub available: bool,
+    pub cooldown_remaining_secs: u64,
+    pub consecutive_failures: u32,
+    pub total_requests: u64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CheckoutError {
+    /// No accounts registered
+    Empty,
+    /// Every usable account is cooling down
+    CoolingDown { retry_after: u64 },
+}
+
+/// Set of upstream accounts handed out least-recently-used first
+pub struct QwenPool<C> {
+    accounts: Vec<Account<C>>,
+}
+
+impl<C> Default for QwenPool<C> {
+    fn default() -> Self {
+        Self {
+            accounts: Vec::new(),
+        }
+    }
+}
+
+impl<C> QwenPool<C> {
+    /// Register an account, replacing any existing one with the same id
+    pub fn add(&mut self, id: String, client: C) {
+        let account = Account {
+            id,
+            client: Arc::new(client),
+            last_used: None,
+            cooldown_until: None,
+            consecutive_failures: 0,
+            total_requests: 0,
+        };
+        match self.accounts.iter_mut().find(|a| a.id == account.id) {
+            Some(existing) => *existing = account,
+            None => self.accounts.push(account),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+
+    /// Pick an account for a request.
+    ///
+    /// A `pinned` account that is still registered is always used (or reported
+    /// as cooling down) since its conversations only exist upstream on that
+    /// account; otherwise the least recently used available account is chosen.
+    pub fn checkout(
+        &mut self,
+        pinned: Option<&str>,
+        now: Instant,
+    ) -> Result<(String, Arc<C>), CheckoutError> {
+        if self.accounts.is_empty() {
+            return Err(CheckoutError::Empty);
+        }
+
+        let index = match pinned.and_then(|id| self.accounts.iter().position(|a| a.id == id)) {
+            Some(index) if self.accounts[index].available(now) => index,
+            Some(index) => {
+                return Err(CheckoutError::CoolingDown {
+                    retry_after: self.retry_after(&self.accounts[index], now),
+                })
+            }
+            None => self
+                .accounts
+                .iter()
+                .enumerate()
+                .filter(|(_, a)| a.available(now))
+                .min_by_key(|(_, a)| a.last_used)
+                .map(|(index, _)| index)
+                .ok_or_else(|| CheckoutError::CoolingDown {
+                    retry_after: self
+                        .accounts
+                        .iter()
+                        .map(|a| self.retry_after(a, now))
+                        .min()
+                        .unwrap_or(1),
+                })?,
+        };
+
+        let account = &mut self.accounts[index];
+        account.last_used = Some(now);
+        account.total_requests += 1;
+        Ok((account.id.clone(), Arc::clone(&account.client)))
+    }
+
+    fn retry_after(&self, account: &Account<C>, now: Instant) -> u64 {
+        account
+            .cooldown_until
+            .map(|until| until.saturating_duration_since(now).as_secs())
+            .unwrap_or(0)
+            .max(1)
+    }
+
+    /// Record the outcome of a request made with account `id`
+    pub fn report(&mut self, id: &str, error: Option<&str>, now: Instant) {
+        let Some(account) = self.accounts.iter_mut().find(|a| a.id == id) else {
+            return;
+        };
+        match error {
+            Some(message) if is_account_error(message) => {
+                account.consecutive_failures += 1;
+                account.cooldown_until = Some(now + COOLDOWN);
+            }
+            Some(_) => {}
+            None => {
+                account.consecutive_failures = 0;
+                account.cooldown_until = None;
+            }
+        }
+    }
+
+    pub fn status(&self, now: Instant) -> Vec<AccountStatus> {
+        self.accounts
+            .iter()
+            .map(|a| AccountStatus {
+                id: a.id.clone(),
+                available: a.available(now),
+                cooldown_remaining_secs: a
+                    .cooldown_until
+                    .map(|until| until.saturating_duration_since(now).as_secs())
+                    .unwrap_or(0),
+                consecutive_failures: a.consecutive_failures,
+                total_requests: a.total_requests,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool() -> QwenPool<()> {
+        let mut pool = QwenPool::default();
+        pool.add("a".to_string(), ());
+        pool.add("b".to_string(), ());
+        pool
+    }
+
+    #[test]
+    fn test_rotates_least_recently_used() {
+        let mut pool = pool();
+        let now = Instant::now();
+        let first = pool.checkout(None, now).unwrap().0;
+        let second = pool.checkout(None, now + Duration::from_secs(1)).unwrap().0;
+        assert_ne!(first, second);
+        let third = pool.checkout(None, now + Duration::from_secs(2)).unwrap().0;
+        assert_eq!(first, third);
+    }
+
+    #[test]
+    fn test_cooldown_and_pinning() {
+        let mut pool = pool();
+        let now = Instant::now();
+        pool.report(
+            "a",
+            Some("Chat completion failed (429 Too Many Requests)"),
+            now,
+        );
+
+        // Unpinned requests skip the cooling account
+        assert_eq!(pool.checkout(None, now).unwrap().0, "b");
+        assert_eq!(
+            pool.checkout(Some("a"), now),
+            Err(CheckoutError::CoolingDown { retry_after: 300 })
+        );
+        // Ordinary upstream errors do not bench the account
+        pool.report("b", Some("Server error: bad_request - invalid"), now);
+        assert_eq!(pool.checkout(Some("b"), now).unwrap().0, "b");
+
+        assert_eq!(pool.checkout(Some("a"), now + COOLDOWN).unwrap().0, "a");
+        assert_eq!(pool.checkout(Some("gone"), now).unwrap().0, "b");
+    }
+}
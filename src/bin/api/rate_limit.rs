@@ -0,0 +1,347 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{Request, State},
+    http::{
+        header::{CONTENT_TYPE, RETRY_AFTER},
+        HeaderValue,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::error::ApiError;
+use super::server::MAX_BODY_BYTES;
+use super::state::AppState;
+
+const MINUTE: Duration = Duration::from_secs(60);
+const DAY_SECS: u64 = 86_400;
+
+/// Limits attached to one API key; unset fields are unlimited
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct KeyLimits {
+    pub requests_per_minute: Option<u32>,
+    pub max_concurrent: Option<u32>,
+    pub daily_requests: Option<u64>,
+    pub daily_characters: Option<u64>,
+}
+
+/// The authenticated key of a request, inserted by the auth middleware
+#[derive(Debug, Clone)]
+pub struct CallerKey {
+    pub key: String,
+    pub tenant: String,
+    pub limits: KeyLimits,
+}
+
+/// Key shown on the dashboard and in logs, without leaking the secret
+pub fn mask_key(key: &str) -> String {
+    let chars: Vec<char> = key.chars().collect();
+    if chars.len() <= 8 {
+        return "****".to_string();
+    }
+    let head: String = chars[..4].iter().collect();
+    let tail: String = chars[chars.len() - 4..].iter().collect();
+    format!("{}…{}", head, tail)
+}
+
+impl CallerKey {
+    pub fn label(&self) -> String {
+        mask_key(&self.key)
+    }
+}
+
+/// Why a request was turned away, with the seconds until it may be retried
+#[derive(Debug, PartialEq, Eq)]
+pub struct LimitExceeded {
+    pub reason: &'static str,
+    pub retry_after: u64,
+}
+
+/// Consumption of one API key, kept by the `StatsCollector`
+pub struct KeyUsage {
+    label: String,
+    tenant: String,
+    limits: KeyLimits,
+    recent: VecDeque<Instant>,
+    in_flight: Arc<AtomicU32>,
+    day: u64,
+    daily_requests: u64,
+    daily_characters: u64,
+    total_requests: u64,
+    rejected_requests: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct KeyUsageStats {
+    pub key: String,
+    pub tenant: String,
+    pub requests_last_minute: usize,
+    pub in_flight: u32,
+    pub daily_requests: u64,
+    pub daily_characters: u64,
+    pub total_requests: u64,
+    pub rejected_requests: u64,
+    pub limits: KeyLimits,
+}
+
+/// Decrements the key's in-flight count when dropped
+pub struct InFlightGuard(Arc<AtomicU32>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+impl KeyUsage {
+    pub fn new(caller: &CallerKey) -> Self {
+        Self {
+            label: caller.label(),
+            tenant: caller.tenant.clone(),
+            limits: caller.limits.clone(),
+            recent: VecDeque::new(),
+            in_flight: Arc::new(AtomicU32::new(0)),
+            day: 0,
+            daily_requests: 0,
+            daily_characters: 0,
+            total_requests: 0,
+            rejected_requests: 0,
+        }
+    }
+
+    /// Check every limit and, if the request fits, count it against the key
+    pub fn try_acquire(
+        &mut self,
+        characters: u64,
+        now: Instant,
+        unix_secs: u64,
+    ) -> Result<InFlightGuard, LimitExceeded> {
+        while self
+            .recent
+            .front()
+            .is_some_and(|t| now.duration_since(*t) >= MINUTE)
+        {
+            self.recent.pop_front();
+        }
+        let today = unix_secs / DAY_SECS;
+        if today != self.day {
+            self.day = today;
+            self.daily_requests = 0;
+            self.daily_characters = 0;
+        }
+
+        let result = self.check(characters, now, unix_secs);
+        if result.is_err() {
+            self.rejected_requests += 1;
+        }
+        result?;
+
+        self.recent.push_back(now);
+        self.daily_requests += 1;
+        self.daily_characters += characters;
+        self.total_requests += 1;
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        Ok(InFlightGuard(Arc::clone(&self.in_flight)))
+    }
+
+    fn check(&self, characters: u64, now: Instant, unix_secs: u64) -> Result<(), LimitExceeded> {
+        let limits = &self.limits;
+        let until_tomorrow = DAY_SECS - unix_secs % DAY_SECS;
+
+        if let Some(max) = limits.daily_requests {
+            if self.daily_requests >= max {
+                return Err(LimitExceeded {
+                    reason: "Daily request quota exceeded",
+                    retry_after: until_tomorrow,
+                });
+            }
+        }
+        if let Some(max) = limits.daily_characters {
+            if self.daily_characters + characters > max {
+                return Err(LimitExceeded {
+                    reason: "Daily character quota exceeded",
+                    retry_after: until_tomorrow,
+                });
+            }
+        }
+        if let Some(max) = limits.requests_per_minute {
+            if self.recent.len() >= max as usize {
+                let oldest = self.recent.front().copied().unwrap_or(now);
+                let wait = MINUTE.saturating_sub(now.duration_since(oldest));
+                return Err(LimitExceeded {
+                    reason: "Rate limit exceeded",
+                    retry_after: wait.as_secs().max(1),
+                });
+            }
+        }
+        if let Some(max) = limits.max_concurrent {
+            if self.in_flight.load(Ordering::SeqCst) >= max {
+                return Err(LimitExceeded {
+                    reason: "Too many concurrent requests",
+                    retry_after: 1,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn snapshot(&self) -> KeyUsageStats {
+        let today = unix_now() / DAY_SECS == self.day;
+        KeyUsageStats {
+            key: self.label.clone(),
+            tenant: self.tenant.clone(),
+            requests_last_minute: self.recent.iter().filter(|t| t.elapsed() < MINUTE).count(),
+            in_flight: self.in_flight.load(Ordering::SeqCst),
+            daily_requests: if today { self.daily_requests } else { 0 },
+            daily_characters: if today { self.daily_characters } else { 0 },
+            total_requests: self.total_requests,
+            rejected_requests: self.rejected_requests,
+            limits: self.limits.clone(),
+        }
+    }
+}
+
+/// Apply the caller's key limits; requests without a key pass through.
+///
+/// The in-flight slot is held until the response body finishes, so streamed
+/// responses count as concurrent for as long as they are open.
+pub async fn enforce_limits(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let Some(caller) = request.extensions().get::<CallerKey>().cloned() else {
+        return Ok(next.run(request).await);
+    };
+
+    // Character quotas count the request body sent upstream, whatever its
+    // text format; multipart bodies carry file uploads rather than prompts
+    let is_multipart = request
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| {
+            v.trim_start()
+                .to_ascii_lowercase()
+                .starts_with("multipart/")
+        });
+    let (request, characters) = if caller.limits.daily_characters.is_some() && !is_multipart {
+        let (parts, body) = request.into_parts();
+        let bytes = read_body(body, MAX_BODY_BYTES).await?;
+        let characters = String::from_utf8_lossy(&bytes).chars().count() as u64;
+        (Request::from_parts(parts, Body::from(bytes)), characters)
+    } else {
+        (request, 0)
+    };
+
+    let guard = match state.acquire_key(&caller, characters).await {
+        Ok(guard) => guard,
+        Err(exceeded) => {
+            let mut response = ApiError::too_many_requests(exceeded.reason).into_response();
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(exceeded.retry_after));
+            return Ok(response);
+        }
+    };
+
+    let (parts, body) = next.run(request).await.into_parts();
+    let body = body.into_data_stream().map(move |chunk| {
+        let _ = &guard;
+        chunk
+    });
+    Ok(Response::from_parts(parts, Body::from_stream(body)))
+}
+
+/// Read a request body, refusing it once it grows past `limit` bytes rather
+/// than after buffering all of it
+async fn read_body(body: Body, limit: usize) -> Result<Bytes, ApiError> {
+    let mut stream = body.into_data_stream();
+    let mut bytes = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        let chunk =
+            chunk.map_err(|e| ApiError::bad_request(format!("Failed to read body: {}", e)))?;
+        if bytes.len() + chunk.len() > limit {
+            return Err(ApiError::payload_too_large(format!(
+                "Request body exceeds {} bytes",
+                limit
+            )));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn caller(limits: KeyLimits) -> CallerKey {
+        CallerKey {
+            key: "sk-test-1234567890".to_string(),
+            tenant: "team-a".to_string(),
+            limits,
+        }
+    }
+
+    #[test]
+    fn test_label_masks_key() {
+        assert_eq!(caller(KeyLimits::default()).label(), "sk-t…7890");
+    }
+
+    #[test]
+    fn test_requests_per_minute() {
+        let limits = KeyLimits {
+            requests_per_minute: Some(2),
+            ..Default::default()
+        };
+        let mut usage = KeyUsage::new(&caller(limits));
+        let now = Instant::now();
+
+        assert!(usage.try_acquire(0, now, 0).is_ok());
+        assert!(usage.try_acquire(0, now, 0).is_ok());
+        let err = usage.try_acquire(0, now, 0).err().unwrap();
+        assert_eq!(err.retry_after, 60);
+        assert!(usage.try_acquire(0, now + MINUTE, 0).is_ok());
+    }
+
+    #[test]
+    fn test_concurrency_and_daily_quota() {
+        let limits = KeyLimits {
+            max_concurrent: Some(1),
+            daily_characters: Some(10),
+            ..Default::default()
+        };
+        let mut usage = KeyUsage::new(&caller(limits));
+        let now = Instant::now();
+
+        let guard = usage.try_acquire(4, now, 0).unwrap();
+        assert_eq!(
+            usage.try_acquire(4, now, 0).err().unwrap().reason,
+            "Too many concurrent requests"
+        );
+        drop(guard);
+        assert!(usage.try_acquire(4, now, 0).is_ok());
+
+        let err = usage.try_acquire(4, now, 100).err().unwrap();
+        assert_eq!(err.reason, "Daily character quota exceeded");
+        assert_eq!(err.retry_after, DAY_SECS - 100);
+        // A new day resets the quota
+        assert!(usage.try_acquire(4, now, DAY_SECS).is_ok());
+    }
+}
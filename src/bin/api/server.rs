@@ -1,73 +1,207 @@
+use std::future::IntoFuture;
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 
 use axum::{
-    extract::State,
-    http::{header::CONTENT_TYPE, Method},
+    extract::{DefaultBodyLimit, State},
+    http::{
+        header::{ACCEPT_RANGES, CONTENT_RANGE, CONTENT_TYPE, RANGE},
+        HeaderName, HeaderValue, Method,
+    },
+    middleware,
     response::IntoResponse,
-    routing::{delete, get, post},
-    Json, Router,
+    routing::get,
+    Extension, Json, Router,
 };
-use reverse_api::Logger;
-use tower_http::cors::{Any, CorsLayer};
+use reverse_api::{CancelToken, Logger};
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+use utoipa::OpenApi;
+use utoipa_axum::{router::OpenApiRouter, routes};
 
-use super::{dashboard, docs, handlers, state::AppState};
+use super::auth::Tenant;
+use super::config::ServerConfig;
+use super::media::MediaStore;
+use super::openapi::ApiDoc;
+use super::state::{AppState, SHARED_CREDENTIALS};
+use super::{
+    anthropic, auth, dashboard, docs, export, handlers, metrics, qwen_pool, rate_limit, shutdown,
+    stats, storage::JsonStorage, telemetry, websocket,
+};
+
+/// Largest request body accepted, by the extractors and by the middleware
+/// that reads bodies itself
+pub const MAX_BODY_BYTES: usize = 2 * 1024 * 1024;
 
 pub fn router(state: AppState) -> Router {
+    let origins = &state.config().server.cors_origins;
+    let allow_origin = if origins.is_empty() || origins.iter().any(|o| o == "*") {
+        AllowOrigin::from(Any)
+    } else {
+        AllowOrigin::list(origins.iter().filter_map(|o| HeaderValue::from_str(o).ok()))
+    };
     let cors = CorsLayer::new()
-        .allow_origin(Any)
+        .allow_origin(allow_origin)
         .allow_methods([Method::POST, Method::GET, Method::OPTIONS, Method::DELETE])
-        .allow_headers([CONTENT_TYPE, axum::http::header::AUTHORIZATION]);
-
-    Router::new()
-        .route("/v1/threads", post(handlers::create_thread))
-        .route("/v1/threads", get(handlers::list_threads))
-        .route(
-            "/v1/threads/{thread_id}/messages",
-            post(handlers::add_message),
-        )
-        .route(
-            "/v1/threads/{thread_id}/messages",
-            get(handlers::list_messages),
-        )
-        .route("/v1/threads/{thread_id}", get(handlers::get_thread))
-        .route("/v1/threads/{thread_id}", delete(handlers::delete_thread))
-        .route("/v1/responses", post(handlers::create_response))
-        .route("/v1/config/qwen", post(handlers::configure_qwen))
-        .route("/v1/files/upload", post(handlers::upload_file_for_qwen))
-        .route("/v1/images/generate", post(handlers::generate_image))
-        .route("/v1/videos/generate", post(handlers::generate_video))
-        .route("/health", get(health_check))
-        .route("/v1/models", get(list_models))
-        .route("/dashboard", get(dashboard::dashboard))
-        .route("/dashboard/stats", get(dashboard::dashboard_stats))
-        .route("/dashboard/requests", get(dashboard::dashboard_requests))
-        .route("/docs", get(docs::api_docs))
+        .allow_headers([
+            CONTENT_TYPE,
+            axum::http::header::AUTHORIZATION,
+            HeaderName::from_static("x-api-key"),
+            HeaderName::from_static("anthropic-version"),
+            HeaderName::from_static("anthropic-beta"),
+            telemetry::REQUEST_ID_HEADER,
+            RANGE,
+        ])
+        .expose_headers([telemetry::REQUEST_ID_HEADER, ACCEPT_RANGES, CONTENT_RANGE]);
+
+    let api = api_routes()
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            rate_limit::enforce_limits,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_api_key,
+        ));
+    let admin = admin_routes().route_layer(middleware::from_fn_with_state(
+        state.clone(),
+        auth::require_admin_key,
+    ));
+    let (router, spec) = OpenApiRouter::with_openapi(ApiDoc::openapi())
+        .merge(api)
+        .merge(admin)
+        .merge(public_routes())
+        .split_for_parts();
+
+    router
+        .merge(docs::routes(&spec))
+        .route("/dashboard/dashboard.css", get(dashboard::dashboard_css))
+        .route("/dashboard/dashboard.js", get(dashboard::dashboard_js))
+        .layer(DefaultBodyLimit::max(MAX_BODY_BYTES))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            stats::record_requests,
+        ))
+        .layer(middleware::from_fn(telemetry::propagate_request_id))
         .with_state(state)
         .layer(cors)
 }
 
-async fn health_check(State(state): State<AppState>) -> impl IntoResponse {
-    let threads = state.list_threads().await;
+/// The OpenAPI document of every route `router` serves, bar the docs
+/// themselves and the dashboard assets
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    OpenApiRouter::<AppState>::with_openapi(ApiDoc::openapi())
+        .merge(api_routes())
+        .merge(admin_routes())
+        .merge(public_routes())
+        .split_for_parts()
+        .1
+}
+
+/// Everything under /v1, scoped to the tenant owning the API key
+fn api_routes() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes!(handlers::create_thread, handlers::list_threads))
+        .routes(routes!(handlers::add_message, handlers::list_messages))
+        .routes(routes!(handlers::edit_message))
+        .routes(routes!(handlers::regenerate_message))
+        .routes(routes!(handlers::list_branches))
+        .routes(routes!(handlers::activate_message))
+        .routes(routes!(export::import_thread))
+        .routes(routes!(export::export_thread))
+        .routes(routes!(handlers::get_thread, handlers::delete_thread))
+        .routes(routes!(handlers::create_response))
+        .routes(routes!(handlers::cancel_response))
+        .routes(routes!(handlers::chat_completions))
+        .routes(routes!(anthropic::create_message))
+        .routes(routes!(
+            handlers::configure_qwen,
+            handlers::list_qwen_accounts
+        ))
+        .routes(routes!(handlers::configure_deepseek))
+        .routes(routes!(handlers::upload_file_for_qwen))
+        .routes(routes!(handlers::generate_image))
+        .routes(routes!(handlers::create_image_generations))
+        .routes(routes!(handlers::generate_video))
+        .routes(routes!(handlers::list_media))
+        .routes(routes!(handlers::get_media))
+        .routes(routes!(handlers::list_jobs))
+        .routes(routes!(handlers::get_job))
+        .routes(routes!(handlers::cancel_job))
+        .routes(routes!(list_models))
+        .routes(routes!(websocket::websocket))
+}
+
+/// Dashboard data spanning every tenant: key usage and the requests made
+/// with each key
+fn admin_routes() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes!(dashboard::dashboard_summary))
+        .routes(routes!(dashboard::dashboard_events))
+        .routes(routes!(dashboard::dashboard_requests))
+        .routes(routes!(dashboard::dashboard_keys))
+}
 
+/// Monitoring endpoints, open without an API key
+fn public_routes() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes!(health_check))
+        .routes(routes!(dashboard::dashboard))
+        .routes(routes!(dashboard::dashboard_stats))
+        .routes(routes!(metrics::metrics))
+}
+
+#[utoipa::path(
+    get,
+    path = "/health",
+    tag = "monitoring",
+    summary = "健康检查",
+    description = "返回服务状态、活跃线程数和版本号",
+    security(()),
+    responses(
+        (status = 200, description = "服务正常")
+    )
+)]
+async fn health_check(State(state): State<AppState>) -> impl IntoResponse {
     Json(serde_json::json!({
         "status": "ok",
-        "active_threads": threads.len(),
+        "active_threads": state.thread_count().await,
         "version": env!("CARGO_PKG_VERSION")
     }))
 }
 
-async fn list_models(State(state): State<AppState>) -> impl IntoResponse {
-    let mut static_models = vec![
-        serde_json::json!({
-            "id": "deepseek",
+#[utoipa::path(
+    get,
+    path = "/v1/models",
+    tag = "config",
+    summary = "列出模型",
+    description = "列出支持的模型，配置 Qwen 账号后包含 Qwen 的模型",
+    responses(
+        (status = 200, description = "模型列表")
+    )
+)]
+async fn list_models(
+    State(state): State<AppState>,
+    Extension(Tenant(tenant)): Extension<Tenant>,
+) -> impl IntoResponse {
+    let mut static_models = vec![serde_json::json!({
+        "id": "deepseek",
+        "object": "model",
+        "created": 1704067200,
+        "owned_by": "deepseek"
+    })];
+
+    for id in reverse_api::grok::Models::new().names() {
+        static_models.push(serde_json::json!({
+            "id": id,
             "object": "model",
             "created": 1704067200,
-            "owned_by": "deepseek"
-        }),
-    ];
+            "owned_by": "xai"
+        }));
+    }
 
     // Add Qwen models if available
-    if let Some(qwen_models) = state.get_qwen_models().await {
+    if let Some(qwen_models) = state.get_qwen_models(&tenant).await {
         for model in qwen_models {
             static_models.push(serde_json::json!({
                 "id": model.id,
@@ -85,34 +219,154 @@ async fn list_models(State(state): State<AppState>) -> impl IntoResponse {
     }))
 }
 
-pub async fn run(
-    host: &str,
-    port: u16,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let addr: SocketAddr = format!("{}:{}", host, port).parse()?;
-    let state = AppState::new();
-    let app = router(state);
+/// Register the provider accounts from the configuration as shared credentials
+async fn register_shared_credentials(state: &AppState) -> Result<(), Box<dyn std::error::Error>> {
+    let qwen = &state.config().providers.qwen;
+    let mut clients = Vec::new();
+    for token in &qwen.tokens {
+        clients.push((
+            qwen_pool::account_id(token),
+            reverse_api::QwenClient::with_token(token.clone())?,
+        ));
+    }
+    for account in &qwen.accounts {
+        clients.push((
+            qwen_pool::account_id(&account.email),
+            reverse_api::QwenClient::new(account.email.clone(), account.password.clone())?,
+        ));
+    }
+    if let Some((_, client)) = clients.first() {
+        match client.get_models().await {
+            Ok(models) => state.set_qwen_models(SHARED_CREDENTIALS, models).await,
+            Err(e) => Logger::info(&format!("⚠️  Could not fetch Qwen models: {}", e)),
+        }
+    }
+    if !clients.is_empty() {
+        Logger::info(&format!(
+            "Registered {} shared Qwen accounts",
+            clients.len()
+        ));
+    }
+    for (id, client) in clients {
+        state.add_qwen_account(SHARED_CREDENTIALS, id, client).await;
+    }
+
+    if let Some(token) = &state.config().providers.deepseek.token {
+        match state
+            .set_deepseek_token(SHARED_CREDENTIALS, token.clone())
+            .await
+        {
+            Ok(()) => Logger::info("Registered shared DeepSeek token"),
+            Err(e) => Logger::error(&format!("DeepSeek token not usable: {}", e.message)),
+        }
+    }
+    Ok(())
+}
+
+/// How long closing connections may take once nothing is in flight
+const SERVER_CLOSE_GRACE: Duration = Duration::from_secs(2);
+
+/// How often old media are swept when a retention limit is configured
+const MEDIA_RETENTION_INTERVAL: Duration = Duration::from_secs(600);
 
-    let listener = tokio::net::TcpListener::bind(addr).await?;
-    let local_addr = listener.local_addr()?;
+/// Apply the media retention policy at startup and then periodically, so
+/// files expire by age even when nothing new is downloaded
+fn spawn_media_retention(state: &AppState) {
+    let media = state.media().clone();
+    if media.retention().is_unlimited() {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(MEDIA_RETENTION_INTERVAL);
+        loop {
+            interval.tick().await;
+            media.enforce_retention().await;
+        }
+    });
+}
+
+pub async fn run(config: ServerConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let addrs: Vec<SocketAddr> = config
+        .listen_addrs()
+        .iter()
+        .map(|addr| addr.parse())
+        .collect::<Result<_, _>>()?;
+    let state = match &config.storage.data_dir {
+        Some(dir) => {
+            Logger::info(&format!(
+                "Persisting threads and files to {}",
+                dir.display()
+            ));
+            AppState::with_storage(Arc::new(JsonStorage::open(dir)?))?
+        }
+        None => AppState::new(),
+    };
+    let keys = config.api_keys()?;
+    let state = if keys.is_empty() {
+        Logger::info("No API keys configured; /v1 endpoints are open");
+        state
+    } else {
+        Logger::info(&format!("Loaded {} API keys", keys.len()));
+        state.with_api_keys(keys)
+    };
+    let media = MediaStore::open(&config.media.output_dir, config.media.retention())?;
+    let shutdown_timeout = Duration::from_secs(config.server.shutdown_timeout_secs);
+    let state = state.with_config(config).with_media(media);
+    register_shared_credentials(&state).await?;
+    spawn_media_retention(&state);
+    let app = router(state.clone());
 
-    Logger::success(&format!("🚀 API server listening on http://{}", local_addr));
+    let mut listeners = Vec::new();
+    for addr in addrs {
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        Logger::success(&format!(
+            "🚀 API server listening on http://{}",
+            listener.local_addr()?
+        ));
+        listeners.push(listener);
+    }
     Logger::info("📚 API Endpoints:");
-    Logger::info("  Health: GET /health");
-    Logger::info("  Models: GET /v1/models");
-    Logger::info("  Threads: POST /v1/threads, GET /v1/threads");
-    Logger::info("  Thread: GET/DELETE /v1/threads/:thread_id");
-    Logger::info("  Messages: POST/GET /v1/threads/:thread_id/messages");
-    Logger::info(
-        "  Response: POST /v1/responses (supports qwen models)",
-    );
-    Logger::info("  Config Qwen: POST /v1/config/qwen");
-    Logger::info("  Dashboard: GET /dashboard");
-    Logger::info("  Dashboard Stats: GET /dashboard/stats");
-    Logger::info("  Dashboard Requests: GET /dashboard/requests");
+    let spec = openapi();
+    for (method, path, operation) in docs::operations(&spec) {
+        Logger::info(&format!(
+            "  {} {} - {}",
+            method,
+            path,
+            operation.summary.as_deref().unwrap_or_default()
+        ));
+    }
     Logger::info("  API Docs: GET /docs");
+    Logger::info("  OpenAPI: GET /openapi.json");
+
+    // Servers only return on failure, which brings the whole process down,
+    // or once told to stop accepting connections
+    let stop = CancelToken::new();
+    let servers: Vec<_> = listeners
+        .into_iter()
+        .map(|listener| {
+            let stop = stop.clone();
+            let server = axum::serve(listener, app.clone())
+                .with_graceful_shutdown(async move { stop.cancelled().await });
+            tokio::spawn(server.into_future())
+        })
+        .collect();
+    let mut servers = futures::future::select_all(servers);
+    tokio::select! {
+        (result, _, _) = &mut servers => result??,
+        _ = shutdown::signal() => {}
+    }
 
-    axum::serve(listener, app).await?;
+    Logger::info("Shutting down; no longer accepting connections");
+    stop.cancel();
+    shutdown::drain(&state, shutdown_timeout).await;
+    // Let finished responses deliver their last events before exiting
+    let _ = tokio::time::timeout(
+        SERVER_CLOSE_GRACE,
+        futures::future::join_all(servers.into_inner()),
+    )
+    .await;
+    state.flush().await?;
+    Logger::success("Saved threads and stats; bye");
 
     Ok(())
 }
@@ -0,0 +1,86 @@
+//! Stopping the server without cutting off answers that are still streaming
+
+use std::time::Duration;
+
+use reverse_api::Logger;
+use tokio::time::Instant;
+
+use super::state::AppState;
+
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How long cancelled responses get to save what they have so far
+const CANCEL_GRACE: Duration = Duration::from_secs(5);
+
+/// Resolves on SIGINT (Ctrl+C) or, on Unix, SIGTERM
+pub async fn signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            Logger::error(&format!("Failed to listen for Ctrl+C: {}", e));
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut stream) => {
+                stream.recv().await;
+            }
+            Err(e) => {
+                Logger::error(&format!("Failed to listen for SIGTERM: {}", e));
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => Logger::info("Received SIGINT"),
+        _ = terminate => Logger::info("Received SIGTERM"),
+    }
+}
+
+/// Wait up to `timeout` for in-flight responses and jobs to finish, then
+/// cancel the rest so their partial answers are still saved
+pub async fn drain(state: &AppState, timeout: Duration) {
+    let in_flight = state.in_flight();
+    if in_flight > 0 {
+        Logger::info(&format!(
+            "Waiting up to {}s for {} in-flight responses and jobs",
+            timeout.as_secs(),
+            in_flight
+        ));
+    }
+    if wait_idle(state, Instant::now() + timeout).await {
+        return;
+    }
+
+    let responses = state.responses().cancel_all();
+    let jobs = state.jobs().cancel_all();
+    Logger::info(&format!(
+        "Shutdown deadline reached; cancelled {} responses and {} jobs",
+        responses, jobs
+    ));
+    if !wait_idle(state, Instant::now() + CANCEL_GRACE).await {
+        Logger::error(&format!(
+            "{} requests still running at exit",
+            state.in_flight()
+        ));
+    }
+}
+
+/// Whether nothing was in flight any more before `deadline`
+async fn wait_idle(state: &AppState, deadline: Instant) -> bool {
+    loop {
+        if state.in_flight() == 0 {
+            return true;
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+        tokio::time::sleep_until(deadline.min(Instant::now() + DRAIN_POLL_INTERVAL)).await;
+    }
+}
@@ -1,117 +1,533 @@
+use super::auth::{ApiKey, DEFAULT_TENANT};
+use super::cancellation::ResponseRegistry;
+use super::config::ServerConfig;
 use super::error::ApiError;
-use super::stats::{LiveRequest, RequestStats, StatsCollector};
-use super::types::ThreadMessage;
+use super::jobs::JobStore;
+use super::media::MediaStore;
+use super::message_tree::{self, MessageNode, UpstreamCursor};
+use super::metrics::Metrics;
+use super::qwen_pool::{is_account_error, AccountStatus, CheckoutError, QwenPool};
+use super::rate_limit::{CallerKey, InFlightGuard, KeyUsageStats, LimitExceeded};
+use super::stats::{LiveRequest, RequestRecord, RequestStats, StatsCollector};
+use super::storage::{self, MemoryStorage, Storage};
+use super::types::{Thread, ThreadMessage};
+use base64::{engine::general_purpose, Engine as _};
+use reverse_api::Logger;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::Instant;
+use tokio::sync::{Mutex, RwLock};
 
 #[derive(Clone)]
 pub struct AppState {
     threads: Arc<RwLock<HashMap<String, ThreadState>>>,
     stats: StatsCollector,
-    qwen_token: Arc<RwLock<Option<String>>>,
-    qwen_models: Arc<RwLock<Option<Vec<reverse_api::qwen::models::Model>>>>,
-    uploaded_files: Arc<RwLock<HashMap<String, reverse_api::qwen::models::QwenFile>>>,
-    qwen_client: Arc<RwLock<Option<Arc<reverse_api::QwenClient>>>>,
+    credentials: Arc<RwLock<HashMap<String, ProviderCredentials>>>,
+    uploaded_files: Arc<RwLock<HashMap<String, StoredFile>>>,
+    chatgpt_clients: Arc<RwLock<HashMap<String, Arc<Mutex<reverse_api::ChatGptClient>>>>>,
+    storage: Arc<dyn Storage>,
+    /// Held while writing a thread, so writes land in the order they read
+    /// the threads
+    persisting: Arc<Mutex<()>>,
+    api_keys: Arc<HashMap<String, ApiKey>>,
+    config: Arc<ServerConfig>,
+    jobs: JobStore,
+    responses: ResponseRegistry,
+    media: MediaStore,
 }
 
+/// Credentials entry holding the accounts from the server configuration,
+/// used by every tenant that has not configured its own
+pub const SHARED_CREDENTIALS: &str = "*";
+
+/// Upstream clients configured by one tenant
+#[derive(Default)]
+struct ProviderCredentials {
+    qwen_models: Option<Vec<reverse_api::qwen::models::Model>>,
+    qwen_pool: QwenPool<reverse_api::QwenClient>,
+    deepseek_client: Option<Arc<reverse_api::DeepSeekClient>>,
+}
+
+/// The tenant's own credentials if `has` them, otherwise the shared ones
+fn credentials_for<'a>(
+    credentials: &HashMap<String, ProviderCredentials>,
+    tenant: &'a str,
+    has: impl Fn(&ProviderCredentials) -> bool,
+) -> &'a str {
+    if credentials.get(tenant).is_some_and(has) {
+        tenant
+    } else {
+        SHARED_CREDENTIALS
+    }
+}
+
+/// An uploaded Qwen file and the tenant that owns it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredFile {
+    pub tenant: String,
+    pub file: reverse_api::qwen::models::QwenFile,
+}
+
+fn default_tenant() -> String {
+    DEFAULT_TENANT.to_string()
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct ThreadState {
+    #[serde(default = "default_tenant")]
+    pub tenant: String,
     pub created_at: u64,
     pub metadata: Option<serde_json::Value>,
-    pub messages: Vec<ThreadMessage>,
+    /// Every message of every branch, in the order they were added
+    pub messages: Vec<MessageNode>,
+    /// Last message of the branch the thread continues from
+    #[serde(default)]
+    pub active_message_id: Option<String>,
     pub model: String,
     pub deepseek_session_id: Option<String>,
     pub deepseek_message_id: Option<String>,
     pub qwen_chat_id: Option<String>,
     pub qwen_parent_id: Option<String>,
+    /// Pool account that owns `qwen_chat_id`
+    #[serde(default)]
+    pub qwen_account: Option<String>,
+    pub grok_extra_data: Option<reverse_api::ExtraData>,
+    #[serde(default)]
+    pub chatgpt_parent_message_id: Option<String>,
 }
 
 impl AppState {
     pub fn new() -> Self {
         Self {
             threads: Arc::new(RwLock::new(HashMap::new())),
+            storage: Arc::new(MemoryStorage::default()),
+            persisting: Arc::new(Mutex::new(())),
             stats: StatsCollector::new(),
-            qwen_token: Arc::new(RwLock::new(None)),
-            qwen_models: Arc::new(RwLock::new(None)),
+            credentials: Arc::new(RwLock::new(HashMap::new())),
             uploaded_files: Arc::new(RwLock::new(HashMap::new())),
-            qwen_client: Arc::new(RwLock::new(None)),
+            chatgpt_clients: Arc::new(RwLock::new(HashMap::new())),
+            api_keys: Arc::new(HashMap::new()),
+            config: Arc::new(ServerConfig::default()),
+            jobs: JobStore::default(),
+            responses: ResponseRegistry::default(),
+            media: MediaStore::new(ServerConfig::default().media.output_dir, Default::default()),
+        }
+    }
+
+    /// Use `config`; the models it names, and the ones `/v1/models` lists,
+    /// get their own metrics label
+    pub fn with_config(self, config: ServerConfig) -> Self {
+        let metrics = self.metrics();
+        metrics.register_models(["deepseek"]);
+        metrics.register_models(reverse_api::grok::Models::new().names());
+        metrics.register_models(config.configured_models());
+        Self {
+            config: Arc::new(config),
+            ..self
+        }
+    }
+
+    pub fn config(&self) -> &ServerConfig {
+        &self.config
+    }
+
+    pub fn jobs(&self) -> &JobStore {
+        &self.jobs
+    }
+
+    pub fn responses(&self) -> &ResponseRegistry {
+        &self.responses
+    }
+
+    pub fn with_media(self, media: MediaStore) -> Self {
+        Self { media, ..self }
+    }
+
+    pub fn media(&self) -> &MediaStore {
+        &self.media
+    }
+
+    /// Require one of `api_keys` on every `/v1` request
+    pub fn with_api_keys(self, api_keys: HashMap<String, ApiKey>) -> Self {
+        Self {
+            api_keys: Arc::new(api_keys),
+            ..self
+        }
+    }
+
+    pub fn auth_enabled(&self) -> bool {
+        !self.api_keys.is_empty()
+    }
+
+    pub fn api_key(&self, key: &str) -> Option<ApiKey> {
+        self.api_keys.get(key).cloned()
+    }
+
+    /// Build state backed by `storage`, restoring its threads and uploaded files
+    pub fn with_storage(storage: Arc<dyn Storage>) -> std::io::Result<Self> {
+        let mut threads: HashMap<String, ThreadState> =
+            storage.load_threads()?.into_iter().collect();
+        for (thread_id, thread) in threads.iter_mut() {
+            thread.normalize(thread_id);
         }
+        let files: HashMap<String, StoredFile> = storage
+            .load_files()?
+            .into_iter()
+            .map(|stored| (stored.file.id.clone(), stored))
+            .collect();
+        let stats = storage.load_stats()?;
+        Logger::info(&format!(
+            "Restored {} threads and {} files from storage",
+            threads.len(),
+            files.len()
+        ));
+
+        let state = Self::new();
+        Ok(Self {
+            threads: Arc::new(RwLock::new(threads)),
+            uploaded_files: Arc::new(RwLock::new(files)),
+            stats: stats.map_or_else(StatsCollector::new, StatsCollector::with_stats),
+            storage,
+            ..state
+        })
+    }
+
+    /// Write a thread as it is now, or remove it once deleted. Called after
+    /// the threads lock is released, so reads never wait on the disk; the
+    /// thread is read again here, so the last write always stores the latest
+    /// version.
+    async fn persist_thread(&self, thread_id: &str) -> Result<(), ApiError> {
+        let _writing = self.persisting.lock().await;
+        let thread = self.threads.read().await.get(thread_id).cloned();
+        let storage = Arc::clone(&self.storage);
+        let thread_id = thread_id.to_string();
+        storage::blocking(move || match thread {
+            Some(thread) => storage.save_thread(&thread_id, &thread),
+            None => storage.delete_thread(&thread_id),
+        })
+        .await
+        .map_err(|e| ApiError::internal_error(format!("Failed to persist thread: {}", e)))
     }
 
+    /// Add a Qwen account to the tenant's pool, replacing one with the same id
+    pub async fn add_qwen_account(
+        &self,
+        tenant: &str,
+        account_id: String,
+        client: reverse_api::QwenClient,
+    ) {
+        let mut credentials = self.credentials.write().await;
+        credentials
+            .entry(tenant.to_string())
+            .or_default()
+            .qwen_pool
+            .add(account_id, client);
+    }
 
-    pub async fn set_qwen_token(&self, token: String) {
-        let mut qw_token = self.qwen_token.write().await;
-        *qw_token = Some(token.clone());
+    /// Take a Qwen account from the tenant's pool, preferring `pinned`
+    pub async fn checkout_qwen(
+        &self,
+        tenant: &str,
+        pinned: Option<&str>,
+    ) -> Result<(String, Arc<reverse_api::QwenClient>), ApiError> {
+        let mut credentials = self.credentials.write().await;
+        let owner = credentials_for(&credentials, tenant, |c| !c.qwen_pool.is_empty());
+        let pool = &mut credentials.entry(owner.to_string()).or_default().qwen_pool;
+        pool.checkout(pinned, Instant::now()).map_err(|e| match e {
+            CheckoutError::Empty => ApiError::bad_request(
+                "Qwen token not configured. Please configure it via POST /v1/config/qwen",
+            ),
+            CheckoutError::CoolingDown { retry_after } => ApiError::too_many_requests(format!(
+                "All Qwen accounts are cooling down, retry in {} seconds",
+                retry_after
+            )),
+        })
+    }
 
-        // Create and cache Qwen client when token is set
-        if let Ok(client) = reverse_api::QwenClient::with_token(token) {
-            let mut qw_client = self.qwen_client.write().await;
-            *qw_client = Some(Arc::new(client));
+    /// Feed the outcome of an upstream call back into the account's health
+    pub async fn report_qwen<T, E: std::fmt::Display>(
+        &self,
+        tenant: &str,
+        account_id: &str,
+        result: &Result<T, E>,
+    ) {
+        let error = result.as_ref().err().map(|e| e.to_string());
+        if let Some(message) = &error {
+            if is_account_error(message) {
+                Logger::error(&format!(
+                    "Qwen account {} is cooling down: {}",
+                    account_id, message
+                ));
+            }
+        }
+        let mut credentials = self.credentials.write().await;
+        let owner = credentials_for(&credentials, tenant, |c| !c.qwen_pool.is_empty());
+        if let Some(creds) = credentials.get_mut(owner) {
+            creds
+                .qwen_pool
+                .report(account_id, error.as_deref(), Instant::now());
         }
     }
 
-    pub async fn get_qwen_client(&self) -> Option<Arc<reverse_api::QwenClient>> {
-        self.qwen_client.read().await.as_ref().map(Arc::clone)
+    pub async fn qwen_accounts(&self, tenant: &str) -> Vec<AccountStatus> {
+        let credentials = self.credentials.read().await;
+        let owner = credentials_for(&credentials, tenant, |c| !c.qwen_pool.is_empty());
+        credentials
+            .get(owner)
+            .map(|creds| creds.qwen_pool.status(Instant::now()))
+            .unwrap_or_default()
     }
 
-    pub async fn get_qwen_models(&self) -> Option<Vec<reverse_api::qwen::models::Model>> {
-        self.qwen_models.read().await.clone()
+    pub async fn set_deepseek_token(&self, tenant: &str, token: String) -> Result<(), ApiError> {
+        let client = reverse_api::DeepSeekClient::new(token).await.map_err(|e| {
+            ApiError::internal_error(format!("Could not create DeepSeek client: {}", e))
+        })?;
+        let mut credentials = self.credentials.write().await;
+        credentials
+            .entry(tenant.to_string())
+            .or_default()
+            .deepseek_client = Some(Arc::new(client));
+        Ok(())
     }
 
-    pub async fn set_qwen_models(&self, models: Vec<reverse_api::qwen::models::Model>) {
-        let mut qw_models = self.qwen_models.write().await;
-        *qw_models = Some(models);
+    pub async fn get_deepseek_client(
+        &self,
+        tenant: &str,
+    ) -> Option<Arc<reverse_api::DeepSeekClient>> {
+        let credentials = self.credentials.read().await;
+        let owner = credentials_for(&credentials, tenant, |c| c.deepseek_client.is_some());
+        credentials.get(owner)?.deepseek_client.clone()
     }
 
-    pub async fn store_uploaded_file(&self, file: reverse_api::qwen::models::QwenFile) -> String {
+    /// ChatGPT client holding the upstream conversation of a thread
+    pub async fn get_chatgpt_client(
+        &self,
+        thread_id: &str,
+    ) -> Option<Arc<Mutex<reverse_api::ChatGptClient>>> {
+        self.chatgpt_clients.read().await.get(thread_id).cloned()
+    }
+
+    pub async fn set_chatgpt_client(
+        &self,
+        thread_id: &str,
+        client: Arc<Mutex<reverse_api::ChatGptClient>>,
+    ) {
+        let mut clients = self.chatgpt_clients.write().await;
+        clients.insert(thread_id.to_string(), client);
+    }
+
+    pub async fn get_qwen_models(
+        &self,
+        tenant: &str,
+    ) -> Option<Vec<reverse_api::qwen::models::Model>> {
+        let credentials = self.credentials.read().await;
+        let owner = credentials_for(&credentials, tenant, |c| c.qwen_models.is_some());
+        credentials.get(owner)?.qwen_models.clone()
+    }
+
+    pub async fn set_qwen_models(
+        &self,
+        tenant: &str,
+        models: Vec<reverse_api::qwen::models::Model>,
+    ) {
+        self.metrics()
+            .register_models(models.iter().map(|model| model.id.as_str()));
+        let mut credentials = self.credentials.write().await;
+        credentials
+            .entry(tenant.to_string())
+            .or_default()
+            .qwen_models = Some(models);
+    }
+
+    pub async fn store_uploaded_file(
+        &self,
+        tenant: &str,
+        file: reverse_api::qwen::models::QwenFile,
+    ) -> String {
         let file_id = file.id.clone();
+        let stored = StoredFile {
+            tenant: tenant.to_string(),
+            file,
+        };
+        let storage = Arc::clone(&self.storage);
+        let record = stored.clone();
+        if let Err(e) = storage::blocking(move || storage.save_file(&record)).await {
+            Logger::error(&format!("Failed to persist file {}: {}", file_id, e));
+        }
         let mut files = self.uploaded_files.write().await;
-        files.insert(file_id.clone(), file);
+        files.insert(file_id.clone(), stored);
         file_id
     }
 
+    /// Register the file records brought in by a thread import. Files the
+    /// tenant already has are kept as they are; an id owned by another
+    /// tenant rejects the whole import with 409.
+    pub async fn import_uploaded_files(
+        &self,
+        tenant: &str,
+        files: Vec<reverse_api::qwen::models::QwenFile>,
+    ) -> Result<(), ApiError> {
+        let mut uploaded = self.uploaded_files.write().await;
+        if let Some(file) = files.iter().find(|file| {
+            uploaded
+                .get(&file.id)
+                .is_some_and(|stored| stored.tenant != tenant)
+        }) {
+            return Err(ApiError::conflict(format!(
+                "File {} is already in use",
+                file.id
+            )));
+        }
+        let mut added = Vec::new();
+        for file in files {
+            if !uploaded.contains_key(&file.id) {
+                let stored = StoredFile {
+                    tenant: tenant.to_string(),
+                    file,
+                };
+                uploaded.insert(stored.file.id.clone(), stored.clone());
+                added.push(stored);
+            }
+        }
+        drop(uploaded);
+
+        let storage = Arc::clone(&self.storage);
+        storage::blocking(move || {
+            added
+                .iter()
+                .try_for_each(|stored| storage.save_file(stored))
+        })
+        .await
+        .map_err(|e| ApiError::internal_error(format!("Failed to persist files: {}", e)))
+    }
+
     pub async fn get_uploaded_file(
         &self,
+        tenant: &str,
         file_id: &str,
     ) -> Option<reverse_api::qwen::models::QwenFile> {
         let files = self.uploaded_files.read().await;
-        files.get(file_id).cloned()
+        files
+            .get(file_id)
+            .filter(|stored| stored.tenant == tenant)
+            .map(|stored| stored.file.clone())
+    }
+
+    /// Keep the bytes of an uploaded image for providers that take inline images
+    pub async fn store_uploaded_image(&self, file_id: &str, data: Vec<u8>) {
+        let storage = Arc::clone(&self.storage);
+        let id = file_id.to_string();
+        if let Err(e) = storage::blocking(move || storage.save_file_data(&id, &data)).await {
+            Logger::error(&format!("Failed to persist image {}: {}", file_id, e));
+        }
+    }
+
+    /// An uploaded image of the tenant as a data URL
+    pub async fn get_uploaded_image(&self, tenant: &str, file_id: &str) -> Option<String> {
+        let file = self.get_uploaded_file(tenant, file_id).await?;
+        if !file.file_type.starts_with("image/") {
+            return None;
+        }
+        let storage = Arc::clone(&self.storage);
+        let id = file_id.to_string();
+        let data = match storage::blocking(move || storage.load_file_data(&id)).await {
+            Ok(data) => data?,
+            Err(e) => {
+                Logger::error(&format!("Failed to read image {}: {}", file_id, e));
+                return None;
+            }
+        };
+        Some(format!(
+            "data:{};base64,{}",
+            file.file_type,
+            general_purpose::STANDARD.encode(data)
+        ))
     }
 
     pub async fn get_uploaded_files(
         &self,
+        tenant: &str,
         file_ids: &[String],
     ) -> Vec<reverse_api::qwen::models::QwenFile> {
         let files = self.uploaded_files.read().await;
         file_ids
             .iter()
-            .filter_map(|id| files.get(id).cloned())
+            .filter_map(|id| files.get(id))
+            .filter(|stored| stored.tenant == tenant)
+            .map(|stored| stored.file.clone())
             .collect()
     }
 
-    pub async fn record_request(
-        &self,
-        method: &str,
-        path: &str,
-        status: u16,
-        duration: std::time::Duration,
-        user_agent: &str,
-    ) {
-        self.stats
-            .record_request(method, path, status, duration, user_agent)
-            .await;
+    pub async fn record_request(&self, record: RequestRecord) {
+        self.stats.record_request(record).await;
     }
 
     pub async fn get_stats(&self) -> RequestStats {
         self.stats.get_stats().await
     }
 
+    pub fn metrics(&self) -> &Metrics {
+        self.stats.metrics()
+    }
+
+    /// Everything exported on `/metrics`, in Prometheus text format
+    pub async fn render_metrics(&self) -> String {
+        let performance = self.stats.get_performance().await;
+        self.stats.metrics().render(&performance)
+    }
+
     pub async fn get_live_requests(&self) -> Vec<LiveRequest> {
         self.stats.get_live_requests().await
     }
 
+    pub fn subscribe_requests(&self) -> tokio::sync::broadcast::Receiver<LiveRequest> {
+        self.stats.subscribe_requests()
+    }
+
+    pub async fn acquire_key(
+        &self,
+        caller: &CallerKey,
+        characters: u64,
+    ) -> Result<InFlightGuard, LimitExceeded> {
+        self.stats.acquire_key(caller, characters).await
+    }
+
+    pub async fn get_key_usage(&self) -> Vec<KeyUsageStats> {
+        self.stats.get_key_usage().await
+    }
+
+    /// Responses, upstream calls and generation jobs that have not finished
+    pub fn in_flight(&self) -> usize {
+        self.responses.in_progress()
+            + self.jobs.unfinished()
+            + self.metrics().upstream_in_flight().max(0) as usize
+    }
+
+    /// Write every thread and the request totals to storage
+    pub async fn flush(&self) -> std::io::Result<()> {
+        let _writing = self.persisting.lock().await;
+        let threads: Vec<(String, ThreadState)> = self
+            .threads
+            .read()
+            .await
+            .iter()
+            .map(|(thread_id, thread)| (thread_id.clone(), thread.clone()))
+            .collect();
+        let stats = self.stats.get_stats().await;
+        let storage = Arc::clone(&self.storage);
+        storage::blocking(move || {
+            for (thread_id, thread) in &threads {
+                storage.save_thread(thread_id, thread)?;
+            }
+            storage.save_stats(&stats)
+        })
+        .await
+    }
+
     pub async fn create_thread(
         &self,
+        tenant: &str,
         messages: Vec<ThreadMessage>,
         metadata: Option<serde_json::Value>,
         model: &str,
@@ -122,110 +538,409 @@ impl AppState {
             .unwrap()
             .as_secs();
 
-
-        let thread_state = ThreadState {
+        let mut thread_state = ThreadState {
+            tenant: tenant.to_string(),
             created_at,
             metadata,
-            messages,
+            messages: Vec::new(),
+            active_message_id: None,
             model: model.to_string(),
             deepseek_session_id: None,
             deepseek_message_id: None,
             qwen_chat_id: None,
             qwen_parent_id: None,
+            qwen_account: None,
+            grok_extra_data: None,
+            chatgpt_parent_message_id: None,
         };
+        for message in messages {
+            let mut node = MessageNode::new(
+                thread_state.active_message_id.clone(),
+                message.role,
+                message.content,
+            );
+            node.created_at = message.created_at.or(node.created_at);
+            thread_state.push(node);
+        }
 
-        let mut threads = self.threads.write().await;
-        threads.insert(thread_id.clone(), thread_state.clone());
+        self.threads
+            .write()
+            .await
+            .insert(thread_id.clone(), thread_state.clone());
+        self.persist_thread(&thread_id).await?;
 
         Ok((thread_id, thread_state))
     }
 
-    pub async fn get_thread(&self, thread_id: &str) -> Result<ThreadState, ApiError> {
+    /// Other tenants' threads are reported as not found
+    pub async fn get_thread(&self, tenant: &str, thread_id: &str) -> Result<ThreadState, ApiError> {
         let threads = self.threads.read().await;
         threads
             .get(thread_id)
+            .filter(|thread| thread.tenant == tenant)
             .cloned()
             .ok_or_else(|| ApiError::not_found("Thread not found"))
     }
 
-    pub async fn list_threads(&self) -> Vec<(String, ThreadState)> {
+    /// The tenant's threads accepted by `filter`, oldest first
+    pub async fn list_threads(
+        &self,
+        tenant: &str,
+        filter: impl Fn(&ThreadState) -> bool,
+    ) -> Vec<Thread> {
         let threads = self.threads.read().await;
-        threads
+        let mut list: Vec<Thread> = threads
             .iter()
-            .map(|(k, v)| (k.clone(), v.clone()))
-            .collect()
+            .filter(|(_, v)| v.tenant == tenant && filter(v))
+            .map(|(id, v)| Thread {
+                id: id.clone(),
+                object: "thread".to_string(),
+                created_at: v.created_at,
+                metadata: v.metadata.clone(),
+            })
+            .collect();
+        list.sort_by(|a, b| a.created_at.cmp(&b.created_at).then(a.id.cmp(&b.id)));
+        list
+    }
+
+    pub async fn thread_count(&self) -> usize {
+        self.threads.read().await.len()
     }
 
-    pub async fn delete_thread(&self, thread_id: &str) -> Result<(), ApiError> {
+    pub async fn delete_thread(&self, tenant: &str, thread_id: &str) -> Result<(), ApiError> {
         let mut threads = self.threads.write().await;
-        threads
-            .remove(thread_id)
-            .ok_or_else(|| ApiError::not_found("Thread not found"))?;
-        Ok(())
+        if threads
+            .get(thread_id)
+            .is_none_or(|thread| thread.tenant != tenant)
+        {
+            return Err(ApiError::not_found("Thread not found"));
+        }
+        threads.remove(thread_id);
+        drop(threads);
+
+        self.chatgpt_clients.write().await.remove(thread_id);
+        self.persist_thread(thread_id).await
     }
 
     pub async fn add_message_to_thread(
         &self,
+        tenant: &str,
         thread_id: &str,
         role: String,
         content: String,
-    ) -> Result<(), ApiError> {
+        file_ids: Vec<String>,
+    ) -> Result<MessageNode, ApiError> {
         let mut threads = self.threads.write().await;
         let thread = threads
             .get_mut(thread_id)
+            .filter(|thread| thread.tenant == tenant)
             .ok_or_else(|| ApiError::not_found("Thread not found"))?;
 
-        let created_at = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-
-        thread.messages.push(ThreadMessage {
-            role,
-            content,
-            created_at: Some(created_at),
-        });
+        let node = thread.add_message(role, content, file_ids);
+        drop(threads);
 
-        Ok(())
+        self.persist_thread(thread_id).await?;
+        Ok(node)
     }
 
-    pub async fn update_thread(&self, thread_id: &str, state: ThreadState) -> Result<(), ApiError> {
+    /// Change the tenant's thread under the write lock, so concurrent
+    /// requests each apply their change to the current version
+    pub async fn update_thread<T>(
+        &self,
+        tenant: &str,
+        thread_id: &str,
+        apply: impl FnOnce(&mut ThreadState) -> Result<T, ApiError>,
+    ) -> Result<T, ApiError> {
         let mut threads = self.threads.write().await;
-        threads.insert(thread_id.to_string(), state);
-        Ok(())
+        let thread = threads
+            .get_mut(thread_id)
+            .filter(|thread| thread.tenant == tenant)
+            .ok_or_else(|| ApiError::not_found("Thread not found"))?;
+        let value = apply(thread)?;
+        drop(threads);
+
+        self.persist_thread(thread_id).await?;
+        Ok(value)
+    }
+
+    /// Store the outcome of a turn run on a copy of the thread. The thread
+    /// must still exist; a thread deleted meanwhile is not brought back.
+    pub async fn save_turn(
+        &self,
+        tenant: &str,
+        thread_id: &str,
+        turn: ThreadState,
+    ) -> Result<(), ApiError> {
+        self.update_thread(tenant, thread_id, |thread| {
+            thread.merge(turn);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Add a thread built elsewhere, such as by an import
+    pub async fn insert_thread(
+        &self,
+        thread_id: &str,
+        thread: ThreadState,
+    ) -> Result<(), ApiError> {
+        self.threads
+            .write()
+            .await
+            .insert(thread_id.to_string(), thread);
+        self.persist_thread(thread_id).await
     }
 }
 
 impl ThreadState {
-    pub fn get_messages(&self) -> &[ThreadMessage] {
-        &self.messages
+    /// Messages on the active branch, oldest first
+    pub fn get_messages(&self) -> Vec<&MessageNode> {
+        message_tree::branch(&self.messages, self.active_message_id.as_deref())
     }
 
-    pub fn add_message(&mut self, role: String, content: String) {
-        let created_at = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
+    pub fn get_message(&self, id: &str) -> Result<&MessageNode, ApiError> {
+        message_tree::find(&self.messages, id)
+            .ok_or_else(|| ApiError::not_found("Message not found"))
+    }
+
+    /// Versions of message `id`, including itself, oldest first
+    pub fn siblings(&self, id: &str) -> Result<Vec<&MessageNode>, ApiError> {
+        let node = self.get_message(id)?;
+        Ok(message_tree::children(
+            &self.messages,
+            node.parent_id.as_deref(),
+        ))
+    }
 
-        self.messages.push(ThreadMessage {
-            role,
+    /// Append a message to the active branch
+    pub fn add_message(
+        &mut self,
+        role: String,
+        content: String,
+        file_ids: Vec<String>,
+    ) -> MessageNode {
+        let mut node = MessageNode::new(self.active_message_id.clone(), role, content);
+        node.file_ids = file_ids;
+        self.push(node)
+    }
+
+    /// Append a provider's answer to the active branch, remembering where the
+    /// upstream conversation stands after it
+    pub fn add_reply(&mut self, content: String, incomplete: bool) -> MessageNode {
+        let mut node = MessageNode::new(
+            self.active_message_id.clone(),
+            "assistant".to_string(),
             content,
-            created_at: Some(created_at),
-        });
+        );
+        node.incomplete = incomplete;
+        node.upstream = Some(self.cursor());
+        self.push(node)
+    }
+
+    /// Take over what a turn did to a copy of this thread: its new messages,
+    /// the upstream positions it recorded and the session handles it left.
+    /// Messages added here in the meantime are kept, and the active branch
+    /// only moves when the turn added a message.
+    pub fn merge(&mut self, turn: ThreadState) {
+        for node in turn.messages {
+            match self.messages.iter_mut().find(|own| own.id == node.id) {
+                Some(own) => own.upstream = node.upstream,
+                None => {
+                    if turn.active_message_id.as_deref() == Some(node.id.as_str()) {
+                        self.active_message_id = Some(node.id.clone());
+                    }
+                    self.messages.push(node);
+                }
+            }
+        }
+        self.deepseek_session_id = turn.deepseek_session_id;
+        self.deepseek_message_id = turn.deepseek_message_id;
+        self.qwen_chat_id = turn.qwen_chat_id;
+        self.qwen_parent_id = turn.qwen_parent_id;
+        self.qwen_account = turn.qwen_account;
+        self.grok_extra_data = turn.grok_extra_data;
+        self.chatgpt_parent_message_id = turn.chatgpt_parent_message_id;
+    }
+
+    fn push(&mut self, node: MessageNode) -> MessageNode {
+        self.active_message_id = Some(node.id.clone());
+        self.messages.push(node.clone());
+        node
+    }
+
+    /// Start a branch beside user message `id` with new content
+    pub fn edit_message(
+        &mut self,
+        id: &str,
+        content: String,
+        file_ids: Vec<String>,
+    ) -> Result<MessageNode, ApiError> {
+        let node = self.get_message(id)?;
+        if node.role != "user" {
+            return Err(ApiError::bad_request("Only user messages can be edited"));
+        }
+        self.active_message_id = node.parent_id.clone();
+        Ok(self.add_message("user".to_string(), content, file_ids))
+    }
+
+    /// Step back to the user message that assistant message `id` answers, so
+    /// the next reply becomes a sibling of `id`
+    pub fn rewind_reply(&mut self, id: &str) -> Result<(), ApiError> {
+        let node = self.get_message(id)?;
+        if node.role != "assistant" {
+            return Err(ApiError::bad_request(
+                "Only assistant messages can be regenerated",
+            ));
+        }
+        let prompt = node
+            .parent_id
+            .as_deref()
+            .and_then(|parent_id| message_tree::find(&self.messages, parent_id))
+            .filter(|parent| parent.role == "user")
+            .ok_or_else(|| ApiError::bad_request("Message does not answer a user message"))?;
+        self.active_message_id = Some(prompt.id.clone());
+        Ok(())
+    }
+
+    /// Continue from the newest branch through message `id`
+    pub fn activate(&mut self, id: &str) -> Result<(), ApiError> {
+        self.get_message(id)?;
+        self.active_message_id = Some(message_tree::latest_leaf(&self.messages, id).to_string());
+        Ok(())
+    }
+
+    /// Forget every provider session handle, so the next turn starts fresh
+    /// upstream conversations
+    pub fn clear_upstream(&mut self) {
+        for node in &mut self.messages {
+            node.upstream = None;
+        }
+        self.restore_cursor();
+    }
+
+    /// Record the current session handles as the upstream position after
+    /// message `id`
+    pub fn record_upstream(&mut self, id: &str) {
+        let cursor = self.cursor();
+        if let Some(node) = self.messages.iter_mut().find(|node| node.id == id) {
+            node.upstream = Some(cursor);
+        }
+    }
+
+    fn cursor(&self) -> UpstreamCursor {
+        let grok = self.grok_extra_data.as_ref();
+        UpstreamCursor {
+            qwen_chat_id: self.qwen_chat_id.clone(),
+            qwen_parent_id: self.qwen_parent_id.clone(),
+            qwen_account: self.qwen_account.clone(),
+            deepseek_session_id: self.deepseek_session_id.clone(),
+            deepseek_message_id: self.deepseek_message_id.clone(),
+            grok_conversation_id: grok.and_then(|extra| extra.conversation_id.clone()),
+            grok_parent_response_id: grok.and_then(|extra| extra.parent_response_id.clone()),
+            chatgpt_parent_message_id: self.chatgpt_parent_message_id.clone(),
+        }
+    }
+
+    /// Point the provider session handles at the last answered message of the
+    /// active branch, so the next turn continues the upstream conversation
+    /// from there rather than from wherever another branch left it
+    pub fn restore_cursor(&mut self) {
+        let cursor = self
+            .get_messages()
+            .iter()
+            .rev()
+            .find_map(|message| message.upstream.clone())
+            .unwrap_or_default();
+
+        self.qwen_chat_id = cursor.qwen_chat_id;
+        self.qwen_parent_id = cursor.qwen_parent_id;
+        if cursor.qwen_account.is_some() {
+            self.qwen_account = cursor.qwen_account;
+        }
+        self.deepseek_session_id = cursor.deepseek_session_id;
+        self.deepseek_message_id = cursor.deepseek_message_id;
+        // The anonymous Grok session is kept; only the position in it moves
+        match cursor.grok_conversation_id {
+            Some(conversation_id) => {
+                if let Some(extra) = self.grok_extra_data.as_mut() {
+                    extra.conversation_id = Some(conversation_id);
+                    extra.parent_response_id = cursor.grok_parent_response_id;
+                }
+            }
+            None => self.grok_extra_data = None,
+        }
+        self.chatgpt_parent_message_id = cursor.chatgpt_parent_message_id;
+    }
+
+    /// Upgrade a thread stored as a flat list of messages: chain them into a
+    /// single branch and attach the session handles to its last answer
+    pub fn normalize(&mut self, thread_id: &str) {
+        if !message_tree::normalize(&mut self.messages, thread_id) {
+            return;
+        }
+        if self.active_message_id.is_none() {
+            self.active_message_id = self.messages.last().map(|node| node.id.clone());
+        }
+        let cursor = self.cursor();
+        if cursor == UpstreamCursor::default() {
+            return;
+        }
+        if let Some(reply) = self
+            .messages
+            .iter_mut()
+            .rev()
+            .find(|node| node.role == "assistant")
+        {
+            reply.upstream.get_or_insert(cursor);
+        }
     }
 }
 
 impl Clone for ThreadState {
     fn clone(&self) -> Self {
         Self {
+            tenant: self.tenant.clone(),
             created_at: self.created_at,
             metadata: self.metadata.clone(),
             messages: self.messages.clone(),
+            active_message_id: self.active_message_id.clone(),
             model: self.model.clone(),
             deepseek_session_id: self.deepseek_session_id.clone(),
             deepseek_message_id: self.deepseek_message_id.clone(),
             qwen_chat_id: self.qwen_chat_id.clone(),
             qwen_parent_id: self.qwen_parent_id.clone(),
+            qwen_account: self.qwen_account.clone(),
+            grok_extra_data: self.grok_extra_data.clone(),
+            chatgpt_parent_message_id: self.chatgpt_parent_message_id.clone(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_keeps_concurrent_turns() {
+        let mut thread: ThreadState = serde_json::from_value(
+            serde_json::json!({"created_at": 1, "model": "qwen3-max", "messages": []}),
+        )
+        .unwrap();
+        let prompt = thread.add_message("user".to_string(), "hi".to_string(), vec![]);
+
+        let mut first = thread.clone();
+        let mut second = thread.clone();
+        first.qwen_chat_id = Some("chat-1".to_string());
+        let first_reply = first.add_reply("one".to_string(), false);
+        second.qwen_chat_id = Some("chat-2".to_string());
+        let second_reply = second.add_reply("two".to_string(), false);
+
+        thread.merge(first);
+        thread.merge(second);
+        assert_eq!(thread.messages.len(), 3);
+        assert_eq!(thread.siblings(&first_reply.id).unwrap().len(), 2);
+        assert_eq!(thread.active_message_id, Some(second_reply.id));
+        assert_eq!(thread.qwen_chat_id.as_deref(), Some("chat-2"));
+        assert_eq!(thread.get_messages()[0].id, prompt.id);
+    }
+}
@@ -1,9 +1,25 @@
+use super::auth;
+use super::logger::{Logger, PerformanceMetric};
+use super::metrics::Metrics;
+use super::rate_limit::{self, CallerKey, InFlightGuard, KeyUsage, KeyUsageStats, LimitExceeded};
+use super::state::AppState;
+use super::telemetry::RequestId;
+use axum::{
+    body::Body,
+    extract::{MatchedPath, Request, State},
+    http::header::USER_AGENT,
+    middleware::Next,
+    response::Response,
+};
+use futures_util::StreamExt;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use tokio::sync::RwLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, RwLock};
+use utoipa::ToSchema;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct RequestStats {
     pub total_requests: u64,
     pub successful_requests: u64,
@@ -12,20 +28,51 @@ pub struct RequestStats {
     pub average_response_time: u64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct LiveRequest {
     pub id: String,
     pub timestamp: u64,
     pub method: String,
     pub path: String,
+    #[serde(default)]
+    pub route: String,
     pub status: u16,
     pub duration_ms: u64,
     pub user_agent: String,
+    #[serde(default)]
+    pub api_key: Option<String>,
+    #[serde(default)]
+    pub model: Option<String>,
 }
 
+/// One finished HTTP request, as seen by [`record_requests`]
+#[derive(Debug, Clone)]
+pub struct RequestRecord {
+    pub request_id: Option<String>,
+    pub method: String,
+    /// Matched route template, e.g. `/v1/threads/{thread_id}`
+    pub route: String,
+    pub path: String,
+    pub status: u16,
+    pub duration: Duration,
+    pub user_agent: String,
+    /// Masked API key of the caller
+    pub api_key: Option<String>,
+    pub model: Option<String>,
+}
+
+/// Model a handler used, set on its response so the request is recorded with it
+#[derive(Debug, Clone)]
+pub struct RequestModel(pub String);
+
 pub struct StatsCollector {
     stats: Arc<RwLock<RequestStats>>,
     live_requests: Arc<RwLock<Vec<LiveRequest>>>,
+    /// Every recorded request, for the dashboard's event stream
+    live_updates: broadcast::Sender<LiveRequest>,
+    key_usage: Arc<RwLock<HashMap<String, KeyUsage>>>,
+    performance: Logger,
+    metrics: Metrics,
 }
 
 const MAX_LIVE_REQUESTS: usize = 100;
@@ -41,17 +88,33 @@ impl StatsCollector {
                 average_response_time: 0,
             })),
             live_requests: Arc::new(RwLock::new(Vec::new())),
+            live_updates: broadcast::channel(MAX_LIVE_REQUESTS).0,
+            key_usage: Arc::new(RwLock::new(HashMap::new())),
+            performance: Logger::new(),
+            metrics: Metrics::default(),
         }
     }
 
-    pub async fn record_request(
-        &self,
-        method: &str,
-        path: &str,
-        status: u16,
-        duration: Duration,
-        user_agent: &str,
-    ) {
+    /// Continue counting from totals saved by an earlier run
+    pub fn with_stats(stats: RequestStats) -> Self {
+        Self {
+            stats: Arc::new(RwLock::new(stats)),
+            ..Self::new()
+        }
+    }
+
+    pub async fn record_request(&self, record: RequestRecord) {
+        let RequestRecord {
+            request_id,
+            method,
+            route,
+            path,
+            status,
+            duration,
+            user_agent,
+            api_key,
+            model,
+        } = record;
         let mut stats = self.stats.write().await;
         let duration_ms = duration.as_millis() as u64;
 
@@ -79,25 +142,36 @@ impl StatsCollector {
 
         drop(stats);
 
+        self.performance
+            .record_metric(&route, &method, duration_ms, status)
+            .await;
+
         let request = LiveRequest {
-            id: format!(
-                "{}",
-                SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_nanos()
-            ),
+            id: request_id.unwrap_or_else(|| {
+                format!(
+                    "{}",
+                    SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap()
+                        .as_nanos()
+                )
+            }),
             timestamp: SystemTime::now()
                 .duration_since(UNIX_EPOCH)
                 .unwrap()
                 .as_secs(),
-            method: method.to_string(),
-            path: path.to_string(),
+            method,
+            path,
+            route,
             status,
             duration_ms,
-            user_agent: user_agent.to_string(),
+            user_agent,
+            api_key,
+            model,
         };
 
+        // Nobody may be listening, which is fine
+        let _ = self.live_updates.send(request.clone());
         let mut requests = self.live_requests.write().await;
         requests.push(request);
         if requests.len() > MAX_LIVE_REQUESTS {
@@ -112,6 +186,45 @@ impl StatsCollector {
     pub async fn get_live_requests(&self) -> Vec<LiveRequest> {
         self.live_requests.read().await.clone()
     }
+
+    /// Requests recorded from now on
+    pub fn subscribe_requests(&self) -> broadcast::Receiver<LiveRequest> {
+        self.live_updates.subscribe()
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub async fn get_performance(&self) -> Vec<PerformanceMetric> {
+        let mut metrics = self.performance.get_metrics().await;
+        metrics.sort_by(|a, b| a.endpoint.cmp(&b.endpoint).then(a.method.cmp(&b.method)));
+        metrics
+    }
+
+    /// Count a request against its API key, or report which limit it hits
+    pub async fn acquire_key(
+        &self,
+        caller: &CallerKey,
+        characters: u64,
+    ) -> Result<InFlightGuard, LimitExceeded> {
+        let now_secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let mut usage = self.key_usage.write().await;
+        let entry = usage
+            .entry(caller.key.clone())
+            .or_insert_with(|| KeyUsage::new(caller));
+        entry.try_acquire(characters, Instant::now(), now_secs)
+    }
+
+    pub async fn get_key_usage(&self) -> Vec<KeyUsageStats> {
+        let usage = self.key_usage.read().await;
+        let mut stats: Vec<KeyUsageStats> = usage.values().map(KeyUsage::snapshot).collect();
+        stats.sort_by(|a, b| a.tenant.cmp(&b.tenant).then(a.key.cmp(&b.key)));
+        stats
+    }
 }
 
 impl Clone for StatsCollector {
@@ -119,6 +232,96 @@ impl Clone for StatsCollector {
         Self {
             stats: Arc::clone(&self.stats),
             live_requests: Arc::clone(&self.live_requests),
+            live_updates: self.live_updates.clone(),
+            key_usage: Arc::clone(&self.key_usage),
+            performance: self.performance.clone(),
+            metrics: self.metrics.clone(),
         }
     }
 }
+
+/// Finishes a [`RequestRecord`] once the response body is done, so streamed
+/// responses are timed until their last event
+struct Recorder {
+    state: AppState,
+    start: Instant,
+    record: Option<RequestRecord>,
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        let Some(mut record) = self.record.take() else {
+            return;
+        };
+        record.duration = self.start.elapsed();
+        self.state.metrics().http_finished(
+            &record.route,
+            &record.method,
+            record.status,
+            record.duration,
+        );
+        let state = self.state.clone();
+        tokio::spawn(async move { state.record_request(record).await });
+    }
+}
+
+/// Record every request into the `StatsCollector` and `/metrics`, including
+/// the error responses produced by `ApiError`, auth and rate limiting.
+///
+/// Unmatched paths share one route label so scanners cannot blow up the
+/// label set.
+pub async fn record_requests(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", |path| path.as_str())
+        .to_string();
+    let request_id = request
+        .extensions()
+        .get::<RequestId>()
+        .map(|id| id.0.clone());
+    let method = request.method().to_string();
+    let path = request.uri().path().to_string();
+    let user_agent = request
+        .headers()
+        .get(USER_AGENT)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let api_key = auth::request_key(request.headers()).map(rate_limit::mask_key);
+
+    let start = Instant::now();
+    state.metrics().http_started(&route);
+    let response = next.run(request).await;
+
+    let model = response
+        .extensions()
+        .get::<RequestModel>()
+        .map(|m| m.0.clone());
+    let recorder = Recorder {
+        state,
+        start,
+        record: Some(RequestRecord {
+            request_id,
+            method,
+            route,
+            path,
+            status: response.status().as_u16(),
+            duration: Duration::ZERO,
+            user_agent,
+            api_key,
+            model,
+        }),
+    };
+
+    let (parts, body) = response.into_parts();
+    let body = body.into_data_stream().map(move |chunk| {
+        let _ = &recorder;
+        chunk
+    });
+    Response::from_parts(parts, Body::from_stream(body))
+}
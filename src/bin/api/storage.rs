@@ -0,0 +1,288 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use reverse_api::Logger;
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::state::{StoredFile, ThreadState};
+use super::stats::RequestStats;
+
+/// Backend that keeps threads, uploaded files and request totals across
+/// restarts.
+///
+/// `AppState` stays the source of truth while running; every thread and file
+/// mutation is written through, request totals are saved on shutdown, and
+/// everything is loaded back once at startup.
+pub trait Storage: Send + Sync {
+    fn load_threads(&self) -> io::Result<Vec<(String, ThreadState)>>;
+    fn save_thread(&self, thread_id: &str, thread: &ThreadState) -> io::Result<()>;
+    fn delete_thread(&self, thread_id: &str) -> io::Result<()>;
+    fn load_files(&self) -> io::Result<Vec<StoredFile>>;
+    fn save_file(&self, file: &StoredFile) -> io::Result<()>;
+    /// Keep the contents of an uploaded file, for providers that take files
+    /// inline rather than by Qwen's URL
+    fn save_file_data(&self, file_id: &str, data: &[u8]) -> io::Result<()>;
+    fn load_file_data(&self, file_id: &str) -> io::Result<Option<Vec<u8>>>;
+    fn load_stats(&self) -> io::Result<Option<RequestStats>>;
+    fn save_stats(&self, stats: &RequestStats) -> io::Result<()>;
+}
+
+/// Keeps only file contents, which `AppState` does not hold; everything else
+/// lives only in memory
+#[derive(Default)]
+pub struct MemoryStorage {
+    file_data: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl Storage for MemoryStorage {
+    fn load_threads(&self) -> io::Result<Vec<(String, ThreadState)>> {
+        Ok(Vec::new())
+    }
+
+    fn save_thread(&self, _thread_id: &str, _thread: &ThreadState) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn delete_thread(&self, _thread_id: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn load_files(&self) -> io::Result<Vec<StoredFile>> {
+        Ok(Vec::new())
+    }
+
+    fn save_file(&self, _file: &StoredFile) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn save_file_data(&self, file_id: &str, data: &[u8]) -> io::Result<()> {
+        self.file_data
+            .lock()
+            .unwrap()
+            .insert(file_id.to_string(), data.to_vec());
+        Ok(())
+    }
+
+    fn load_file_data(&self, file_id: &str) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.file_data.lock().unwrap().get(file_id).cloned())
+    }
+
+    fn load_stats(&self) -> io::Result<Option<RequestStats>> {
+        Ok(None)
+    }
+
+    fn save_stats(&self, _stats: &RequestStats) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Run storage IO on the blocking pool, off the async executor
+pub async fn blocking<T, F>(io: F) -> io::Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> io::Result<T> + Send + 'static,
+{
+    tokio::task::spawn_blocking(io)
+        .await
+        .map_err(io::Error::other)?
+}
+
+const STATS_RECORD: &str = "stats";
+
+/// Stores one JSON document per record under `<dir>/threads` and `<dir>/files`,
+/// file contents under `<dir>/file_data`, and request totals in
+/// `<dir>/stats.json`
+pub struct JsonStorage {
+    dir: PathBuf,
+    threads_dir: PathBuf,
+    files_dir: PathBuf,
+    file_data_dir: PathBuf,
+}
+
+impl JsonStorage {
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref();
+        let storage = Self {
+            dir: dir.to_path_buf(),
+            threads_dir: dir.join("threads"),
+            files_dir: dir.join("files"),
+            file_data_dir: dir.join("file_data"),
+        };
+        fs::create_dir_all(&storage.threads_dir)?;
+        fs::create_dir_all(&storage.files_dir)?;
+        fs::create_dir_all(&storage.file_data_dir)?;
+        Ok(storage)
+    }
+
+    fn record_path(dir: &Path, id: &str) -> io::Result<PathBuf> {
+        let valid = !id.is_empty()
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid record id: {}", id),
+            ));
+        }
+        Ok(dir.join(format!("{}.json", id)))
+    }
+
+    fn write_record<T: Serialize>(dir: &Path, id: &str, value: &T) -> io::Result<()> {
+        let path = Self::record_path(dir, id)?;
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec_pretty(value)?)?;
+        // Rename so a crash never leaves a half-written record behind
+        fs::rename(tmp_path, path)
+    }
+
+    fn remove(path: PathBuf) -> io::Result<()> {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    fn data_path(&self, file_id: &str) -> io::Result<PathBuf> {
+        Ok(Self::record_path(&self.file_data_dir, file_id)?.with_extension("bin"))
+    }
+
+    fn read_records<T: DeserializeOwned>(dir: &Path) -> io::Result<Vec<(String, T)>> {
+        let mut records = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            let Some(id) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            // One unreadable record should not keep the server from starting
+            let record = match fs::read(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|data| serde_json::from_slice(&data).map_err(|e| e.to_string()))
+            {
+                Ok(record) => record,
+                Err(e) => {
+                    Logger::error(&format!("Skipping record {}: {}", path.display(), e));
+                    continue;
+                }
+            };
+            records.push((id.to_string(), record));
+        }
+        Ok(records)
+    }
+}
+
+impl Storage for JsonStorage {
+    fn load_threads(&self) -> io::Result<Vec<(String, ThreadState)>> {
+        Self::read_records(&self.threads_dir)
+    }
+
+    fn save_thread(&self, thread_id: &str, thread: &ThreadState) -> io::Result<()> {
+        Self::write_record(&self.threads_dir, thread_id, thread)
+    }
+
+    fn delete_thread(&self, thread_id: &str) -> io::Result<()> {
+        Self::remove(Self::record_path(&self.threads_dir, thread_id)?)
+    }
+
+    fn load_files(&self) -> io::Result<Vec<StoredFile>> {
+        Ok(Self::read_records(&self.files_dir)?
+            .into_iter()
+            .map(|(_, file)| file)
+            .collect())
+    }
+
+    fn save_file(&self, file: &StoredFile) -> io::Result<()> {
+        Self::write_record(&self.files_dir, &file.file.id, file)
+    }
+
+    fn save_file_data(&self, file_id: &str, data: &[u8]) -> io::Result<()> {
+        let path = self.data_path(file_id)?;
+        let tmp_path = path.with_extension("bin.tmp");
+        fs::write(&tmp_path, data)?;
+        fs::rename(tmp_path, path)
+    }
+
+    fn load_file_data(&self, file_id: &str) -> io::Result<Option<Vec<u8>>> {
+        match fs::read(self.data_path(file_id)?) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn load_stats(&self) -> io::Result<Option<RequestStats>> {
+        match fs::read(Self::record_path(&self.dir, STATS_RECORD)?) {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn save_stats(&self, stats: &RequestStats) -> io::Result<()> {
+        Self::write_record(&self.dir, STATS_RECORD, stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_storage_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("api-storage-{}", uuid::Uuid::new_v4()));
+        let storage = JsonStorage::open(&dir).unwrap();
+
+        let thread = ThreadState {
+            tenant: "default".to_string(),
+            created_at: 1,
+            metadata: None,
+            messages: vec![],
+            active_message_id: None,
+            model: "qwen3-max".to_string(),
+            deepseek_session_id: None,
+            deepseek_message_id: None,
+            qwen_chat_id: Some("chat".to_string()),
+            qwen_parent_id: Some("parent".to_string()),
+            qwen_account: None,
+            grok_extra_data: None,
+            chatgpt_parent_message_id: None,
+        };
+        storage.save_thread("thread-1", &thread).unwrap();
+
+        let threads = storage.load_threads().unwrap();
+        assert_eq!(threads.len(), 1);
+        assert_eq!(threads[0].0, "thread-1");
+        assert_eq!(threads[0].1.qwen_chat_id.as_deref(), Some("chat"));
+
+        fs::write(dir.join("threads").join("broken.json"), b"{").unwrap();
+        assert_eq!(storage.load_threads().unwrap().len(), 1);
+        fs::remove_file(dir.join("threads").join("broken.json")).unwrap();
+
+        storage.delete_thread("thread-1").unwrap();
+        assert!(storage.load_threads().unwrap().is_empty());
+        assert!(storage.save_thread("../escape", &thread).is_err());
+
+        storage.save_file_data("file-1", b"png").unwrap();
+        assert_eq!(storage.load_file_data("file-1").unwrap().unwrap(), b"png");
+        assert!(storage.load_file_data("file-2").unwrap().is_none());
+
+        assert!(storage.load_stats().unwrap().is_none());
+        let stats = RequestStats {
+            total_requests: 3,
+            successful_requests: 2,
+            failed_requests: 1,
+            last_request_time: Some(10),
+            average_response_time: 40,
+        };
+        storage.save_stats(&stats).unwrap();
+        assert_eq!(storage.load_stats().unwrap().unwrap().total_requests, 3);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,21 +1,101 @@
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 
-#[derive(Debug, Deserialize)]
+use super::jobs::{Job, JobStatus};
+use super::media::{MediaFile, MediaKind};
+use super::qwen_pool::AccountStatus;
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Path)]
 pub struct ThreadPath {
     pub thread_id: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct CreateThreadRequest {
     #[serde(default)]
     pub messages: Vec<ThreadMessage>,
     #[serde(default)]
     pub metadata: Option<serde_json::Value>,
-    #[serde(default = "default_model")]
-    pub model: String,
+    /// Falls back to the configured default model
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+/// Sort direction of the list endpoints, by creation time
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListThreadsQuery {
+    pub limit: Option<usize>,
+    #[serde(default)]
+    pub order: SortOrder,
+    pub after: Option<String>,
+    pub before: Option<String>,
+    pub model: Option<String>,
+    pub metadata_key: Option<String>,
+    /// Only with `metadata_key`; without it any value matches
+    pub metadata_value: Option<String>,
+    /// Unix seconds, exclusive
+    pub created_after: Option<u64>,
+    /// Unix seconds, exclusive
+    pub created_before: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListMessagesQuery {
+    pub limit: Option<usize>,
+    /// Messages default to chronological order
+    #[serde(default = "chronological")]
+    pub order: SortOrder,
+    pub after: Option<String>,
+    pub before: Option<String>,
+}
+
+fn chronological() -> SortOrder {
+    SortOrder::Asc
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Path)]
+pub struct JobPath {
+    pub job_id: String,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Path)]
+pub struct ResponsePath {
+    pub response_id: String,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Path)]
+pub struct MessagePath {
+    pub thread_id: String,
+    pub message_id: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListJobsQuery {
+    pub limit: Option<usize>,
+    #[serde(default)]
+    pub order: SortOrder,
+    pub after: Option<String>,
+    pub before: Option<String>,
+    pub status: Option<JobStatus>,
+    pub kind: Option<MediaKind>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct AddMessageRequest {
     pub role: String,
     pub content: String,
@@ -23,7 +103,7 @@ pub struct AddMessageRequest {
     pub files: Option<Vec<String>>,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, ToSchema)]
 pub struct ThreadMessage {
     pub role: String,
     pub content: String,
@@ -31,23 +111,42 @@ pub struct ThreadMessage {
     pub created_at: Option<u64>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct CreateResponseRequest {
     pub thread_id: String,
     #[serde(default = "default_model")]
     #[allow(dead_code)]
     pub model: String,
     #[serde(default)]
-    #[allow(dead_code)]
     pub instructions: Option<String>,
     #[serde(default)]
-    #[allow(dead_code)]
     pub stream: bool,
     #[serde(default)]
     pub file_ids: Option<Vec<String>>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct EditMessageRequest {
+    pub content: String,
+    #[serde(default)]
+    pub instructions: Option<String>,
+    #[serde(default)]
+    pub stream: bool,
+    #[serde(default)]
+    pub file_ids: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct RegenerateMessageRequest {
+    #[serde(default)]
+    pub instructions: Option<String>,
+    #[serde(default)]
+    pub stream: bool,
+    #[serde(default)]
+    pub file_ids: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct GenerateImageRequest {
     pub prompt: String,
     #[serde(default)]
@@ -58,9 +157,12 @@ pub struct GenerateImageRequest {
     pub thread_id: Option<String>,
     #[serde(default)]
     pub download: bool,
+    /// Return a job immediately instead of waiting for the result
+    #[serde(default)]
+    pub background: bool,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct GenerateVideoRequest {
     pub prompt: String,
     #[serde(default)]
@@ -71,13 +173,55 @@ pub struct GenerateVideoRequest {
     pub thread_id: Option<String>,
     #[serde(default)]
     pub download: bool,
+    /// Return a job immediately instead of waiting for the result
+    #[serde(default)]
+    pub background: bool,
+}
+
+/// Encoding of the images returned by `/v1/images/generations`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageResponseFormat {
+    #[default]
+    Url,
+    B64Json,
+}
+
+/// OpenAI-compatible image generation request
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ImageGenerationRequest {
+    pub prompt: String,
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub n: Option<u32>,
+    /// OpenAI size such as `1024x1024`, or a Qwen ratio such as `16:9`;
+    /// Grok models ignore it
+    #[serde(default)]
+    pub size: Option<String>,
+    #[serde(default)]
+    pub response_format: ImageResponseFormat,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ImageGenerationResponse {
+    pub created: u64,
+    pub data: Vec<ImageData>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ImageData {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub b64_json: Option<String>,
 }
 
 fn default_qwen_model() -> String {
     "qwen3-max".to_string()
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 pub struct FileUploadResponse {
     pub id: String,
     pub name: String,
@@ -85,27 +229,37 @@ pub struct FileUploadResponse {
     pub file_class: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct GenerateImageResponse {
     pub image_url: String,
     pub prompt: String,
     pub chat_id: Option<String>,
     pub response_id: String,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub media_id: Option<String>,
+    /// Path under which this server serves the downloaded file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub media_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub local_path: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct GenerateVideoResponse {
     pub video_url: String,
     pub prompt: String,
     pub chat_id: Option<String>,
     pub response_id: String,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub media_id: Option<String>,
+    /// Path under which this server serves the downloaded file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub media_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub local_path: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct Thread {
     pub id: String,
     pub object: String,
@@ -113,30 +267,43 @@ pub struct Thread {
     pub metadata: Option<serde_json::Value>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ThreadDeleted {
+    pub id: String,
+    pub object: String,
+    pub deleted: bool,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
 pub struct Message {
     pub id: String,
     pub object: String,
     pub created_at: u64,
     pub thread_id: String,
+    /// Message this one follows; versions of a message share it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<String>,
+    /// `incomplete` for answers cut short by a cancelled response
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
     pub role: String,
     pub content: Vec<ContentPart>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct ContentPart {
     #[serde(rename = "type")]
     pub content_type: String,
     pub text: TextContent,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct TextContent {
     pub value: String,
     pub annotations: Vec<serde_json::Value>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct Response {
     pub id: String,
     pub object: String,
@@ -144,24 +311,281 @@ pub struct Response {
     pub thread_id: String,
     pub status: String,
     pub model: String,
+    /// Assistant message holding the answer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub response: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+impl Response {
+    /// The same response once it ended with `status`
+    pub fn finish(&self, status: &str, message_id: String, answer: String) -> Self {
+        Self {
+            status: status.to_string(),
+            message_id: Some(message_id),
+            response: Some(answer),
+            ..self.clone()
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
 pub struct ListThreadsResponse {
     pub object: String,
     pub data: Vec<Thread>,
+    pub first_id: Option<String>,
+    pub last_id: Option<String>,
     pub has_more: bool,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct ListMessagesResponse {
     pub object: String,
     pub data: Vec<Message>,
+    pub first_id: Option<String>,
+    pub last_id: Option<String>,
+    pub has_more: bool,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ListBranchesResponse {
+    pub object: String,
+    pub data: Vec<Message>,
+    /// Version on the thread's active branch, if any
+    pub active_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Path)]
+pub struct MediaPath {
+    pub media_id: String,
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListMediaQuery {
+    pub limit: Option<usize>,
+    #[serde(default)]
+    pub order: SortOrder,
+    pub after: Option<String>,
+    pub before: Option<String>,
+    pub kind: Option<MediaKind>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ListMediaResponse {
+    pub object: String,
+    pub data: Vec<MediaFile>,
+    pub first_id: Option<String>,
+    pub last_id: Option<String>,
     pub has_more: bool,
 }
 
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ListJobsResponse {
+    pub object: String,
+    pub data: Vec<Job>,
+    pub first_id: Option<String>,
+    pub last_id: Option<String>,
+    pub has_more: bool,
+}
+
+/// Qwen accounts to add to the tenant's pool; at least one field is required
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ConfigureQwenRequest {
+    #[serde(default)]
+    pub token: Option<String>,
+    #[serde(default)]
+    pub tokens: Vec<String>,
+    #[serde(default)]
+    pub accounts: Vec<QwenCredentials>,
+}
+
+/// Email/password login of a Qwen account
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct QwenCredentials {
+    pub email: Option<String>,
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ConfigureDeepSeekRequest {
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ConfigureResponse {
+    pub status: String,
+    pub message: String,
+    /// Ids of the Qwen accounts added to the pool
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub accounts: Option<Vec<String>>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ListAccountsResponse {
+    pub object: String,
+    pub data: Vec<AccountStatus>,
+}
+
 fn default_model() -> String {
     "qwen3-max".to_string()
 }
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ChatCompletionRequest {
+    pub model: String,
+    pub messages: Vec<ChatMessage>,
+    #[serde(default)]
+    pub stream: bool,
+    /// Accepted for compatibility and ignored; the upstream chats cannot be
+    /// sampled differently
+    #[serde(default)]
+    #[allow(dead_code)]
+    pub temperature: Option<f32>,
+    /// Accepted for compatibility and ignored
+    #[serde(default)]
+    #[allow(dead_code)]
+    pub top_p: Option<f32>,
+    /// Accepted for compatibility and ignored; the upstream chats cannot limit
+    /// the answer length
+    #[serde(default)]
+    #[allow(dead_code)]
+    pub max_tokens: Option<u32>,
+    #[serde(default)]
+    #[allow(dead_code)]
+    pub user: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, ToSchema)]
+pub struct ChatMessage {
+    pub role: String,
+    #[serde(default)]
+    pub content: ChatContent,
+}
+
+/// OpenAI message content: either a plain string or an array of typed parts
+#[derive(Debug, Deserialize, Serialize, Clone, ToSchema)]
+#[serde(untagged)]
+pub enum ChatContent {
+    Text(String),
+    Parts(Vec<ChatContentPart>),
+}
+
+impl Default for ChatContent {
+    fn default() -> Self {
+        ChatContent::Text(String::new())
+    }
+}
+
+impl ChatContent {
+    pub fn text(&self) -> String {
+        match self {
+            ChatContent::Text(text) => text.clone(),
+            ChatContent::Parts(parts) => parts
+                .iter()
+                .filter_map(|p| p.text.as_deref())
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+
+    pub fn image_urls(&self) -> Vec<String> {
+        match self {
+            ChatContent::Text(_) => vec![],
+            ChatContent::Parts(parts) => parts
+                .iter()
+                .filter_map(|p| p.image_url.as_ref().map(|i| i.url.clone()))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, ToSchema)]
+pub struct ChatContentPart {
+    #[serde(rename = "type")]
+    pub part_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_url: Option<ImageUrl>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, ToSchema)]
+pub struct ImageUrl {
+    pub url: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ChatCompletion {
+    pub id: String,
+    pub object: String,
+    pub created: u64,
+    pub model: String,
+    pub choices: Vec<ChatChoice>,
+    pub usage: Usage,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ChatChoice {
+    pub index: u32,
+    pub message: ChatCompletionMessage,
+    pub finish_reason: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ChatCompletionMessage {
+    pub role: String,
+    pub content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning_content: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ChatCompletionChunk {
+    pub id: String,
+    pub object: String,
+    pub created: u64,
+    pub model: String,
+    pub choices: Vec<ChatChunkChoice>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ChatChunkChoice {
+    pub index: u32,
+    pub delta: ChatDelta,
+    pub finish_reason: Option<String>,
+}
+
+#[derive(Debug, Serialize, Default, ToSchema)]
+pub struct ChatDelta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning_content: Option<String>,
+}
+
+#[derive(Debug, Serialize, Clone, Copy, ToSchema)]
+pub struct Usage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub total_tokens: u32,
+}
+
+impl Usage {
+    /// Upstream web APIs don't report token counts, so approximate them
+    /// at roughly four characters per token.
+    pub fn estimate(prompt: &str, completion: &str) -> Self {
+        let estimate = |text: &str| text.chars().count().div_ceil(4) as u32;
+        let prompt_tokens = estimate(prompt);
+        let completion_tokens = estimate(completion);
+        Self {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+        }
+    }
+}
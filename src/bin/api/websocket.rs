@@ -0,0 +1,528 @@
+//! Interactive chat over one WebSocket per thread (`GET /v1/ws`).
+//!
+//! Clients send JSON frames tagged by `type` (user messages and control
+//! frames) and receive `{"type": ..., "data": ...}` frames carrying the same
+//! events as a streamed `/v1/responses` call, plus progress of the media jobs
+//! started from the connection.
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+use axum::{
+    extract::{
+        ws::{Message as WsMessage, WebSocket, WebSocketUpgrade},
+        Query, State,
+    },
+    response::{IntoResponse, Response as AxumResponse},
+    Extension,
+};
+use futures_util::{SinkExt, StreamExt};
+use reverse_api::{CancelToken, Logger};
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::Instrument;
+use utoipa::IntoParams;
+
+use super::auth::Tenant;
+use super::error::{ApiError, ErrorResponse};
+use super::export;
+use super::handlers;
+use super::jobs::Job;
+use super::media::MediaKind;
+use super::providers::{self, Provider};
+use super::rate_limit::{CallerKey, InFlightGuard};
+use super::state::{AppState, ThreadState};
+use super::types::{CreateResponseRequest, GenerateImageRequest, GenerateVideoRequest};
+
+/// How often jobs started from a connection are checked for progress
+const JOB_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct WebSocketQuery {
+    /// Thread to attach to; a new thread is created without one
+    #[serde(default)]
+    pub thread_id: Option<String>,
+    /// Model of the new thread
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+/// Frames sent by the client
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientFrame {
+    /// Add a user message to the thread and answer it
+    Message {
+        content: String,
+        #[serde(default)]
+        file_ids: Option<Vec<String>>,
+        #[serde(default)]
+        instructions: Option<String>,
+    },
+    /// Stop the response in progress, or the job `job_id`
+    Cancel {
+        #[serde(default)]
+        job_id: Option<String>,
+    },
+    /// Answer the user message behind an assistant message again, by default
+    /// the last one on the active branch
+    Regenerate {
+        #[serde(default)]
+        message_id: Option<String>,
+        #[serde(default)]
+        instructions: Option<String>,
+    },
+    /// Continue the thread with another model
+    Model {
+        model: String,
+    },
+    GenerateImage(GenerateImageRequest),
+    GenerateVideo(GenerateVideoRequest),
+}
+
+/// Text of a server frame
+fn frame(name: &str, data: serde_json::Value) -> String {
+    serde_json::json!({ "type": name, "data": data }).to_string()
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/ws",
+    tag = "responses",
+    summary = "WebSocket 对话",
+    description = "一个连接对应一个线程，省略 thread_id 时按 model 新建线程；浏览器可用 api_key 查询参数认证。客户端发送按 type 区分的 JSON 帧，服务器推送 {\"type\", \"data\"} 帧",
+    params(WebSocketQuery),
+    responses(
+        (status = 101, description = "切换到 WebSocket 协议"),
+        (status = 404, description = "线程不存在", body = ErrorResponse)
+    )
+)]
+pub async fn websocket(
+    State(state): State<AppState>,
+    Extension(Tenant(tenant)): Extension<Tenant>,
+    caller: Option<Extension<CallerKey>>,
+    Query(query): Query<WebSocketQuery>,
+    upgrade: WebSocketUpgrade,
+) -> Result<AxumResponse, ApiError> {
+    // Resolve the thread first so a bad id is a plain HTTP error
+    let thread_id = match query.thread_id {
+        Some(thread_id) => {
+            state.get_thread(&tenant, &thread_id).await?;
+            thread_id
+        }
+        None => {
+            let model = query
+                .model
+                .unwrap_or_else(|| state.config().models.default.clone());
+            let model = state
+                .config()
+                .resolve_model(&model)
+                .ok_or_else(|| providers::unsupported_model(&model))?;
+            state
+                .create_thread(&tenant, Vec::new(), None, &model)
+                .await?
+                .0
+        }
+    };
+
+    let span = tracing::Span::current();
+    Ok(upgrade
+        .on_upgrade(move |socket| {
+            let (tx, rx) = mpsc::unbounded_channel();
+            let session = Session {
+                state,
+                tenant,
+                caller: caller.map(|Extension(caller)| caller),
+                thread_id,
+                tx,
+                busy: None,
+                watched: HashSet::new(),
+            };
+            session.run(socket, rx).instrument(span)
+        })
+        .into_response())
+}
+
+/// One connection, bound to one thread
+struct Session {
+    state: AppState,
+    tenant: String,
+    caller: Option<CallerKey>,
+    thread_id: String,
+    tx: mpsc::UnboundedSender<String>,
+    /// Response or model switch running on the thread, and how to stop it
+    busy: Option<(CancelToken, JoinHandle<()>)>,
+    /// Jobs started from this connection, whose updates are pushed
+    watched: HashSet<String>,
+}
+
+impl Session {
+    async fn run(mut self, socket: WebSocket, mut rx: mpsc::UnboundedReceiver<String>) {
+        let (mut sink, mut stream) = socket.split();
+        tokio::spawn(async move {
+            while let Some(text) = rx.recv().await {
+                if sink.send(WsMessage::Text(text.into())).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        match self.state.get_thread(&self.tenant, &self.thread_id).await {
+            Ok(thread) => self.emit("session.created", self.thread_info(&thread)),
+            Err(e) => return self.error(e),
+        }
+
+        while let Some(Ok(message)) = stream.next().await {
+            let text = match message {
+                WsMessage::Text(text) => text,
+                WsMessage::Close(_) => break,
+                // Pings are answered by axum
+                _ => continue,
+            };
+            let result = match serde_json::from_str::<ClientFrame>(&text) {
+                Ok(frame) => self.handle(frame).await,
+                Err(e) => Err(ApiError::bad_request(format!("Invalid frame: {}", e))),
+            };
+            if let Err(e) = result {
+                self.error(e);
+            }
+        }
+
+        // Like a client leaving a streamed response: the partial answer is
+        // kept on the thread. Jobs keep running and can be polled.
+        if let Some((cancel, _)) = self.busy.take() {
+            cancel.cancel();
+        }
+    }
+
+    async fn handle(&mut self, frame: ClientFrame) -> Result<(), ApiError> {
+        match frame {
+            ClientFrame::Message {
+                content,
+                file_ids,
+                instructions,
+            } => {
+                self.ensure_idle()?;
+                if content.trim().is_empty() {
+                    return Err(ApiError::bad_request("Message content cannot be empty"));
+                }
+                let limit = self.acquire(content.chars().count() as u64).await?;
+                let node = self
+                    .state
+                    .add_message_to_thread(
+                        &self.tenant,
+                        &self.thread_id,
+                        "user".to_string(),
+                        content,
+                        file_ids.unwrap_or_default(),
+                    )
+                    .await?;
+                self.emit(
+                    "thread.message.created",
+                    serde_json::to_value(handlers::thread_message(&self.thread_id, 0, &node))
+                        .unwrap(),
+                );
+                let thread = self.state.get_thread(&self.tenant, &self.thread_id).await?;
+                self.respond(thread, instructions, limit)
+            }
+            ClientFrame::Cancel {
+                job_id: Some(job_id),
+            } => {
+                let job = self.state.jobs().cancel(&self.tenant, &job_id)?;
+                // Watched jobs report their cancellation themselves
+                if !self.watched.contains(&job_id) {
+                    self.emit("job.cancelled", serde_json::to_value(job).unwrap());
+                }
+                Ok(())
+            }
+            ClientFrame::Cancel { job_id: None } => match &self.busy {
+                Some((cancel, task)) if !task.is_finished() => {
+                    cancel.cancel();
+                    Ok(())
+                }
+                _ => Err(ApiError::bad_request("No response in progress")),
+            },
+            ClientFrame::Regenerate {
+                message_id,
+                instructions,
+            } => {
+                self.ensure_idle()?;
+                let mut thread = self.state.get_thread(&self.tenant, &self.thread_id).await?;
+                let message_id = match message_id {
+                    Some(message_id) => message_id,
+                    None => thread
+                        .get_messages()
+                        .into_iter()
+                        .rev()
+                        .find(|node| node.role == "assistant")
+                        .map(|node| node.id.clone())
+                        .ok_or_else(|| {
+                            ApiError::bad_request("No assistant message to regenerate")
+                        })?,
+                };
+                thread.rewind_reply(&message_id)?;
+                let limit = self.acquire(0).await?;
+                self.respond(thread, instructions, limit)
+            }
+            ClientFrame::Model { model } => self.switch_model(&model).await,
+            ClientFrame::GenerateImage(mut request) => {
+                request.thread_id = Some(self.thread_id.clone());
+                request.background = true;
+                let limit = self.acquire(request.prompt.chars().count() as u64).await?;
+                let (account, client, extra_data) = handlers::media_session(
+                    &self.state,
+                    &self.tenant,
+                    Some(&self.thread_id),
+                    &request.model,
+                )
+                .await?;
+                let job = self.state.jobs().create(
+                    &self.tenant,
+                    MediaKind::Image,
+                    &request.prompt,
+                    &request.model,
+                );
+                let work = handlers::render_image(
+                    self.state.clone(),
+                    self.tenant.clone(),
+                    account,
+                    client,
+                    extra_data,
+                    request,
+                );
+                handlers::spawn_job(&self.state, &job, async move {
+                    let _limit = limit;
+                    work.await
+                });
+                self.watch_job(job);
+                Ok(())
+            }
+            ClientFrame::GenerateVideo(mut request) => {
+                request.thread_id = Some(self.thread_id.clone());
+                request.background = true;
+                let limit = self.acquire(request.prompt.chars().count() as u64).await?;
+                let (account, client, extra_data) = handlers::media_session(
+                    &self.state,
+                    &self.tenant,
+                    Some(&self.thread_id),
+                    &request.model,
+                )
+                .await?;
+                let job = self.state.jobs().create(
+                    &self.tenant,
+                    MediaKind::Video,
+                    &request.prompt,
+                    &request.model,
+                );
+                let jobs = self.state.jobs().clone();
+                let job_id = job.id.clone();
+                let work = handlers::render_video(
+                    self.state.clone(),
+                    self.tenant.clone(),
+                    account,
+                    client,
+                    extra_data,
+                    request,
+                    move |progress| jobs.progress(&job_id, progress),
+                );
+                handlers::spawn_job(&self.state, &job, async move {
+                    let _limit = limit;
+                    work.await
+                });
+                self.watch_job(job);
+                Ok(())
+            }
+        }
+    }
+
+    /// Stream an answer to the last user message of `thread`
+    fn respond(
+        &mut self,
+        thread: ThreadState,
+        instructions: Option<String>,
+        limit: Option<InFlightGuard>,
+    ) -> Result<(), ApiError> {
+        let mut payload = CreateResponseRequest {
+            thread_id: self.thread_id.clone(),
+            model: thread.model.clone(),
+            instructions,
+            stream: true,
+            file_ids: None,
+        };
+        let handlers::PendingResponse {
+            message,
+            guard,
+            response,
+        } = handlers::begin_response(&self.state, &thread, &mut payload)?;
+        let cancel = guard.token();
+        let (state, tx) = (self.state.clone(), self.tx.clone());
+        let task = tokio::spawn(
+            async move {
+                let _limit = limit;
+                handlers::stream_turn(
+                    state,
+                    payload,
+                    thread,
+                    message,
+                    guard.token(),
+                    response,
+                    move |name, data| {
+                        let _ = tx.send(frame(name, data));
+                    },
+                )
+                .await;
+                drop(guard);
+            }
+            .instrument(tracing::Span::current()),
+        );
+        self.busy = Some((cancel, task));
+        Ok(())
+    }
+
+    /// Move the thread to `model`. A different provider has no upstream
+    /// conversation yet, so the history is replayed to it first, the same way
+    /// an import does.
+    async fn switch_model(&mut self, model: &str) -> Result<(), ApiError> {
+        self.ensure_idle()?;
+        let model = self
+            .state
+            .config()
+            .resolve_model(model)
+            .ok_or_else(|| providers::unsupported_model(model))?;
+        let mut thread = self.state.get_thread(&self.tenant, &self.thread_id).await?;
+        let reseed = Provider::from_model(&thread.model) != Provider::from_model(&model)
+            && thread
+                .get_messages()
+                .iter()
+                .any(|node| node.role == "assistant");
+        thread.model = model.clone();
+        if !reseed {
+            let info = self.thread_info(&thread);
+            self.state
+                .update_thread(&self.tenant, &self.thread_id, |thread| {
+                    thread.model = model;
+                    Ok(())
+                })
+                .await?;
+            self.emit("thread.updated", info);
+            return Ok(());
+        }
+
+        // The whole history goes upstream again
+        let characters = thread
+            .get_messages()
+            .iter()
+            .map(|node| node.content.chars().count() as u64)
+            .sum();
+        let limit = self.acquire(characters).await?;
+        let (state, tx) = (self.state.clone(), self.tx.clone());
+        let thread_id = self.thread_id.clone();
+        let info = self.thread_info(&thread);
+        let cancel = CancelToken::new();
+        let token = cancel.clone();
+        let task = tokio::spawn(
+            async move {
+                let _limit = limit;
+                let result = async {
+                    export::reseed(&state, &thread_id, &mut thread, Some(&token)).await?;
+                    let tenant = thread.tenant.clone();
+                    state
+                        .update_thread(&tenant, &thread_id, |current| {
+                            current.model = model;
+                            current.merge(thread);
+                            Ok(())
+                        })
+                        .await
+                }
+                .await;
+                let _ = match result {
+                    Ok(()) => tx.send(frame("thread.updated", info)),
+                    Err(e) => {
+                        Logger::error(&format!("Switching model failed: {}", e.message));
+                        tx.send(frame("error", error_data(&e)))
+                    }
+                };
+            }
+            .instrument(tracing::Span::current()),
+        );
+        self.busy = Some((cancel, task));
+        Ok(())
+    }
+
+    /// Report the job's progress until it finishes or the client goes away
+    fn watch_job(&mut self, job: Job) {
+        self.watched.insert(job.id.clone());
+        self.emit("job.created", serde_json::to_value(&job).unwrap());
+        let (state, tx, tenant) = (self.state.clone(), self.tx.clone(), self.tenant.clone());
+        tokio::spawn(async move {
+            let mut last = (job.status, job.progress);
+            loop {
+                tokio::time::sleep(JOB_POLL_INTERVAL).await;
+                let Ok(job) = state.jobs().get(&tenant, &job.id) else {
+                    break;
+                };
+                if (job.status, job.progress) == last {
+                    continue;
+                }
+                last = (job.status, job.progress);
+                let name = if job.status.is_finished() {
+                    format!("job.{}", job.status.as_str())
+                } else {
+                    "job.progress".to_string()
+                };
+                let finished = job.status.is_finished();
+                if tx
+                    .send(frame(&name, serde_json::to_value(job).unwrap()))
+                    .is_err()
+                    || finished
+                {
+                    break;
+                }
+            }
+        });
+    }
+
+    fn ensure_idle(&self) -> Result<(), ApiError> {
+        match &self.busy {
+            Some((_, task)) if !task.is_finished() => Err(ApiError::bad_request(
+                "A response is already in progress on this thread",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Count a turn against the caller's API key limits
+    async fn acquire(&self, characters: u64) -> Result<Option<InFlightGuard>, ApiError> {
+        let Some(caller) = &self.caller else {
+            return Ok(None);
+        };
+        self.state
+            .acquire_key(caller, characters)
+            .await
+            .map(Some)
+            .map_err(|exceeded| ApiError::too_many_requests(exceeded.reason))
+    }
+
+    fn thread_info(&self, thread: &ThreadState) -> serde_json::Value {
+        serde_json::json!({
+            "thread_id": self.thread_id,
+            "model": thread.model,
+            "active_message_id": thread.active_message_id,
+        })
+    }
+
+    fn emit(&self, name: &str, data: serde_json::Value) {
+        let _ = self.tx.send(frame(name, data));
+    }
+
+    fn error(&self, error: ApiError) {
+        self.emit("error", error_data(&error));
+    }
+}
+
+/// Same shape as the `error` event of a streamed response
+fn error_data(error: &ApiError) -> serde_json::Value {
+    serde_json::json!({ "status": "error", "detail": error.message })
+}
@@ -1,14 +1,21 @@
 mod api;
 
+use api::config::ServerConfig;
+use api::telemetry::LogFormat;
 use reverse_api::Logger;
 use std::env;
+use std::path::PathBuf;
 
 fn print_usage() {
     println!("Usage: api_server [OPTIONS]");
     println!();
     println!("Options:");
+    println!("  --config <FILE>    TOML configuration file; flags and env vars override it");
     println!("  --host <HOST>      Server host (default: 0.0.0.0)");
     println!("  --port <PORT>      Server port (default: 6969)");
+    println!("  --data-dir <DIR>   Persist threads and files under DIR (default: in-memory)");
+    println!("  --api-keys <FILE>  JSON map of API key to tenant; enables authentication");
+    println!("  --log-format <FMT> Log output: text (default) or json");
     println!("  --help             Show this help message");
     println!();
     println!();
@@ -16,13 +23,19 @@ fn print_usage() {
     println!("  api_server");
     println!("  api_server --port 8080");
     println!("  api_server --host 127.0.0.1 --port 8080");
+    println!("  api_server --data-dir ./data");
+    println!("  api_server --config server.toml --port 8080");
 }
 
 #[tokio::main]
 async fn main() {
     let args: Vec<String> = env::args().collect();
-    let mut host = "0.0.0.0".to_string();
-    let mut port = 6969u16;
+    let mut config_path: Option<PathBuf> = None;
+    let mut host: Option<String> = None;
+    let mut port: Option<u16> = None;
+    let mut data_dir: Option<String> = None;
+    let mut api_keys: Option<String> = None;
+    let mut log_format: Option<LogFormat> = None;
 
     let mut i = 1;
     while i < args.len() {
@@ -31,9 +44,18 @@ async fn main() {
                 print_usage();
                 return;
             }
+            "--config" => {
+                if i + 1 < args.len() {
+                    config_path = Some(PathBuf::from(&args[i + 1]));
+                    i += 2;
+                } else {
+                    Logger::error("--config requires a value");
+                    std::process::exit(1);
+                }
+            }
             "--host" => {
                 if i + 1 < args.len() {
-                    host = args[i + 1].clone();
+                    host = Some(args[i + 1].clone());
                     i += 2;
                 } else {
                     Logger::error("--host requires a value");
@@ -43,7 +65,7 @@ async fn main() {
             "--port" => {
                 if i + 1 < args.len() {
                     match args[i + 1].parse::<u16>() {
-                        Ok(p) => port = p,
+                        Ok(p) => port = Some(p),
                         Err(_) => {
                             Logger::error(&format!("Invalid port number: {}", args[i + 1]));
                             std::process::exit(1);
@@ -55,6 +77,39 @@ async fn main() {
                     std::process::exit(1);
                 }
             }
+            "--data-dir" => {
+                if i + 1 < args.len() {
+                    data_dir = Some(args[i + 1].clone());
+                    i += 2;
+                } else {
+                    Logger::error("--data-dir requires a value");
+                    std::process::exit(1);
+                }
+            }
+            "--api-keys" => {
+                if i + 1 < args.len() {
+                    api_keys = Some(args[i + 1].clone());
+                    i += 2;
+                } else {
+                    Logger::error("--api-keys requires a value");
+                    std::process::exit(1);
+                }
+            }
+            "--log-format" => {
+                if i + 1 < args.len() {
+                    match args[i + 1].parse::<LogFormat>() {
+                        Ok(format) => log_format = Some(format),
+                        Err(e) => {
+                            Logger::error(&e);
+                            std::process::exit(1);
+                        }
+                    }
+                    i += 2;
+                } else {
+                    Logger::error("--log-format requires a value");
+                    std::process::exit(1);
+                }
+            }
             _ => {
                 Logger::error(&format!("Unknown option: {}", args[i]));
                 println!();
@@ -64,22 +119,56 @@ async fn main() {
         }
     }
 
-    if let Ok(env_host) = env::var("API_HOST") {
-        host = env_host;
+    let mut config = match &config_path {
+        Some(path) => ServerConfig::load(path).unwrap_or_else(|err| {
+            Logger::error(&err.to_string());
+            std::process::exit(1);
+        }),
+        None => ServerConfig::default(),
+    };
+    // Command-line flags win over the environment, which wins over the file
+    if let Err(err) = config.apply_env() {
+        Logger::error(&err.to_string());
+        std::process::exit(1);
+    }
+
+    if let Some(host) = host {
+        config.server.host = host;
+        config.server.listen.clear();
+    }
+    if let Some(port) = port {
+        config.server.port = port;
+        config.server.listen.clear();
+    }
+    if let Some(data_dir) = data_dir {
+        config.storage.data_dir = Some(data_dir.into());
+    }
+    if let Some(api_keys) = api_keys {
+        config.auth.api_keys_file = Some(api_keys.into());
+    }
+    if let Some(log_format) = log_format {
+        config.logging.format = log_format;
     }
 
-    if let Ok(env_port) = env::var("API_PORT") {
-        if let Ok(p) = env_port.parse::<u16>() {
-            port = p;
-        }
+    let source = config_path
+        .as_ref()
+        .map_or("command line".to_string(), |p| p.display().to_string());
+    if let Err(err) = config.validate(&source) {
+        Logger::error(&err.to_string());
+        std::process::exit(1);
     }
+    api::telemetry::init(config.logging.format, &config.logging.level);
 
     Logger::info("Starting Chat2API Server");
     Logger::info("=======================");
-    Logger::info(&format!("Host: {}", host));
-    Logger::info(&format!("Port: {}", port));
-    Logger::info("Supported Models: Qwen (qwen-*)");
-    if let Err(err) = api::server::run(&host, port).await {
+    if let Some(path) = &config_path {
+        Logger::info(&format!("Config: {}", path.display()));
+    }
+    Logger::info(&format!("Listen: {}", config.listen_addrs().join(", ")));
+    Logger::info(
+        "Supported Models: Qwen (qwen*), DeepSeek (deepseek*), Grok (grok-*), ChatGPT (chatgpt-*)",
+    );
+    if let Err(err) = api::server::run(config).await {
         Logger::error(&format!("API server failed: {}", err));
         std::process::exit(1);
     }
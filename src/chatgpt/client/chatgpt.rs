@@ -611,6 +611,7 @@ impl ChatGptClient {
     }
 
     /// Start a conversation
+    #[tracing::instrument(name = "chatgpt.chat", skip_all)]
     pub async fn start_conversation(&mut self, message: &str) -> Result<String> {
         self.get_tokens().await?;
         let conduit_token = self.get_conduit(false).await?;
@@ -839,6 +840,7 @@ impl ChatGptClient {
     }
 
     /// Start a conversation with an image
+    #[tracing::instrument(name = "chatgpt.chat_with_image", skip_all)]
     pub async fn start_with_image(&mut self, message: &str, image_data: &str) -> Result<String> {
         self.get_tokens().await?;
         let conduit_token = self.get_conduit(false).await?;
@@ -986,6 +988,16 @@ impl ChatGptClient {
         Ok(parsed_response)
     }
 
+    /// Message the next turn of the conversation replies to
+    pub fn parent_message_id(&self) -> Option<&str> {
+        self.data.parent_message_id.as_deref()
+    }
+
+    /// Continue the conversation from an earlier message instead of the latest
+    pub fn set_parent_message_id(&mut self, parent_message_id: Option<String>) {
+        self.data.parent_message_id = parent_message_id;
+    }
+
     /// Send a question and get response
     pub async fn ask_question(&mut self, message: &str) -> Result<String> {
         self.start_conversation(message).await
@@ -1001,6 +1013,7 @@ impl ChatGptClient {
     }
 
     /// Hold a conversation with ability to continue chatting
+    #[tracing::instrument(name = "chatgpt.hold_conversation", skip_all, fields(new))]
     pub async fn hold_conversation(&mut self, message: &str, new: bool) -> Result<String> {
         let mut index = 2000;
 
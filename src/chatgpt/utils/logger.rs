@@ -18,7 +18,16 @@ impl Logger {
         Local::now().format("%H:%M:%S").to_string()
     }
 
-    fn log(&self, _level: &str, prefix: &str, message: &str, color: Color) {
+    fn log(&self, level: &str, prefix: &str, message: &str, color: Color) {
+        if crate::console::structured() {
+            match level {
+                "ERROR" => tracing::error!("{}", message),
+                "WARNING" => tracing::warn!("{}", message),
+                _ => tracing::info!("{}", message),
+            }
+            return;
+        }
+
         let _guard = self.lock.lock().unwrap();
 
         let timestamp = Self::get_timestamp();
@@ -0,0 +1,45 @@
+//! Terminal output shared by the provider clients.
+//!
+//! Without a `tracing` subscriber the clients print to stdout, which is what
+//! the examples expect. Once a subscriber is installed (the API server does
+//! this at startup) messages become tracing events instead, so they carry the
+//! current request span and follow the configured log format, and streamed
+//! text is no longer echoed.
+
+use std::io::Write;
+
+/// Whether output goes through `tracing` rather than stdout
+pub fn structured() -> bool {
+    tracing::dispatcher::has_been_set()
+}
+
+/// Echo a piece of streamed text
+pub fn stream(text: &str) {
+    if !structured() {
+        print!("{}", text);
+        std::io::stdout().flush().ok();
+    }
+}
+
+/// Finish a block of streamed text
+pub fn end_stream() {
+    if !structured() {
+        println!();
+    }
+}
+
+pub fn info(message: &str) {
+    if structured() {
+        tracing::info!("{}", message);
+    } else {
+        println!("{}", message);
+    }
+}
+
+pub fn debug(message: &str) {
+    if structured() {
+        tracing::debug!("{}", message);
+    } else {
+        println!("{}", message);
+    }
+}
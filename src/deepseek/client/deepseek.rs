@@ -1,3 +1,4 @@
+use crate::cancel::{self, CancelToken};
 use crate::deepseek::error::{DeepSeekError, Result};
 use crate::deepseek::models::{DeepSeekChatRequest, DeepSeekResponse, ExtraData};
 use crate::deepseek::signature::{DeepSeekHash, DeepSeekSignature};
@@ -260,6 +261,37 @@ impl DeepSeekClient {
         message: &str,
         extra_data: Option<&ExtraData>,
     ) -> Result<DeepSeekResponse> {
+        self.start_convo_streaming(message, extra_data, |_| {})
+            .await
+    }
+
+    /// Same as `start_convo`, but reports each content delta through `on_delta`
+    pub async fn start_convo_streaming<F>(
+        &self,
+        message: &str,
+        extra_data: Option<&ExtraData>,
+        on_delta: F,
+    ) -> Result<DeepSeekResponse>
+    where
+        F: Fn(&str) + Send + Sync,
+    {
+        self.start_convo_cancellable(message, extra_data, None, on_delta)
+            .await
+    }
+
+    /// Same as `start_convo_streaming`, but stops reading once `cancel` is
+    /// cancelled and returns the partial answer marked `incomplete`
+    #[tracing::instrument(name = "deepseek.chat", skip_all, fields(continued = extra_data.is_some()))]
+    pub async fn start_convo_cancellable<F>(
+        &self,
+        message: &str,
+        extra_data: Option<&ExtraData>,
+        cancel: Option<&CancelToken>,
+        on_delta: F,
+    ) -> Result<DeepSeekResponse>
+    where
+        F: Fn(&str) + Send + Sync,
+    {
         let access_token = self.acquire_token(&self.api_key).await?;
 
         // Use existing session or create new one
@@ -322,8 +354,19 @@ impl DeepSeekClient {
                 let mut stream = response.bytes_stream();
                 let mut content = String::new();
                 let mut message_id = String::new();
-
-                while let Some(chunk) = stream.next().await {
+                let mut incomplete = false;
+
+                loop {
+                    let chunk = tokio::select! {
+                        chunk = stream.next() => chunk,
+                        _ = cancel::cancelled(cancel) => {
+                            incomplete = true;
+                            break;
+                        }
+                    };
+                    let Some(chunk) = chunk else {
+                        break;
+                    };
                     let chunk = chunk?;
                     let text = String::from_utf8_lossy(&chunk);
 
@@ -345,14 +388,14 @@ impl DeepSeekClient {
                                     if let Some(path) = json["p"].as_str() {
                                         if path.contains("response/content") {
                                             content.push_str(text_value);
-                                            print!("{}", text_value);
-                                            std::io::Write::flush(&mut std::io::stdout()).ok();
+                                            on_delta(text_value);
+                                            crate::console::stream(text_value);
                                         }
                                     } else if !text_value.is_empty() {
                                         // Sometimes "v" comes without "p"
                                         content.push_str(text_value);
-                                        print!("{}", text_value);
-                                        std::io::Write::flush(&mut std::io::stdout()).ok();
+                                        on_delta(text_value);
+                                        crate::console::stream(text_value);
                                     }
                                 }
                             }
@@ -360,7 +403,7 @@ impl DeepSeekClient {
                     }
                 }
 
-                println!(); // New line after streaming
+                crate::console::end_stream();
 
                 return Ok(DeepSeekResponse {
                     response: Some(content),
@@ -368,6 +411,7 @@ impl DeepSeekClient {
                         session_id,
                         message_id,
                     },
+                    incomplete,
                 });
             }
         }
@@ -19,9 +19,13 @@ pub struct DeepSeekChatRequest {
 
 // Response data structure for continuous conversation
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[non_exhaustive]
 pub struct DeepSeekResponse {
     pub response: Option<String>,
     pub extra_data: ExtraData,
+    /// Stopped early through a `CancelToken`
+    #[serde(default)]
+    pub incomplete: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
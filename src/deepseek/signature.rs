@@ -72,7 +72,7 @@ impl DeepSeekHash {
                     .get(ptr as usize..ptr as usize + len as usize)
                     .unwrap();
                 let s = std::str::from_utf8(data).unwrap();
-                println!("WASM Log: {}", s);
+                crate::console::debug(&format!("WASM Log: {}", s));
             },
         )?;
 
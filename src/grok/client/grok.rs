@@ -1,5 +1,7 @@
+use crate::cancel::{self, CancelToken};
 use crate::grok::*;
 use base64::{engine::general_purpose, Engine as _};
+use futures_util::StreamExt;
 use rquest::Client;
 use rquest_util::Emulation;
 use serde_json::{json, Value};
@@ -253,6 +255,19 @@ impl Grok {
         &mut self,
         message: &str,
         extra_data: Option<&ExtraData>,
+    ) -> Result<GrokResponse> {
+        self.start_convo_cancellable(message, extra_data, None)
+            .await
+    }
+
+    /// Same as `start_convo`, but stops reading once `cancel` is cancelled
+    /// and returns the tokens received so far marked `incomplete`
+    #[tracing::instrument(name = "grok.chat", skip_all, fields(model = %self.model, continued = extra_data.is_some()))]
+    pub async fn start_convo_cancellable(
+        &mut self,
+        message: &str,
+        extra_data: Option<&ExtraData>,
+        cancel: Option<&CancelToken>,
     ) -> Result<GrokResponse> {
         let xsid: String;
         let conversation_id: Option<String>;
@@ -400,13 +415,31 @@ impl Grok {
             .send()
             .await?;
 
-        let text = response.text().await?;
+        // One JSON object per line; a cancelled read leaves at most the last
+        // line cut off, which the parser below skips
+        let mut stream = response.bytes_stream();
+        let mut body = Vec::new();
+        let mut incomplete = false;
+        loop {
+            let chunk = tokio::select! {
+                chunk = stream.next() => chunk,
+                _ = cancel::cancelled(cancel) => {
+                    incomplete = true;
+                    break;
+                }
+            };
+            let Some(chunk) = chunk else {
+                break;
+            };
+            body.extend_from_slice(&chunk?);
+        }
+        let text = String::from_utf8_lossy(&body);
 
         if text.contains("rejected by anti-bot rules") {
             return Err(GrokError::AntiBotRejection);
         }
 
-        if !text.contains("modelResponse") {
+        if !incomplete && !text.contains("modelResponse") {
             return Err(GrokError::Other(format!("Unexpected response: {}", text)));
         }
 
@@ -481,9 +514,14 @@ impl Grok {
         let private_key_b64 =
             general_purpose::STANDARD.encode(self.keys.get("privateKey").unwrap());
 
+        if incomplete && full_response.is_none() {
+            full_response = Some(stream_response.concat());
+        }
+
         Ok(GrokResponse {
             response: full_response,
             stream_response,
+            incomplete,
             images: image_urls,
             extra_data: ExtraData {
                 anon_user: self.anon_user.clone().unwrap(),
@@ -16,7 +16,15 @@ impl Logger {
         Self::log("INFO", "[!]", message, Color::White);
     }
 
-    fn log(_level: &str, prefix: &str, message: &str, color: Color) {
+    fn log(level: &str, prefix: &str, message: &str, color: Color) {
+        if crate::console::structured() {
+            match level {
+                "ERROR" => tracing::error!("{}", message),
+                _ => tracing::info!("{}", message),
+            }
+            return;
+        }
+
         let timestamp = Local::now().format("%H:%M:%S");
         let formatted_timestamp = format!("[{}]", timestamp).bright_black();
         let colored_prefix = prefix.color(color);
@@ -2,10 +2,14 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[non_exhaustive]
 pub struct GrokResponse {
     pub response: Option<String>,
     pub stream_response: Vec<String>,
     pub images: Option<Vec<String>>,
+    /// Stopped early through a `CancelToken`
+    #[serde(default)]
+    pub incomplete: bool,
     pub extra_data: ExtraData,
 }
 
@@ -61,6 +65,13 @@ impl Models {
             .unwrap_or(&self.models["grok-3-auto"].0)
     }
 
+    /// All supported model ids, sorted
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.models.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
     pub fn get_mode(&self, model: &str) -> &String {
         self.models
             .get(model)
@@ -1,8 +1,11 @@
+mod cancel;
 pub mod chatgpt;
+mod console;
 pub mod deepseek;
 pub mod grok;
 pub mod qwen;
 
+pub use cancel::CancelToken;
 pub use chatgpt::{ChatGptClient, ChatGptError};
 pub use deepseek::client::deepseek::DeepSeekClient;
 pub use deepseek::error::{DeepSeekError, Result as DeepSeekResult};
@@ -9,10 +9,14 @@ impl MediaDownloader {
         Self { client }
     }
 
-    /// Download media (image or video) from URL to local file
-    pub async fn download_media(&self, url: &str, output_path: &str) -> Result<()> {
-        println!("⬇️  Downloading media to: {}", output_path);
+    /// Downloader with its own HTTP client, for media that is not tied to a
+    /// Qwen session
+    pub fn standalone() -> Result<Self> {
+        Ok(Self::new(rquest::Client::builder().build()?))
+    }
 
+    /// Fetch media (image or video) from URL into memory
+    pub async fn fetch_media(&self, url: &str) -> Result<Vec<u8>> {
         let response = self.client.get(url).send().await?;
 
         if !response.status().is_success() {
@@ -22,13 +26,20 @@ impl MediaDownloader {
             )));
         }
 
-        let bytes = response.bytes().await?;
+        Ok(response.bytes().await?.to_vec())
+    }
+
+    /// Download media (image or video) from URL to local file
+    pub async fn download_media(&self, url: &str, output_path: &str) -> Result<()> {
+        crate::console::info(&format!("⬇️  Downloading media to: {}", output_path));
+
+        let bytes = self.fetch_media(url).await?;
         std::fs::write(output_path, bytes)?;
 
-        println!(
+        crate::console::info(&format!(
             "✅ Downloaded successfully: {} bytes",
             std::fs::metadata(output_path)?.len()
-        );
+        ));
         Ok(())
     }
 }
@@ -2,7 +2,7 @@ use super::chat_manager::ChatManager;
 use super::constants::{build_json_headers, BASE_URL};
 use super::streaming::ConversationBuilder;
 use crate::qwen::error::Result;
-use crate::qwen::models::{ExtraData, QwenResponse, TaskResponse, TaskStatus};
+use crate::qwen::models::{ExtraData, QwenResponse, TaskProgress, TaskResponse, TaskStatus};
 
 pub struct MediaGenerator {
     client: rquest::Client,
@@ -73,6 +73,7 @@ impl MediaGenerator {
             parent_id,
             web_search_results: None,
             thinking_content: None,
+            incomplete: false,
         })
     }
 
@@ -111,6 +112,32 @@ impl MediaGenerator {
     ) -> Result<QwenResponse>
     where
         F: Fn(&str, u8) + Send + Sync,
+    {
+        self.generate_video_with_status(
+            prompt,
+            size,
+            model_id,
+            extra_data,
+            token,
+            chat_manager,
+            |progress| progress_callback(&progress.status, progress.percent),
+        )
+        .await
+    }
+
+    /// Generate a video, reporting the full task status on every poll
+    pub async fn generate_video_with_status<F>(
+        &self,
+        prompt: &str,
+        size: Option<&str>,
+        model_id: Option<&str>,
+        extra_data: Option<&ExtraData>,
+        token: &str,
+        chat_manager: &ChatManager,
+        status_callback: F,
+    ) -> Result<QwenResponse>
+    where
+        F: Fn(&TaskProgress) + Send + Sync,
     {
         let model = model_id.unwrap_or("qwen3-max");
         let chat_id = if let Some(data) = extra_data {
@@ -173,12 +200,20 @@ impl MediaGenerator {
                 crate::qwen::error::QwenError::ApiError("No task_id in response".to_string())
             })?;
 
-        println!("🎬 Video generation started, task_id: {}", task_id);
-        progress_callback("started", 0);
+        crate::console::info(&format!(
+            "🎬 Video generation started, task_id: {}",
+            task_id
+        ));
+        status_callback(&TaskProgress {
+            task_id: task_id.clone(),
+            status: "started".to_string(),
+            percent: 0,
+            remaining_time: String::new(),
+        });
 
         // Poll task status
         let video_url = self
-            .poll_task_status(&task_id, token, &progress_callback)
+            .poll_task_status(&task_id, token, &status_callback)
             .await?;
 
         Ok(QwenResponse {
@@ -188,6 +223,7 @@ impl MediaGenerator {
             parent_id: Some(task_response.data.parent_id),
             web_search_results: None,
             thinking_content: None,
+            incomplete: false,
         })
     }
 
@@ -196,11 +232,20 @@ impl MediaGenerator {
         &self,
         task_id: &str,
         token: &str,
-        progress_callback: &F,
+        status_callback: &F,
     ) -> Result<String>
     where
-        F: Fn(&str, u8) + Send + Sync,
+        F: Fn(&TaskProgress) + Send + Sync,
     {
+        let report = |task_status: &TaskStatus, status: &str, percent: u8| {
+            status_callback(&TaskProgress {
+                task_id: task_id.to_string(),
+                status: status.to_string(),
+                percent,
+                remaining_time: task_status.remaining_time.clone(),
+            })
+        };
+
         let url = format!("{}/api/v1/tasks/status/{}", BASE_URL, task_id);
         let headers = build_json_headers(Some(token));
 
@@ -225,12 +270,13 @@ impl MediaGenerator {
 
             match task_status.task_status.as_str() {
                 "success" => {
-                    progress_callback("success", 100);
-                    println!("\n✅ Video generation completed!");
+                    report(&task_status, "success", 100);
+                    crate::console::end_stream();
+                    crate::console::info("✅ Video generation completed!");
                     return Ok(task_status.content);
                 }
                 "failed" => {
-                    progress_callback("failed", 0);
+                    report(&task_status, "failed", 0);
                     return Err(crate::qwen::error::QwenError::ApiError(format!(
                         "Video generation failed: {}",
                         task_status.message
@@ -238,10 +284,9 @@ impl MediaGenerator {
                 }
                 "running" => {
                     let progress = ((attempt as f32 / max_attempts as f32) * 100.0) as u8;
-                    progress_callback("running", progress);
+                    report(&task_status, "running", progress);
                     if attempt % 5 == 0 {
-                        print!(".");
-                        std::io::Write::flush(&mut std::io::stdout()).ok();
+                        crate::console::stream(".");
                     }
                 }
                 _ => {}
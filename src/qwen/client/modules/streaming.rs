@@ -1,6 +1,7 @@
+use crate::cancel::{self, CancelToken};
 use crate::qwen::error::Result;
 use crate::qwen::models::{
-    ChatCompletionRequest, Extra, FeatureConfig, Meta, QwenFile, QwenMessage,
+    ChatCompletionRequest, Extra, FeatureConfig, Meta, QwenFile, QwenMessage, StreamEvent,
 };
 use futures_util::stream::StreamExt;
 use uuid::Uuid;
@@ -9,14 +10,38 @@ pub struct StreamingHandler;
 
 impl StreamingHandler {
     pub async fn handle_streaming_response(response: rquest::Response) -> Result<StreamingOutput> {
+        Self::handle_streaming_response_with_events(response, None, |_| {}).await
+    }
+
+    /// Same as `handle_streaming_response`, but reports every delta through
+    /// `on_event` and stops early once `cancel` is cancelled
+    pub async fn handle_streaming_response_with_events<F>(
+        response: rquest::Response,
+        cancel: Option<&CancelToken>,
+        on_event: F,
+    ) -> Result<StreamingOutput>
+    where
+        F: Fn(StreamEvent) + Send + Sync,
+    {
         let mut stream = response.bytes_stream();
         let mut content = String::new();
         let mut response_id: Option<String> = None;
         let mut thinking_content = String::new();
         let mut web_search_results: Option<Vec<crate::qwen::models::WebSearchInfo>> = None;
         let mut current_phase = String::new();
+        let mut incomplete = false;
 
-        while let Some(chunk) = stream.next().await {
+        loop {
+            let chunk = tokio::select! {
+                chunk = stream.next() => chunk,
+                _ = cancel::cancelled(cancel) => {
+                    incomplete = true;
+                    break;
+                }
+            };
+            let Some(chunk) = chunk else {
+                break;
+            };
             let chunk = chunk?;
             let text = String::from_utf8_lossy(&chunk);
 
@@ -58,8 +83,10 @@ impl StreamingHandler {
                                             delta.get("content").and_then(|v| v.as_str())
                                         {
                                             thinking_content.push_str(think_content);
-                                            print!("{}", think_content);
-                                            std::io::Write::flush(&mut std::io::stdout()).ok();
+                                            on_event(StreamEvent::Thinking(
+                                                think_content.to_string(),
+                                            ));
+                                            crate::console::stream(think_content);
                                         }
                                     }
 
@@ -72,6 +99,9 @@ impl StreamingHandler {
                                                 >(
                                                     search_info.clone()
                                                 ) {
+                                                    on_event(StreamEvent::WebSearch(
+                                                        results.clone(),
+                                                    ));
                                                     web_search_results = Some(results);
                                                 }
                                             }
@@ -97,8 +127,8 @@ impl StreamingHandler {
                                             delta.get("content").and_then(|v| v.as_str())
                                         {
                                             content.push_str(text);
-                                            print!("{}", text);
-                                            std::io::Write::flush(&mut std::io::stdout()).ok();
+                                            on_event(StreamEvent::Answer(text.to_string()));
+                                            crate::console::stream(text);
                                         }
                                     }
                                 }
@@ -109,7 +139,7 @@ impl StreamingHandler {
             }
         }
 
-        println!();
+        crate::console::end_stream();
 
         Ok(StreamingOutput {
             content,
@@ -120,15 +150,19 @@ impl StreamingHandler {
                 Some(thinking_content)
             },
             web_search_results,
+            incomplete,
         })
     }
 }
 
+#[non_exhaustive]
 pub struct StreamingOutput {
     pub content: String,
     pub response_id: String,
     pub thinking_content: Option<String>,
     pub web_search_results: Option<Vec<crate::qwen::models::WebSearchInfo>>,
+    /// The stream was cancelled before it ended
+    pub incomplete: bool,
 }
 
 pub struct ConversationBuilder;
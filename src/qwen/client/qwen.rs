@@ -1,5 +1,7 @@
 use crate::qwen::error::Result;
-use crate::qwen::models::{ExtraData, Model, QwenFile, QwenResponse};
+use crate::qwen::models::{
+    ConvoOptions, ExtraData, Model, QwenFile, QwenResponse, StreamEvent, TaskProgress,
+};
 
 use super::modules::{
     auth::AuthManager,
@@ -138,6 +140,7 @@ impl QwenClient {
         .await
     }
 
+    #[tracing::instrument(name = "qwen.upload_file", skip_all)]
     pub async fn upload_file(&self, file_path: &str) -> Result<QwenFile> {
         let user_id = self.chat_manager.get_user_id().await?;
         self.file_uploader.upload_file(file_path, user_id).await
@@ -158,6 +161,7 @@ impl QwenClient {
             .await
     }
 
+    #[tracing::instrument(name = "qwen.chat", skip_all, fields(model = model_id, files = files.len()))]
     pub async fn start_convo_with_files(
         &self,
         message: &str,
@@ -165,6 +169,50 @@ impl QwenClient {
         model_id: Option<&str>,
         extra_data: Option<&ExtraData>,
     ) -> Result<QwenResponse> {
+        self.run_convo(
+            message,
+            files,
+            model_id,
+            extra_data,
+            ConvoOptions::default(),
+            |_| {},
+        )
+        .await
+    }
+
+    /// Run a conversation turn, reporting answer, thinking and search deltas
+    /// through `on_event` as they arrive
+    #[tracing::instrument(name = "qwen.chat_stream", skip_all, fields(model = model_id, files = files.len()))]
+    pub async fn start_convo_streaming<F>(
+        &self,
+        message: &str,
+        files: Vec<QwenFile>,
+        model_id: Option<&str>,
+        extra_data: Option<&ExtraData>,
+        options: ConvoOptions,
+        on_event: F,
+    ) -> Result<QwenResponse>
+    where
+        F: Fn(StreamEvent) + Send + Sync,
+    {
+        self.run_convo(message, files, model_id, extra_data, options, on_event)
+            .await
+    }
+
+    /// Send one turn and read its answer, continuing the chat of
+    /// `extra_data` or opening a new one
+    async fn run_convo<F>(
+        &self,
+        message: &str,
+        files: Vec<QwenFile>,
+        model_id: Option<&str>,
+        extra_data: Option<&ExtraData>,
+        options: ConvoOptions,
+        on_event: F,
+    ) -> Result<QwenResponse>
+    where
+        F: Fn(StreamEvent) + Send + Sync,
+    {
         let token = self.auth.get_token().await?;
 
         let model = if let Some(id) = model_id {
@@ -196,9 +244,9 @@ impl QwenClient {
             files,
             chat_id.clone(),
             parent_id.clone(),
-            false,
-            false,
-            None,
+            options.enable_search,
+            options.enable_thinking,
+            options.thinking_budget,
         );
 
         let response = self
@@ -218,7 +266,12 @@ impl QwenClient {
             )));
         }
 
-        let output = StreamingHandler::handle_streaming_response(response).await?;
+        let output = StreamingHandler::handle_streaming_response_with_events(
+            response,
+            options.cancel.as_ref(),
+            on_event,
+        )
+        .await?;
 
         Ok(QwenResponse {
             content: output.content,
@@ -227,6 +280,7 @@ impl QwenClient {
             parent_id,
             web_search_results: output.web_search_results,
             thinking_content: output.thinking_content,
+            incomplete: output.incomplete,
         })
     }
 
@@ -333,6 +387,7 @@ impl QwenClient {
             parent_id: None,
             web_search_results: output.web_search_results,
             thinking_content: output.thinking_content,
+            incomplete: output.incomplete,
         })
     }
 
@@ -341,6 +396,7 @@ impl QwenClient {
     // ============================================================
 
     /// Generate an image from text prompt
+    #[tracing::instrument(name = "qwen.generate_image", skip_all, fields(model = model_id))]
     pub async fn generate_image(
         &self,
         prompt: &str,
@@ -362,6 +418,7 @@ impl QwenClient {
     }
 
     /// Generate a video from text prompt
+    #[tracing::instrument(name = "qwen.generate_video", skip_all, fields(model = model_id))]
     pub async fn generate_video(
         &self,
         prompt: &str,
@@ -383,6 +440,7 @@ impl QwenClient {
     }
 
     /// Generate a video with progress callback
+    #[tracing::instrument(name = "qwen.generate_video", skip_all, fields(model = model_id))]
     pub async fn generate_video_with_progress<F>(
         &self,
         prompt: &str,
@@ -408,6 +466,34 @@ impl QwenClient {
             .await
     }
 
+    /// Generate a video, reporting the task id, progress and Qwen's remaining
+    /// time estimate on every poll
+    #[tracing::instrument(name = "qwen.generate_video", skip_all, fields(model = model_id))]
+    pub async fn generate_video_with_status<F>(
+        &self,
+        prompt: &str,
+        size: Option<&str>,
+        model_id: Option<&str>,
+        extra_data: Option<&ExtraData>,
+        status_callback: F,
+    ) -> Result<QwenResponse>
+    where
+        F: Fn(&TaskProgress) + Send + Sync,
+    {
+        let token = self.auth.get_token().await?;
+        self.media_generator
+            .generate_video_with_status(
+                prompt,
+                size,
+                model_id,
+                extra_data,
+                &token,
+                &self.chat_manager,
+                status_callback,
+            )
+            .await
+    }
+
     /// Download media (image or video) to local file
     pub async fn download_media(&self, url: &str, output_path: &str) -> Result<()> {
         self.media_downloader.download_media(url, output_path).await
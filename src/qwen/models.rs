@@ -1,3 +1,4 @@
+use crate::CancelToken;
 use serde::{Deserialize, Serialize};
 
 /// Chat type for different interaction modes
@@ -332,6 +333,7 @@ pub struct WebSearchInfo {
 
 // Response data structure for continuous conversation
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[non_exhaustive]
 pub struct QwenResponse {
     pub content: String,           // The actual response content
     pub response_id: String,       // For tracking conversation
@@ -341,6 +343,27 @@ pub struct QwenResponse {
     pub web_search_results: Option<Vec<WebSearchInfo>>, // Web search results if search was enabled
     #[serde(default)]
     pub thinking_content: Option<String>, // Thinking process if thinking was enabled
+    /// Stopped early through a `CancelToken`
+    #[serde(default)]
+    pub incomplete: bool,
+}
+
+/// Incremental output reported while a chat completion is streaming
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum StreamEvent {
+    Answer(String),
+    Thinking(String),
+    WebSearch(Vec<WebSearchInfo>),
+}
+
+/// Optional features for a conversation turn
+#[derive(Debug, Clone, Default)]
+pub struct ConvoOptions {
+    pub enable_search: bool,
+    pub enable_thinking: bool,
+    pub thinking_budget: Option<u32>,
+    /// Stops reading the answer early, keeping what arrived so far
+    pub cancel: Option<CancelToken>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -389,3 +412,13 @@ pub struct TaskStatus {
     pub remaining_time: String,
     pub content: String, // Video URL when completed
 }
+
+/// Progress of a generation task, passed to status callbacks while polling
+#[derive(Debug, Clone)]
+pub struct TaskProgress {
+    pub task_id: String,
+    pub status: String, // "started", "running", "success", "failed"
+    pub percent: u8,
+    /// Estimate reported by Qwen, empty until the task is running
+    pub remaining_time: String,
+}